@@ -1,3 +1,5 @@
 fn main() {
+    // Only the desktop app has a Tauri context to generate.
+    #[cfg(feature = "gui")]
     tauri_build::build()
 }
@@ -0,0 +1,177 @@
+//! The desktop app: the Tauri builder, window commands and global
+//! shortcuts around `password::commands`.
+
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter, Manager, Runtime, Window};
+use tauri_plugin_global_shortcut::{GlobalShortcutExt, ShortcutState};
+
+use crate::password;
+use crate::password::registry::VaultState;
+
+#[tauri::command]
+async fn force_focus<R: Runtime>(window: Window<R>) -> Result<(), String> {
+    window.set_focus().map_err(|e| e.to_string())?;
+
+    #[cfg(target_os = "macos")]
+    {
+        use cocoa::appkit::{NSApp, NSApplication};
+        unsafe {
+            let app = NSApp();
+            app.activateIgnoringOtherApps_(cocoa::base::YES);
+        }
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn toggle_window_visibility<R: Runtime>(window: Window<R>) -> Result<(), String> {
+    if window.is_visible().map_err(|e| e.to_string())? {
+        window.hide().map_err(|e| e.to_string())?;
+        password::autolock::lock_and_notify(window.app_handle(), None);
+    } else {
+        window.show().map_err(|e| e.to_string())?;
+        window.set_focus().map_err(|e| e.to_string())?;
+
+        #[cfg(target_os = "macos")]
+        {
+            use cocoa::appkit::{NSApp, NSApplication};
+            unsafe {
+                let app = NSApp();
+                app.activateIgnoringOtherApps_(cocoa::base::YES);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn enable_clickthrough<R: Runtime>(window: Window<R>) -> Result<(), String> {
+    window
+        .set_ignore_cursor_events(true)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn disable_clickthrough<R: Runtime>(window: Window<R>) -> Result<(), String> {
+    window
+        .set_ignore_cursor_events(false)
+        .map_err(|e| e.to_string())?;
+    window.set_focus().map_err(|e| e.to_string())?;
+
+    #[cfg(target_os = "macos")]
+    {
+        use cocoa::appkit::{NSApp, NSApplication};
+        unsafe {
+            let app = NSApp();
+            app.activateIgnoringOtherApps_(cocoa::base::YES);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    tauri::Builder::default()
+        .plugin(tauri_plugin_global_shortcut::Builder::new().build())
+        .plugin(tauri_plugin_clipboard_manager::init())
+        .plugin(tauri_plugin_opener::init())
+        .invoke_handler(tauri::generate_handler![
+            force_focus,
+            enable_clickthrough,
+            disable_clickthrough,
+            toggle_window_visibility,
+            password::commands::list_vaults,
+            password::commands::create_vault,
+            password::commands::add_vault,
+            password::commands::open_vault,
+            password::commands::close_vault,
+            password::commands::rename_vault,
+            password::commands::remove_vault,
+            password::commands::unlock_vault,
+            password::commands::lock_vault,
+            password::commands::vault_exists,
+            password::commands::is_vault_unlocked,
+            password::commands::quick_unlock,
+            password::commands::enable_quick_unlock,
+            password::commands::disable_quick_unlock,
+            password::commands::is_quick_unlock_enabled,
+            password::commands::get_all_passwords,
+            password::commands::search_entries,
+            password::commands::find_entries_for_url,
+            password::commands::get_password,
+            password::commands::reveal_password,
+            password::commands::get_totp_code,
+            password::commands::get_password_history,
+            password::commands::restore_password,
+            password::commands::reveal_field,
+            password::commands::get_entry_templates,
+            password::commands::create_password,
+            password::commands::update_password,
+            password::commands::delete_password,
+            password::commands::get_trash,
+            password::commands::restore_from_trash,
+            password::commands::purge_from_trash,
+            password::commands::empty_trash,
+            password::commands::import_entries,
+            password::commands::export_vault,
+            password::commands::get_vault_settings,
+            password::commands::update_vault_settings,
+            password::commands::list_backups,
+            password::commands::restore_backup,
+            password::commands::change_master_password,
+            password::commands::calibrate_kdf,
+            password::commands::update_kdf_params,
+            password::commands::generate_password,
+            password::commands::generate_password_for_site,
+            password::commands::generate_passphrase,
+            password::commands::check_password_strength,
+        ])
+        .setup(|app| {
+            setup_global_shortcuts(app.handle())?;
+            let data_dir = password::storage::get_app_data_dir(app.handle())?;
+            let (state, error) = VaultState::load_or_reset(data_dir);
+            if let Some(e) = error {
+                eprintln!("Could not read the vault list, starting with an empty one: {e}");
+            }
+            app.manage(Mutex::new(state));
+            password::autolock::spawn_auto_lock(app.handle().clone());
+            Ok(())
+        })
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
+
+fn setup_global_shortcuts(app: &AppHandle) -> Result<(), Box<dyn std::error::Error>> {
+    app.global_shortcut()
+        .on_shortcut("CommandOrControl+Shift+Space", {
+            let app_handle = app.clone();
+            move |_app, _shortcut, event| {
+                if event.state == ShortcutState::Pressed {
+                    if let Some(window) = app_handle.get_webview_window("main") {
+                        let _ = window.emit("toggle-command-palette", ());
+
+                        if let Ok(is_visible) = window.is_visible() {
+                            if !is_visible {
+                                let _ = window.show();
+                                let _ = window.set_focus();
+
+                                #[cfg(target_os = "macos")]
+                                {
+                                    use cocoa::appkit::{NSApp, NSApplication};
+                                    unsafe {
+                                        let app = NSApp();
+                                        app.activateIgnoringOtherApps_(cocoa::base::YES);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        })?;
+
+    Ok(())
+}
@@ -0,0 +1,1101 @@
+//! Command-line access to a zims vault. Works on the vault the desktop app
+//! opened last unless `--vault` names another registered vault or a path.
+//! Every invocation unlocks the vault, does one thing and locks it again.
+//!
+//! Needs none of the desktop app's dependencies; build it on its own with
+//! `cargo build --no-default-features --bin zims-cli`.
+
+use clap::{Args, Parser, Subcommand};
+use serde::Serialize;
+use std::io::{self, BufRead, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use zims_lib::password::backend::{StorageKind, VaultStorage};
+use zims_lib::password::crypto::{self, DEFAULT_UNLOCK_TARGET};
+use zims_lib::password::export::{self, ExportFormat};
+use zims_lib::password::generator;
+use zims_lib::password::import::{self, ImportFormat, ImportOptions};
+use zims_lib::password::registry::{VaultRegistry, VaultState};
+use zims_lib::password::search::SearchFilters;
+use zims_lib::password::storage;
+use zims_lib::password::types::{
+    CustomField, EntryInput, EntryKind, FieldKind, PassphraseOptions, PasswordEntry,
+    PasswordEntryDetails, PasswordEntrySummary, PasswordOptions, UrlMatch, WordCase,
+};
+use zims_lib::password::{Vault, VaultError};
+
+const EXIT_CODES: &str = "\
+Exit status:
+  0   success
+  1   I/O, key derivation, encryption or keyring failure
+  2   invalid command line
+  3   wrong master password
+  4   vault locked
+  5   vault or entry not found
+  6   vault already exists
+  7   vault open in another process
+  8   vault changed on disk
+  9   vault corrupted or unsupported format
+  10  invalid input";
+
+#[derive(Parser)]
+#[command(
+    name = "zims-cli",
+    version,
+    about = "Command-line access to a zims vault"
+)]
+#[command(after_help = EXIT_CODES)]
+struct Cli {
+    /// Registered vault name, or vault path [default: the vault the desktop
+    /// app opened last]
+    #[arg(long, global = true, env = "ZIMS_VAULT")]
+    vault: Option<String>,
+
+    /// Print results and errors as JSON
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a new vault, registered under --name or at the --vault path
+    /// (a KeePass database if the path ends in .kdbx)
+    Init {
+        /// Register the vault with the desktop app under this name
+        #[arg(long)]
+        name: Option<String>,
+        /// Store the vault as a directory of versioned files
+        #[arg(long)]
+        directory: bool,
+    },
+    /// List the vaults registered with the desktop app
+    Vaults,
+    /// Check that the master password opens the vault
+    Unlock,
+    /// List entries
+    Ls {
+        /// Only entries with this tag
+        #[arg(long)]
+        tag: Option<String>,
+    },
+    /// Find entries by fuzzy matching on title, URL host, username, tags
+    /// and notes, best match first
+    Search {
+        query: String,
+        /// Only entries with this tag; repeat to require several
+        #[arg(long)]
+        tag: Vec<String>,
+        /// Only entries of this kind
+        #[arg(long)]
+        kind: Option<EntryKind>,
+        /// Only favorites
+        #[arg(long)]
+        favorite: bool,
+    },
+    /// List the entries to offer for a page, best match first
+    Match { url: String },
+    /// Show an entry, by id or title
+    Get {
+        entry: String,
+        /// Print only the password
+        #[arg(long)]
+        password: bool,
+        /// Print only this field's value
+        #[arg(long, value_name = "NAME", conflicts_with = "password")]
+        field: Option<String>,
+    },
+    /// Show an entry's current two-factor code, by id or title
+    Totp { entry: String },
+    /// List an entry's previous passwords, newest first
+    History {
+        entry: String,
+        /// Print the passwords as well as when they were replaced
+        #[arg(long)]
+        show: bool,
+        /// Make the previous password with this number current again
+        #[arg(long, value_name = "N", conflicts_with = "show")]
+        restore: Option<usize>,
+    },
+    /// Add an entry; the password is prompted for unless --generate is given
+    /// or the kind has none
+    Add {
+        title: String,
+        #[command(flatten)]
+        fields: EntryFields,
+    },
+    /// Edit an entry, by id or title
+    Edit {
+        entry: String,
+        #[arg(long)]
+        title: Option<String>,
+        #[command(flatten)]
+        fields: EntryFields,
+        /// Prompt for a new password
+        #[arg(long, conflicts_with = "generate")]
+        password: bool,
+        /// Remove a field; repeat for several
+        #[arg(long = "remove-field", value_name = "NAME")]
+        remove_fields: Vec<String>,
+    },
+    /// Move an entry to the trash, by id or title
+    Rm { entry: String },
+    /// List trashed entries, or restore or purge one, by id or title
+    Trash {
+        /// Move this entry back out of the trash
+        #[arg(long, value_name = "ENTRY")]
+        restore: Option<String>,
+        /// Delete this entry for good
+        #[arg(long, value_name = "ENTRY", conflicts_with = "restore")]
+        purge: Option<String>,
+        /// Delete every trashed entry for good
+        #[arg(long, conflicts_with_all = ["restore", "purge"])]
+        empty: bool,
+    },
+    /// Import another password manager's export
+    Import {
+        /// bitwarden_json, one_password_pux, one_password_csv, keepass_xml,
+        /// chrome_csv or firefox_csv
+        format: ImportFormat,
+        file: PathBuf,
+        /// Only show what would be imported
+        #[arg(long)]
+        dry_run: bool,
+        /// Leave out entries that duplicate existing ones
+        #[arg(long)]
+        skip_duplicates: bool,
+    },
+    /// Export entries, encrypted under a new passphrase or in plaintext
+    Export {
+        /// encrypted, bitwarden_json or bitwarden_csv
+        format: ExportFormat,
+        /// File to write
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Only entries with this tag; repeat for several
+        #[arg(short, long = "tag")]
+        tags: Vec<String>,
+    },
+    /// Generate a password
+    Generate(GenerateArgs),
+    /// Generate a passphrase from the EFF large wordlist; its entropy goes
+    /// to stderr
+    Passphrase(PassphraseArgs),
+    /// Rate a password read from the terminal or stdin
+    Strength,
+}
+
+#[derive(Args)]
+struct EntryFields {
+    #[arg(short, long)]
+    username: Option<String>,
+    #[arg(long)]
+    url: Option<String>,
+    /// Another URL the entry is for; repeat for several. On edit, replaces
+    /// all of them
+    #[arg(long = "other-url", value_name = "URL")]
+    urls: Vec<String>,
+    /// How the entry's URLs match a page: base_domain, host, starts_with,
+    /// regex or never
+    #[arg(long = "match", value_name = "MODE")]
+    url_match: Option<UrlMatch>,
+    #[arg(long)]
+    notes: Option<String>,
+    /// Tag the entry; repeat for several. On edit, replaces all tags
+    #[arg(short, long = "tag")]
+    tags: Vec<String>,
+    /// Generate a new password with the default options
+    #[arg(long)]
+    generate: bool,
+    /// Prompt for a TOTP secret or otpauth:// URI
+    #[arg(long)]
+    totp: bool,
+    /// login, secure_note, credit_card, identity, ssh_key, api_token or wifi
+    #[arg(long)]
+    kind: Option<EntryKind>,
+    /// Set a field; repeat for several
+    #[arg(long = "field", value_name = "NAME=VALUE")]
+    fields: Vec<String>,
+    /// Prompt for a hidden field's value; repeat for several
+    #[arg(long = "secret-field", value_name = "NAME")]
+    secret_fields: Vec<String>,
+}
+
+#[derive(Args)]
+struct GenerateArgs {
+    #[arg(short, long, default_value_t = PasswordOptions::default().length)]
+    length: u32,
+    #[arg(long)]
+    no_uppercase: bool,
+    #[arg(long)]
+    no_lowercase: bool,
+    #[arg(long)]
+    no_numbers: bool,
+    #[arg(long)]
+    no_symbols: bool,
+    /// Allow look-alike characters such as 0/O and 1/l
+    #[arg(long)]
+    allow_ambiguous: bool,
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    min_uppercase: u32,
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    min_lowercase: u32,
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    min_numbers: u32,
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    min_symbols: u32,
+    /// Also use these characters
+    #[arg(long, value_name = "CHARS", default_value = "")]
+    include: String,
+    /// Never use these characters
+    #[arg(long, value_name = "CHARS", default_value = "")]
+    exclude: String,
+    /// The most times a character may repeat in a row
+    #[arg(long, value_name = "N")]
+    max_consecutive: Option<u32>,
+    /// Follow Apple password rules, e.g. "minlength: 8; required: digit"
+    #[arg(long, conflicts_with = "site")]
+    rules: Option<String>,
+    /// Follow the password rules the vault settings or the bundled presets
+    /// have for this site
+    #[arg(long, value_name = "URL")]
+    site: Option<String>,
+}
+
+#[derive(Args)]
+struct PassphraseArgs {
+    #[arg(short, long, default_value_t = PassphraseOptions::default().words)]
+    words: u32,
+    #[arg(short, long, default_value_t = PassphraseOptions::default().separator)]
+    separator: String,
+    /// Capitalize each word
+    #[arg(long, conflicts_with = "uppercase")]
+    capitalize: bool,
+    /// Write the words in uppercase
+    #[arg(long)]
+    uppercase: bool,
+    /// Append a digit to one of the words
+    #[arg(long)]
+    digit: bool,
+}
+
+impl PassphraseArgs {
+    fn options(&self) -> PassphraseOptions {
+        PassphraseOptions {
+            words: self.words,
+            separator: self.separator.clone(),
+            case: if self.capitalize {
+                WordCase::Capitalized
+            } else if self.uppercase {
+                WordCase::Uppercase
+            } else {
+                WordCase::Lowercase
+            },
+            include_digit: self.digit,
+        }
+    }
+}
+
+impl GenerateArgs {
+    fn options(&self) -> PasswordOptions {
+        PasswordOptions {
+            length: self.length,
+            include_uppercase: !self.no_uppercase,
+            include_lowercase: !self.no_lowercase,
+            include_numbers: !self.no_numbers,
+            include_symbols: !self.no_symbols,
+            exclude_ambiguous: !self.allow_ambiguous,
+            min_uppercase: self.min_uppercase,
+            min_lowercase: self.min_lowercase,
+            min_numbers: self.min_numbers,
+            min_symbols: self.min_symbols,
+            include_chars: self.include.clone(),
+            exclude_chars: self.exclude.clone(),
+            max_consecutive: self.max_consecutive,
+            rules: self.rules.clone(),
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(&cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            if cli.json {
+                eprintln!("{}", serde_json::to_string(&e).unwrap_or_default());
+            } else {
+                eprintln!("zims-cli: {}", e);
+            }
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), VaultError> {
+    match &cli.command {
+        Command::Init { name, directory } => {
+            let kind = if *directory {
+                StorageKind::Directory
+            } else {
+                StorageKind::File
+            };
+            let path = cli.vault.as_ref().map(PathBuf::from);
+            if name.is_none() && path.is_none() {
+                return Err(VaultError::InvalidInput(
+                    "Give the new vault a --name, or a path with --vault".to_string(),
+                ));
+            }
+            if path.as_ref().is_some_and(|path| kind.open(path).exists()) {
+                return Err(VaultError::AlreadyExists);
+            }
+
+            let password = read_new_master_password()?;
+            let kdf_params = crypto::calibrate_kdf(DEFAULT_UNLOCK_TARGET)?;
+
+            let location = match (name, path) {
+                (Some(name), path) => {
+                    let mut state = VaultState::load(storage::default_data_dir()?)?;
+                    let id = state.create(name, &password, kdf_params, kind, path)?;
+                    state.registry().get(id)?.path.display().to_string()
+                }
+                (None, Some(path)) => {
+                    let mut vault = Vault::with_storage(kind.open(path));
+                    vault.create(&password, kdf_params)?;
+                    vault.storage().location()
+                }
+                (None, None) => unreachable!("checked above"),
+            };
+
+            print(cli, &serde_json::json!({ "path": location }), || {
+                println!("Created vault at {}", location)
+            })
+        }
+        Command::Vaults => {
+            let registry = VaultRegistry::load(&storage::default_data_dir()?)?;
+
+            print(cli, &registry.vaults, || {
+                for record in &registry.vaults {
+                    println!("{:<20}  {}", record.name, record.path.display());
+                }
+            })
+        }
+        Command::Unlock => {
+            let mut vault = open_vault(cli)?;
+            let count = vault.entries()?.len();
+
+            print(cli, &serde_json::json!({ "entries": count }), || {
+                println!("Vault unlocked: {} entries", count)
+            })
+        }
+        Command::Ls { tag } => {
+            let mut vault = open_vault(cli)?;
+            let summaries: Vec<PasswordEntrySummary> = vault
+                .entries()?
+                .iter()
+                .filter(|e| tag.as_ref().is_none_or(|tag| e.tags.contains(tag)))
+                .map(|e| e.clone().into())
+                .collect();
+
+            print(cli, &summaries, || {
+                for summary in &summaries {
+                    println!(
+                        "{}  {:<30}  {}",
+                        summary.id, summary.title, summary.username
+                    );
+                }
+            })
+        }
+        Command::Search {
+            query,
+            tag,
+            kind,
+            favorite,
+        } => {
+            let mut vault = open_vault(cli)?;
+            let filters = SearchFilters {
+                favorite: favorite.then_some(true),
+                tags: tag.clone(),
+                kind: *kind,
+                ..SearchFilters::default()
+            };
+            let results = vault.search_entries(query, &filters)?;
+
+            print(cli, &results, || {
+                for result in &results {
+                    println!(
+                        "{}  {:<30}  {}",
+                        result.entry.id, result.entry.title, result.entry.username
+                    );
+                }
+            })
+        }
+        Command::Match { url } => {
+            let mut vault = open_vault(cli)?;
+            let summaries = vault.find_entries_for_url(url)?;
+
+            print(cli, &summaries, || {
+                for summary in &summaries {
+                    println!(
+                        "{}  {:<30}  {}",
+                        summary.id, summary.title, summary.username
+                    );
+                }
+            })
+        }
+        Command::Get {
+            entry,
+            password,
+            field,
+        } => {
+            let mut vault = open_vault(cli)?;
+            let id = resolve_entry(&mut vault, entry)?;
+            let entry = vault.entry(id)?;
+
+            if let Some(name) = field {
+                let value = entry
+                    .field(name)
+                    .ok_or_else(|| VaultError::NotFound(format!("Field '{}'", name)))?
+                    .value
+                    .as_str();
+                return print(cli, &serde_json::json!({ "value": value }), || {
+                    println!("{}", value)
+                });
+            }
+
+            if *password {
+                let secret = entry.password.as_str();
+                return print(cli, &serde_json::json!({ "password": secret }), || {
+                    println!("{}", secret)
+                });
+            }
+
+            let details: PasswordEntryDetails = entry.clone().into();
+            print(cli, &details, || print_details(&details))
+        }
+        Command::Totp { entry } => {
+            let mut vault = open_vault(cli)?;
+            let id = resolve_entry(&mut vault, entry)?;
+            let code = vault.totp_code(id)?;
+
+            print(cli, &code, || {
+                println!("{} ({}s left)", code.code, code.seconds_remaining)
+            })
+        }
+        Command::History {
+            entry,
+            show,
+            restore,
+        } => {
+            let mut vault = open_vault(cli)?;
+            let id = resolve_entry(&mut vault, entry)?;
+
+            if let Some(index) = restore {
+                vault.restore_password(id, *index)?;
+                return print(cli, &serde_json::json!({ "id": id }), || {});
+            }
+
+            let history = vault.password_history(id)?.to_vec();
+            let listed: Vec<serde_json::Value> = history
+                .iter()
+                .enumerate()
+                .map(|(index, item)| {
+                    let mut value = serde_json::json!({
+                        "index": index,
+                        "changed_at": item.changed_at,
+                    });
+                    if *show {
+                        value["password"] = item.password.as_str().into();
+                    }
+                    value
+                })
+                .collect();
+            print(cli, &listed, || {
+                for (index, item) in history.iter().enumerate() {
+                    let changed_at = item.changed_at.to_rfc3339();
+                    if *show {
+                        println!("{}  {}  {}", index, changed_at, item.password.as_str());
+                    } else {
+                        println!("{}  {}", index, changed_at);
+                    }
+                }
+            })
+        }
+        Command::Add { title, fields } => {
+            let mut vault = open_vault(cli)?;
+
+            let kind = fields.kind.unwrap_or_default();
+            let password = if fields.generate {
+                Zeroizing::new(generator::generate_password(&PasswordOptions::default())?)
+            } else if kind.has_password() {
+                read_secret("Entry password: ")?
+            } else {
+                Zeroizing::new(String::new())
+            };
+            let totp = if fields.totp {
+                Some(
+                    read_secret("TOTP secret or otpauth:// URI: ")?
+                        .to_string()
+                        .into(),
+                )
+            } else {
+                None
+            };
+            let custom = set_fields(kind, fields, Vec::new())?;
+
+            let id = vault.add_entry(EntryInput {
+                kind,
+                title: title.clone(),
+                username: fields.username.clone().unwrap_or_default(),
+                password: password.to_string().into(),
+                url: fields.url.clone(),
+                urls: fields.urls.clone(),
+                url_match: fields.url_match.unwrap_or_default(),
+                notes: fields.notes.clone(),
+                totp,
+                fields: custom,
+                tags: fields.tags.clone(),
+                favorite: false,
+            })?;
+
+            print(cli, &serde_json::json!({ "id": id }), || println!("{}", id))
+        }
+        Command::Edit {
+            entry,
+            title,
+            fields,
+            password,
+            remove_fields,
+        } => {
+            let mut vault = open_vault(cli)?;
+            let id = resolve_entry(&mut vault, entry)?;
+
+            let current = vault.entry(id)?.clone();
+            let new_password = if fields.generate {
+                generator::generate_password(&PasswordOptions::default())?.into()
+            } else if *password {
+                read_secret("Entry password: ")?.to_string().into()
+            } else {
+                current.password.clone()
+            };
+            let totp = if fields.totp {
+                Some(
+                    read_secret("TOTP secret or otpauth:// URI: ")?
+                        .to_string()
+                        .into(),
+                )
+            } else {
+                current.totp.clone()
+            };
+            let kind = fields.kind.unwrap_or(current.kind);
+            let mut custom = current.fields.clone();
+            custom.retain(|field| !remove_fields.contains(&field.name));
+            let custom = set_fields(kind, fields, custom)?;
+
+            vault.update_entry(
+                id,
+                EntryInput {
+                    kind,
+                    title: title.clone().unwrap_or_else(|| current.title.clone()),
+                    username: fields
+                        .username
+                        .clone()
+                        .unwrap_or_else(|| current.username.clone()),
+                    password: new_password,
+                    url: fields.url.clone().or_else(|| current.url.clone()),
+                    urls: if fields.urls.is_empty() {
+                        current.urls.clone()
+                    } else {
+                        fields.urls.clone()
+                    },
+                    url_match: fields.url_match.unwrap_or(current.url_match),
+                    notes: fields.notes.clone().or_else(|| current.notes.clone()),
+                    totp,
+                    fields: custom,
+                    tags: if fields.tags.is_empty() {
+                        current.tags.clone()
+                    } else {
+                        fields.tags.clone()
+                    },
+                    favorite: current.favorite,
+                },
+            )?;
+
+            print(cli, &serde_json::json!({ "id": id }), || {})
+        }
+        Command::Rm { entry } => {
+            let mut vault = open_vault(cli)?;
+            let id = resolve_entry(&mut vault, entry)?;
+            vault.delete_entry(id)?;
+
+            print(cli, &serde_json::json!({ "id": id }), || {})
+        }
+        Command::Trash {
+            restore,
+            purge,
+            empty,
+        } => {
+            let mut vault = open_vault(cli)?;
+
+            if let Some(query) = restore {
+                let id = find_entry(vault.trash()?, query, "Trashed entry")?;
+                vault.restore_entry(id)?;
+                return print(cli, &serde_json::json!({ "id": id }), || {});
+            }
+            if let Some(query) = purge {
+                let id = find_entry(vault.trash()?, query, "Trashed entry")?;
+                vault.purge_entry(id)?;
+                return print(cli, &serde_json::json!({ "id": id }), || {});
+            }
+            if *empty {
+                let purged = vault.empty_trash()?;
+                return print(cli, &serde_json::json!({ "purged": purged }), || {
+                    println!("Purged {} entries", purged)
+                });
+            }
+
+            let summaries: Vec<PasswordEntrySummary> =
+                vault.trash()?.iter().map(|e| e.clone().into()).collect();
+            print(cli, &summaries, || {
+                for summary in &summaries {
+                    let deleted_at = summary.deleted_at.map(|at| at.to_rfc3339());
+                    println!(
+                        "{}  {:<30}  {}",
+                        summary.id,
+                        summary.title,
+                        deleted_at.unwrap_or_default()
+                    );
+                }
+            })
+        }
+        Command::Import {
+            format,
+            file,
+            dry_run,
+            skip_duplicates,
+        } => {
+            let data = Zeroizing::new(std::fs::read(file)?);
+            let entries = import::parse(*format, &data)?;
+
+            let mut vault = open_vault(cli)?;
+            let options = ImportOptions {
+                dry_run: *dry_run,
+                skip_duplicates: *skip_duplicates,
+            };
+            let report = vault.import_entries(entries, &options)?;
+
+            print(cli, &report, || {
+                for entry in &report.entries {
+                    let mark = match (entry.skipped, entry.duplicate_of) {
+                        (true, _) => "skip",
+                        (false, Some(_)) => "dup ",
+                        (false, None) => "    ",
+                    };
+                    println!(
+                        "{}  {:<30}  {}",
+                        mark, entry.summary.title, entry.summary.username
+                    );
+                }
+                let verb = if report.dry_run {
+                    "Would import"
+                } else {
+                    "Imported"
+                };
+                println!(
+                    "{} {} entries, skipped {}",
+                    verb, report.imported, report.skipped
+                );
+            })
+        }
+        Command::Export {
+            format,
+            output,
+            tags,
+        } => {
+            let mut vault = open_vault(cli)?;
+
+            let data = match format {
+                ExportFormat::Encrypted => {
+                    let passphrase = read_new_secret("Export passphrase")?;
+                    Zeroizing::new(vault.export_encrypted(&passphrase, tags)?)
+                }
+                _ => {
+                    let password = read_secret("Master password again to export in plaintext: ")?;
+                    vault.export_plaintext(&password, *format, tags)?
+                }
+            };
+            export::write_file(output, &data)?;
+
+            let path = output.display().to_string();
+            print(cli, &serde_json::json!({ "path": path }), || {
+                println!("Exported to {}", path)
+            })
+        }
+        Command::Generate(args) => {
+            let mut options = args.options();
+            if let Some(site) = &args.site {
+                options.rules = open_vault(cli)?.password_rules_for(site)?;
+            }
+            let password = Zeroizing::new(generator::generate_password(&options)?);
+
+            print(
+                cli,
+                &serde_json::json!({ "password": password.as_str() }),
+                || println!("{}", password.as_str()),
+            )
+        }
+        Command::Passphrase(args) => {
+            let passphrase = generator::generate_passphrase(&args.options())?;
+            let phrase = Zeroizing::new(passphrase.passphrase);
+
+            print(
+                cli,
+                &serde_json::json!({
+                    "passphrase": phrase.as_str(),
+                    "entropy": passphrase.entropy,
+                }),
+                || {
+                    println!("{}", phrase.as_str());
+                    eprintln!("{:.0} bits of entropy", passphrase.entropy);
+                },
+            )
+        }
+        Command::Strength => {
+            let password = read_secret("Password: ")?;
+            let strength = generator::check_strength(&password);
+
+            print(cli, &strength, || {
+                println!(
+                    "Score: {}/4 ({:.0} bits of entropy)",
+                    strength.score, strength.entropy
+                );
+                for hint in &strength.feedback {
+                    println!("- {}", hint);
+                }
+            })
+        }
+    }
+}
+
+/// Exit status per error kind, as listed in `EXIT_CODES`. 2 is left to clap
+/// for usage errors.
+fn exit_code(error: &VaultError) -> u8 {
+    match error {
+        VaultError::Io(_)
+        | VaultError::KdfFailure(_)
+        | VaultError::Crypto(_)
+        | VaultError::Keyring(_) => 1,
+        VaultError::WrongPassword => 3,
+        VaultError::Locked => 4,
+        VaultError::NotFound(_) => 5,
+        VaultError::AlreadyExists => 6,
+        VaultError::InUse(_) => 7,
+        VaultError::Conflict => 8,
+        VaultError::Corrupted(_) | VaultError::VersionUnsupported(_) => 9,
+        VaultError::InvalidInput(_) | VaultError::Unsupported(_) => 10,
+    }
+}
+
+/// Resolves `--vault` as the name of a registered vault, then as a path.
+/// Without it, the vault the desktop app opened last is used.
+fn vault_storage(cli: &Cli) -> Result<Box<dyn VaultStorage>, VaultError> {
+    let registry = VaultRegistry::load(&storage::default_data_dir()?)?;
+    let record = match &cli.vault {
+        Some(vault) => registry
+            .vaults
+            .iter()
+            .find(|v| v.name.eq_ignore_ascii_case(vault)),
+        None => registry.most_recent(),
+    };
+
+    match (record, &cli.vault) {
+        (Some(record), _) => Ok(record.storage_kind.open(record.path.clone())),
+        (None, Some(path)) => Ok(StorageKind::detect(Path::new(path)).open(path)),
+        (None, None) => Err(VaultError::NotFound("Vault".to_string())),
+    }
+}
+
+fn open_vault(cli: &Cli) -> Result<Vault, VaultError> {
+    let mut vault = Vault::with_storage(vault_storage(cli)?);
+    if !vault.exists() {
+        return Err(VaultError::NotFound("Vault file".to_string()));
+    }
+
+    let password = read_secret("Master password: ")?;
+    vault.unlock(&password)?;
+
+    Ok(vault)
+}
+
+/// Finds an entry by id, or else by its title, ignoring case.
+fn resolve_entry(vault: &mut Vault, query: &str) -> Result<Uuid, VaultError> {
+    find_entry(vault.entries()?, query, "Password entry")
+}
+
+/// The entry among `entries` with `query` as its id or title; `what` names
+/// the entries for the error when there is none.
+fn find_entry(entries: &[PasswordEntry], query: &str, what: &str) -> Result<Uuid, VaultError> {
+    if let Ok(id) = Uuid::parse_str(query) {
+        return Ok(id);
+    }
+
+    let query = query.to_lowercase();
+    let matches: Vec<Uuid> = entries
+        .iter()
+        .filter(|e| e.title.to_lowercase() == query)
+        .map(|e| e.id)
+        .collect();
+
+    match matches.as_slice() {
+        [id] => Ok(*id),
+        [] => Err(VaultError::NotFound(what.to_string())),
+        _ => Err(VaultError::InvalidInput(format!(
+            "{} entries have that title; use the id",
+            matches.len()
+        ))),
+    }
+}
+
+/// Sets the fields given with `--field` and `--secret-field` in `custom`,
+/// prompting for secret values. A field named in the kind's template gets
+/// the template's field kind.
+fn set_fields(
+    kind: EntryKind,
+    fields: &EntryFields,
+    mut custom: Vec<CustomField>,
+) -> Result<Vec<CustomField>, VaultError> {
+    let field_kind = |name: &str, default| {
+        kind.template()
+            .iter()
+            .find(|t| t.name == name)
+            .map_or(default, |t| t.kind)
+    };
+    let mut set = |name: &str, kind, value: String| {
+        let value = value.into();
+        match custom.iter_mut().find(|f| f.name == name) {
+            Some(field) => {
+                field.kind = kind;
+                field.value = value;
+            }
+            None => custom.push(CustomField {
+                name: name.to_string(),
+                kind,
+                value,
+            }),
+        }
+    };
+
+    for field in &fields.fields {
+        let (name, value) = field.split_once('=').ok_or_else(|| {
+            VaultError::InvalidInput(format!("Expected NAME=VALUE, got '{}'", field))
+        })?;
+        let name = name.trim();
+        set(name, field_kind(name, FieldKind::Text), value.to_string());
+    }
+    for name in &fields.secret_fields {
+        let value = read_secret(&format!("{}: ", name))?;
+        set(name, field_kind(name, FieldKind::Hidden), value.to_string());
+    }
+
+    Ok(custom)
+}
+
+/// Reads a secret from the terminal without echo, or, when stdin is not a
+/// terminal, as the next line of stdin so scripts can pipe secrets in.
+fn read_secret(prompt: &str) -> Result<Zeroizing<String>, VaultError> {
+    if io::stdin().is_terminal() {
+        return Ok(Zeroizing::new(rpassword::prompt_password(prompt)?));
+    }
+
+    let mut line = Zeroizing::new(String::new());
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+        return Err(VaultError::InvalidInput(
+            "Expected a password on stdin".to_string(),
+        ));
+    }
+    let len = line.trim_end_matches(['\r', '\n']).len();
+    line.truncate(len);
+
+    Ok(line)
+}
+
+fn read_new_master_password() -> Result<Zeroizing<String>, VaultError> {
+    read_new_secret("Master password")
+}
+
+/// Prompts for a new, non-empty secret, asking twice on a terminal.
+fn read_new_secret(what: &str) -> Result<Zeroizing<String>, VaultError> {
+    let password = read_secret(&format!("New {}: ", what.to_lowercase()))?;
+    if password.is_empty() {
+        return Err(VaultError::InvalidInput(format!(
+            "{} cannot be empty",
+            what
+        )));
+    }
+
+    if io::stdin().is_terminal() {
+        let confirmation = read_secret(&format!("Repeat {}: ", what.to_lowercase()))?;
+        if confirmation != password {
+            return Err(VaultError::InvalidInput(
+                "Passwords do not match".to_string(),
+            ));
+        }
+    }
+
+    Ok(password)
+}
+
+fn print<T: Serialize>(cli: &Cli, value: &T, human: impl FnOnce()) -> Result<(), VaultError> {
+    if cli.json {
+        println!("{}", serde_json::to_string_pretty(value)?);
+    } else {
+        human();
+    }
+    Ok(())
+}
+
+fn print_details(details: &PasswordEntryDetails) {
+    println!("Title:    {}", details.title);
+    if details.kind != EntryKind::Login {
+        println!("Kind:     {}", details.kind);
+    }
+    println!("Username: {}", details.username);
+    if let Some(url) = &details.url {
+        println!("URL:      {}", url);
+    }
+    for url in &details.urls {
+        println!("          {}", url);
+    }
+    if details.url_match != UrlMatch::default() {
+        println!("Match:    {}", details.url_match);
+    }
+    if !details.tags.is_empty() {
+        println!("Tags:     {}", details.tags.join(", "));
+    }
+    println!("Id:       {}", details.id);
+    println!("Created:  {}", details.created_at.to_rfc3339());
+    println!("Updated:  {}", details.updated_at.to_rfc3339());
+    if details.has_totp {
+        println!("TOTP:     yes");
+    }
+    if !details.fields.is_empty() {
+        println!();
+        for field in &details.fields {
+            println!(
+                "{}: {}",
+                field.name,
+                field.value.as_deref().unwrap_or("(hidden)")
+            );
+        }
+    }
+    if let Some(notes) = &details.notes {
+        println!();
+        println!("{}", notes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+    use zims_lib::password::crypto::KdfParams;
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+
+        let cli =
+            Cli::try_parse_from(["zims-cli", "--json", "get", "GitHub", "--password"]).unwrap();
+        assert!(cli.json);
+        assert!(matches!(cli.command, Command::Get { password: true, .. }));
+
+        assert!(
+            Cli::try_parse_from(["zims-cli", "edit", "x", "--password", "--generate"]).is_err()
+        );
+
+        let cli = Cli::try_parse_from([
+            "zims-cli",
+            "add",
+            "Visa",
+            "--kind",
+            "credit_card",
+            "--field",
+            "Cardholder=Alice",
+        ])
+        .unwrap();
+        let Command::Add { fields, .. } = cli.command else {
+            panic!("expected add");
+        };
+        assert_eq!(fields.kind, Some(EntryKind::CreditCard));
+        assert_eq!(fields.fields, vec!["Cardholder=Alice"]);
+        assert!(Cli::try_parse_from(["zims-cli", "add", "x", "--kind", "bank"]).is_err());
+
+        let cli = Cli::try_parse_from(["zims-cli", "import", "keepass_xml", "db.xml", "--dry-run"])
+            .unwrap();
+        assert!(matches!(
+            cli.command,
+            Command::Import {
+                format: ImportFormat::KeepassXml,
+                dry_run: true,
+                ..
+            }
+        ));
+        assert!(Cli::try_parse_from(["zims-cli", "import", "lastpass", "x.csv"]).is_err());
+    }
+
+    #[test]
+    fn test_exit_codes_are_distinct() {
+        let errors = [
+            VaultError::WrongPassword,
+            VaultError::Locked,
+            VaultError::NotFound("Vault file".to_string()),
+            VaultError::AlreadyExists,
+            VaultError::InUse(1),
+            VaultError::Conflict,
+            VaultError::Corrupted(String::new()),
+            VaultError::InvalidInput(String::new()),
+            VaultError::Io(io::Error::other("")),
+        ];
+        let mut codes: Vec<u8> = errors.iter().map(exit_code).collect();
+        assert!(!codes.contains(&0) && !codes.contains(&2));
+
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+    }
+
+    #[test]
+    fn test_resolve_entry_by_title() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut vault = Vault::new(dir.path().join("vault.encrypted"));
+        vault.create("hunter2", KdfParams::POLICY_FLOOR).unwrap();
+
+        let entry = |title: &str| EntryInput {
+            title: title.to_string(),
+            ..EntryInput::default()
+        };
+        let github = vault.add_entry(entry("GitHub")).unwrap();
+        vault.add_entry(entry("Mail")).unwrap();
+        vault.add_entry(entry("Mail")).unwrap();
+
+        assert_eq!(resolve_entry(&mut vault, "github").unwrap(), github);
+        assert_eq!(
+            resolve_entry(&mut vault, &github.to_string()).unwrap(),
+            github
+        );
+        assert!(matches!(
+            resolve_entry(&mut vault, "Mail"),
+            Err(VaultError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            resolve_entry(&mut vault, "Bank"),
+            Err(VaultError::NotFound(_))
+        ));
+    }
+}
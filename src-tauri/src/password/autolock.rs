@@ -0,0 +1,72 @@
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager, Runtime};
+use uuid::Uuid;
+
+use super::commands::VaultStateMutex;
+use super::error::VaultError;
+
+/// Emitted to the webview whenever a vault is locked, for any reason, with
+/// the vault's id as payload.
+pub const VAULT_LOCKED_EVENT: &str = "vault-locked";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Starts the background thread that locks each vault once it has been idle
+/// for longer than its `auto_lock_secs` setting.
+pub fn spawn_auto_lock<R: Runtime>(app: AppHandle<R>) {
+    thread::spawn(move || loop {
+        thread::sleep(POLL_INTERVAL);
+
+        let locked = {
+            let state = app.state::<VaultStateMutex>();
+            let mut state = state.lock().unwrap();
+            state.lock_expired(Instant::now())
+        };
+
+        notify(&app, &locked);
+    });
+}
+
+/// Locks vault `id`, or every vault if `None`, and tells the webview about
+/// each one that was unlocked.
+pub fn lock_and_notify<R: Runtime>(app: &AppHandle<R>, id: Option<Uuid>) {
+    let locked = {
+        let state = app.state::<VaultStateMutex>();
+        let mut state = state.lock().unwrap();
+        match id {
+            Some(id) => match state.vault(id) {
+                Ok(vault) if vault.is_unlocked() => {
+                    vault.lock();
+                    vec![id]
+                }
+                _ => Vec::new(),
+            },
+            None => state.lock_all(),
+        }
+    };
+
+    notify(app, &locked);
+}
+
+/// Closes vault `id`, notifying the webview if that locked it.
+pub fn close_and_notify<R: Runtime>(app: &AppHandle<R>, id: Uuid) -> Result<(), VaultError> {
+    let was_unlocked = {
+        let state = app.state::<VaultStateMutex>();
+        let mut state = state.lock().unwrap();
+        let was_unlocked = state.vault(id)?.is_unlocked();
+        state.close(id)?;
+        was_unlocked
+    };
+
+    if was_unlocked {
+        notify(app, &[id]);
+    }
+    Ok(())
+}
+
+fn notify<R: Runtime>(app: &AppHandle<R>, locked: &[Uuid]) {
+    for id in locked {
+        let _ = app.emit(VAULT_LOCKED_EVENT, id.to_string());
+    }
+}
@@ -0,0 +1,634 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use super::error::VaultError;
+use super::storage::{self, BackupInfo, VaultHeader, VaultLock, MAX_BACKUP_GENERATIONS};
+
+/// Where a vault's encrypted bytes live. Implementations only move opaque
+/// bytes around; everything involving keys stays in `Vault`.
+pub trait VaultStorage: Send {
+    /// Human-readable location, for messages.
+    fn location(&self) -> String;
+
+    fn exists(&self) -> bool;
+
+    /// The current vault bytes; `NotFound` if there is no vault yet.
+    fn load(&self) -> Result<Vec<u8>, VaultError>;
+
+    /// Stores `data` as the current vault and moves the previous one into a
+    /// backup ring of `backup_generations`. With `expected_generation` set,
+    /// fails with `Conflict` if the stored vault has moved past it.
+    fn save(
+        &self,
+        data: &[u8],
+        expected_generation: Option<u64>,
+        backup_generations: u32,
+    ) -> Result<(), VaultError>;
+
+    /// Removes the vault, its backups and its quick-unlock credential.
+    fn delete(&self) -> Result<(), VaultError>;
+
+    /// Backups, most recent first. Generation 1 is the version before the
+    /// current one.
+    fn list_backups(&self) -> Result<Vec<BackupInfo>, VaultError>;
+
+    fn load_backup(&self, generation: u32) -> Result<Vec<u8>, VaultError>;
+
+    /// Takes the exclusive lock held while the vault is unlocked, failing
+    /// with `InUse` if someone else holds it. Released when dropped.
+    fn lock(&self) -> Result<StorageLock, VaultError>;
+
+    /// The vault key wrapped for quick unlock, if enabled.
+    fn load_quick_unlock(&self) -> Result<Vec<u8>, VaultError>;
+
+    fn save_quick_unlock(&self, wrapped_key: &[u8]) -> Result<(), VaultError>;
+
+    /// Removes the wrapped key; a missing one is not an error.
+    fn delete_quick_unlock(&self) -> Result<(), VaultError>;
+
+    fn has_quick_unlock(&self) -> bool;
+}
+
+/// Guard returned by `VaultStorage::lock`.
+pub type StorageLock = Box<dyn Send>;
+
+/// The on-disk layouts a vault can be created with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageKind {
+    /// A single `vault.encrypted` file with numbered backups beside it.
+    #[default]
+    File,
+    /// A directory of immutable, numbered versions.
+    Directory,
+}
+
+impl StorageKind {
+    /// Existing directory vaults are recognised by the path being a directory.
+    pub fn detect(path: &Path) -> Self {
+        if path.is_dir() {
+            StorageKind::Directory
+        } else {
+            StorageKind::File
+        }
+    }
+
+    pub fn open(self, path: impl Into<PathBuf>) -> Box<dyn VaultStorage> {
+        match self {
+            StorageKind::File => Box::new(FileStorage::new(path)),
+            StorageKind::Directory => Box::new(DirectoryStorage::new(path)),
+        }
+    }
+}
+
+/// Fails with `Conflict` if `current` carries a generation other than
+/// `expected`. No current vault is not a conflict.
+fn check_generation(current: Option<&[u8]>, expected: u64) -> Result<(), VaultError> {
+    if let Some(data) = current {
+        let (header, _) = VaultHeader::from_bytes(data)?;
+        if header.generation != expected {
+            return Err(VaultError::Conflict);
+        }
+    }
+    Ok(())
+}
+
+/// The original layout: one file, crash-safe replacement, and a ring of
+/// `<file>.<n>` backups beside it.
+pub struct FileStorage {
+    path: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl VaultStorage for FileStorage {
+    fn location(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn exists(&self) -> bool {
+        self.path.is_file()
+    }
+
+    fn load(&self) -> Result<Vec<u8>, VaultError> {
+        storage::load_vault(&self.path)
+    }
+
+    fn save(
+        &self,
+        data: &[u8],
+        expected_generation: Option<u64>,
+        backup_generations: u32,
+    ) -> Result<(), VaultError> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        storage::save_vault(&self.path, data, expected_generation, backup_generations)
+    }
+
+    fn delete(&self) -> Result<(), VaultError> {
+        storage::delete_vault(&self.path)
+    }
+
+    fn list_backups(&self) -> Result<Vec<BackupInfo>, VaultError> {
+        storage::list_backup_files(&self.path)
+    }
+
+    fn load_backup(&self, generation: u32) -> Result<Vec<u8>, VaultError> {
+        storage::load_backup(&self.path, generation)
+    }
+
+    fn lock(&self) -> Result<StorageLock, VaultError> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(Box::new(VaultLock::acquire(&self.path)?))
+    }
+
+    fn load_quick_unlock(&self) -> Result<Vec<u8>, VaultError> {
+        storage::read_quick_unlock(&self.path)
+    }
+
+    fn save_quick_unlock(&self, wrapped_key: &[u8]) -> Result<(), VaultError> {
+        storage::write_quick_unlock(&self.path, wrapped_key)
+    }
+
+    fn delete_quick_unlock(&self) -> Result<(), VaultError> {
+        storage::delete_quick_unlock(&self.path)
+    }
+
+    fn has_quick_unlock(&self) -> bool {
+        storage::quick_unlock_path(&self.path).exists()
+    }
+}
+
+const VERSION_EXTENSION: &str = "zims";
+
+/// Keeps every version of the vault as its own file, `<sequence>.zims`, in
+/// one directory, and never rewrites an existing file. This suits folders
+/// synchronised by tools that cope better with new files than with files
+/// changing in place. Old versions beyond the backup ring are deleted.
+pub struct DirectoryStorage {
+    dir: PathBuf,
+}
+
+impl DirectoryStorage {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Sequence numbers and paths of all versions, newest first.
+    fn versions(&self) -> Result<Vec<(u64, PathBuf)>, VaultError> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut versions = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(VERSION_EXTENSION) {
+                continue;
+            }
+            let sequence = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse::<u64>().ok());
+            if let Some(sequence) = sequence {
+                versions.push((sequence, path));
+            }
+        }
+
+        versions.sort_by_key(|v| std::cmp::Reverse(v.0));
+        Ok(versions)
+    }
+
+    fn version_path(&self, sequence: u64) -> PathBuf {
+        self.dir
+            .join(format!("{:010}.{}", sequence, VERSION_EXTENSION))
+    }
+
+    /// Name handed to `VaultLock`, which appends `.lock`.
+    fn lock_base(&self) -> PathBuf {
+        self.dir.join("vault")
+    }
+
+    fn quick_unlock_path(&self) -> PathBuf {
+        self.dir.join("quick-unlock")
+    }
+}
+
+impl VaultStorage for DirectoryStorage {
+    fn location(&self) -> String {
+        self.dir.display().to_string()
+    }
+
+    fn exists(&self) -> bool {
+        self.versions().is_ok_and(|v| !v.is_empty())
+    }
+
+    fn load(&self) -> Result<Vec<u8>, VaultError> {
+        match self.versions()?.first() {
+            Some((_, path)) => Ok(fs::read(path)?),
+            None => Err(VaultError::NotFound("Vault file".to_string())),
+        }
+    }
+
+    fn save(
+        &self,
+        data: &[u8],
+        expected_generation: Option<u64>,
+        backup_generations: u32,
+    ) -> Result<(), VaultError> {
+        fs::create_dir_all(&self.dir)?;
+        let versions = self.versions()?;
+
+        if let Some(expected) = expected_generation {
+            let current = match versions.first() {
+                Some((_, path)) => Some(fs::read(path)?),
+                None => None,
+            };
+            check_generation(current.as_deref(), expected)?;
+        }
+
+        let sequence = versions.first().map_or(1, |(sequence, _)| sequence + 1);
+        let path = self.version_path(sequence);
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut file = fs::File::create(&tmp_path)?;
+            file.write_all(data)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_path, &path)?;
+        storage::sync_parent_dir(&path)?;
+
+        // `versions` excludes the file just written, so everything past the
+        // ring size in it is surplus.
+        let keep = backup_generations.min(MAX_BACKUP_GENERATIONS) as usize;
+        for (_, old) in versions.iter().skip(keep) {
+            fs::remove_file(old)?;
+        }
+
+        Ok(())
+    }
+
+    fn delete(&self) -> Result<(), VaultError> {
+        for (_, path) in self.versions()? {
+            fs::remove_file(path)?;
+        }
+        self.delete_quick_unlock()?;
+        storage::delete_lock_file(&self.lock_base())?;
+
+        // Only removes the directory if nothing else was put in it.
+        let _ = fs::remove_dir(&self.dir);
+        Ok(())
+    }
+
+    fn list_backups(&self) -> Result<Vec<BackupInfo>, VaultError> {
+        let mut backups = Vec::new();
+
+        for (index, (_, path)) in self.versions()?.iter().skip(1).enumerate() {
+            let metadata = fs::metadata(path)?;
+            backups.push(BackupInfo {
+                generation: index as u32 + 1,
+                size: metadata.len(),
+                modified: metadata.modified()?.into(),
+            });
+        }
+
+        Ok(backups)
+    }
+
+    fn load_backup(&self, generation: u32) -> Result<Vec<u8>, VaultError> {
+        let versions = self.versions()?;
+
+        match versions.get(generation as usize).filter(|_| generation > 0) {
+            Some((_, path)) => Ok(fs::read(path)?),
+            None => Err(VaultError::NotFound(format!("Backup {}", generation))),
+        }
+    }
+
+    fn lock(&self) -> Result<StorageLock, VaultError> {
+        fs::create_dir_all(&self.dir)?;
+        Ok(Box::new(VaultLock::acquire(&self.lock_base())?))
+    }
+
+    fn load_quick_unlock(&self) -> Result<Vec<u8>, VaultError> {
+        match fs::read(self.quick_unlock_path()) {
+            Ok(data) => Ok(data),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(VaultError::NotFound("Quick unlock credential".to_string()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save_quick_unlock(&self, wrapped_key: &[u8]) -> Result<(), VaultError> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.quick_unlock_path();
+        let tmp_path = path.with_extension("tmp");
+        {
+            let mut file = fs::File::create(&tmp_path)?;
+            file.write_all(wrapped_key)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_path, &path)?;
+        Ok(())
+    }
+
+    fn delete_quick_unlock(&self) -> Result<(), VaultError> {
+        match fs::remove_file(self.quick_unlock_path()) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn has_quick_unlock(&self) -> bool {
+        self.quick_unlock_path().exists()
+    }
+}
+
+/// Keeps the vault in memory, for tests. Clones share the same contents and
+/// lock, so two `Vault`s over clones behave like two processes on one file.
+#[derive(Clone, Default)]
+pub struct MemoryStorage {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    current: Option<Vec<u8>>,
+    /// Most recent first.
+    backups: VecDeque<BackupEntry>,
+    quick_unlock: Option<Vec<u8>>,
+    locked: bool,
+}
+
+struct BackupEntry {
+    data: Vec<u8>,
+    info: BackupInfo,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+struct MemoryLock {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+impl Drop for MemoryLock {
+    fn drop(&mut self) {
+        self.state.lock().unwrap().locked = false;
+    }
+}
+
+impl VaultStorage for MemoryStorage {
+    fn location(&self) -> String {
+        "memory".to_string()
+    }
+
+    fn exists(&self) -> bool {
+        self.state.lock().unwrap().current.is_some()
+    }
+
+    fn load(&self) -> Result<Vec<u8>, VaultError> {
+        self.state
+            .lock()
+            .unwrap()
+            .current
+            .clone()
+            .ok_or_else(|| VaultError::NotFound("Vault file".to_string()))
+    }
+
+    fn save(
+        &self,
+        data: &[u8],
+        expected_generation: Option<u64>,
+        backup_generations: u32,
+    ) -> Result<(), VaultError> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(expected) = expected_generation {
+            check_generation(state.current.as_deref(), expected)?;
+        }
+
+        if let Some(previous) = state.current.replace(data.to_vec()) {
+            state.backups.push_front(BackupEntry {
+                info: BackupInfo {
+                    generation: 0,
+                    size: previous.len() as u64,
+                    modified: Utc::now(),
+                },
+                data: previous,
+            });
+        }
+        state
+            .backups
+            .truncate(backup_generations.min(MAX_BACKUP_GENERATIONS) as usize);
+
+        Ok(())
+    }
+
+    fn delete(&self) -> Result<(), VaultError> {
+        let mut state = self.state.lock().unwrap();
+        state.current = None;
+        state.backups.clear();
+        state.quick_unlock = None;
+        Ok(())
+    }
+
+    fn list_backups(&self) -> Result<Vec<BackupInfo>, VaultError> {
+        let state = self.state.lock().unwrap();
+
+        Ok(state
+            .backups
+            .iter()
+            .enumerate()
+            .map(|(index, backup)| BackupInfo {
+                generation: index as u32 + 1,
+                ..backup.info.clone()
+            })
+            .collect())
+    }
+
+    fn load_backup(&self, generation: u32) -> Result<Vec<u8>, VaultError> {
+        let state = self.state.lock().unwrap();
+
+        generation
+            .checked_sub(1)
+            .and_then(|index| state.backups.get(index as usize))
+            .map(|backup| backup.data.clone())
+            .ok_or_else(|| VaultError::NotFound(format!("Backup {}", generation)))
+    }
+
+    fn lock(&self) -> Result<StorageLock, VaultError> {
+        let mut state = self.state.lock().unwrap();
+        if state.locked {
+            return Err(VaultError::InUse(std::process::id()));
+        }
+        state.locked = true;
+
+        Ok(Box::new(MemoryLock {
+            state: Arc::clone(&self.state),
+        }))
+    }
+
+    fn load_quick_unlock(&self) -> Result<Vec<u8>, VaultError> {
+        self.state
+            .lock()
+            .unwrap()
+            .quick_unlock
+            .clone()
+            .ok_or_else(|| VaultError::NotFound("Quick unlock credential".to_string()))
+    }
+
+    fn save_quick_unlock(&self, wrapped_key: &[u8]) -> Result<(), VaultError> {
+        self.state.lock().unwrap().quick_unlock = Some(wrapped_key.to_vec());
+        Ok(())
+    }
+
+    fn delete_quick_unlock(&self) -> Result<(), VaultError> {
+        self.state.lock().unwrap().quick_unlock = None;
+        Ok(())
+    }
+
+    fn has_quick_unlock(&self) -> bool {
+        self.state.lock().unwrap().quick_unlock.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::password::crypto::KdfParams;
+
+    /// A minimal vault file: a valid header carrying `generation`, then a
+    /// one-byte payload that tells versions apart.
+    fn version(generation: u64, marker: u8) -> Vec<u8> {
+        let mut header = VaultHeader::new([7u8; 32], KdfParams::default());
+        header.generation = generation;
+        storage::encode_vault_file(&header, &[marker])
+    }
+
+    /// Behaviour every backend must share.
+    fn exercise(storage: &dyn VaultStorage) {
+        assert!(!storage.exists());
+        assert!(matches!(storage.load(), Err(VaultError::NotFound(_))));
+
+        storage.save(&version(0, 0), None, 2).unwrap();
+        assert!(storage.exists());
+        for generation in 1..4u64 {
+            storage
+                .save(
+                    &version(generation, generation as u8),
+                    Some(generation - 1),
+                    2,
+                )
+                .unwrap();
+        }
+        assert_eq!(storage.load().unwrap(), version(3, 3));
+
+        assert!(matches!(
+            storage.save(&version(9, 9), Some(1), 2),
+            Err(VaultError::Conflict)
+        ));
+
+        let backups = storage.list_backups().unwrap();
+        let generations: Vec<u32> = backups.iter().map(|b| b.generation).collect();
+        assert_eq!(generations, vec![1, 2]);
+        assert_eq!(storage.load_backup(1).unwrap(), version(2, 2));
+        assert_eq!(storage.load_backup(2).unwrap(), version(1, 1));
+        assert!(matches!(
+            storage.load_backup(3),
+            Err(VaultError::NotFound(_))
+        ));
+
+        // Restoring saves an older generation without a check.
+        storage.save(&version(1, 1), None, 2).unwrap();
+        assert_eq!(storage.load().unwrap(), version(1, 1));
+
+        let lock = storage.lock().unwrap();
+        drop(lock);
+        storage.lock().unwrap();
+
+        assert!(!storage.has_quick_unlock());
+        storage.save_quick_unlock(b"wrapped").unwrap();
+        assert_eq!(storage.load_quick_unlock().unwrap(), b"wrapped");
+        storage.delete_quick_unlock().unwrap();
+        storage.delete_quick_unlock().unwrap();
+        assert!(matches!(
+            storage.load_quick_unlock(),
+            Err(VaultError::NotFound(_))
+        ));
+
+        storage.save_quick_unlock(b"wrapped").unwrap();
+        storage.delete().unwrap();
+        assert!(!storage.exists());
+        assert!(!storage.has_quick_unlock());
+        assert!(storage.list_backups().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_file_storage() {
+        let dir = tempfile::tempdir().unwrap();
+        exercise(&FileStorage::new(dir.path().join("vault.encrypted")));
+    }
+
+    #[test]
+    fn test_directory_storage() {
+        let dir = tempfile::tempdir().unwrap();
+        exercise(&DirectoryStorage::new(dir.path().join("vault")));
+        assert!(!dir.path().join("vault").exists());
+    }
+
+    #[test]
+    fn test_memory_storage() {
+        exercise(&MemoryStorage::new());
+    }
+
+    #[test]
+    fn test_memory_lock_is_shared_between_clones() {
+        let storage = MemoryStorage::new();
+        let other = storage.clone();
+
+        let lock = storage.lock().unwrap();
+        assert!(matches!(other.lock(), Err(VaultError::InUse(_))));
+        drop(lock);
+        other.lock().unwrap();
+    }
+
+    #[test]
+    fn test_directory_storage_never_rewrites_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = DirectoryStorage::new(dir.path());
+
+        storage.save(&version(0, 0), None, 5).unwrap();
+        let first = storage.version_path(1);
+        let modified = fs::metadata(&first).unwrap().modified().unwrap();
+
+        storage.save(&version(1, 1), Some(0), 5).unwrap();
+        assert_eq!(fs::read(&first).unwrap(), version(0, 0));
+        assert_eq!(fs::metadata(&first).unwrap().modified().unwrap(), modified);
+        assert_eq!(fs::read(storage.version_path(2)).unwrap(), version(1, 1));
+
+        // Unrelated files in the directory are left alone.
+        fs::write(dir.path().join("README"), b"synced folder").unwrap();
+        storage.save(&version(2, 2), Some(1), 0).unwrap();
+        assert!(!first.exists());
+        assert!(dir.path().join("README").exists());
+        assert_eq!(StorageKind::detect(dir.path()), StorageKind::Directory);
+    }
+}
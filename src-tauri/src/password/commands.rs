@@ -0,0 +1,687 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, State};
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use super::autolock;
+use super::backend::StorageKind;
+use super::crypto::{self, KdfParams, DEFAULT_UNLOCK_TARGET};
+use super::error::VaultError;
+use super::export::{self, ExportFormat};
+use super::fields::{self, EntryTemplate};
+use super::generator;
+use super::import::{self, ImportFormat, ImportOptions, ImportReport};
+use super::registry::{VaultInfo, VaultState};
+use super::search::{SearchFilters, SearchResult};
+use super::storage::{self, BackupInfo};
+use super::totp::TotpCode;
+use super::types::{
+    CustomField, EntryInput, EntryKind, FieldKind, Passphrase, PassphraseOptions,
+    PasswordEntryDetails, PasswordEntrySummary, PasswordHistoryItem, PasswordOptions,
+    StrengthScore, UrlMatch, VaultSettings,
+};
+
+/// Every registered vault, loaded from the app data directory. Managed as
+/// Tauri state from `setup`, since the directory is only known once the app
+/// is running.
+pub type VaultStateMutex = Mutex<VaultState>;
+
+fn parse_id(id: &str) -> Result<Uuid, VaultError> {
+    Ok(Uuid::parse_str(id)?)
+}
+
+#[tauri::command]
+pub async fn list_vaults(state: State<'_, VaultStateMutex>) -> Result<Vec<VaultInfo>, VaultError> {
+    let state = state.lock().unwrap();
+    Ok(state.list())
+}
+
+/// Creates a vault, registers it under `name` and switches to it, unlocked.
+/// `storage_kind` picks the on-disk layout and defaults to a single file;
+/// `path` defaults to the app's own vaults directory; a `path` ending in
+/// `.kdbx` creates a KeePass database instead of a zims vault.
+#[tauri::command]
+pub async fn create_vault(
+    name: String,
+    master_password: String,
+    kdf_params: Option<KdfParams>,
+    storage_kind: Option<StorageKind>,
+    path: Option<PathBuf>,
+    state: State<'_, VaultStateMutex>,
+) -> Result<String, VaultError> {
+    let master_password = Zeroizing::new(master_password);
+    let kdf_params = match kdf_params {
+        Some(params) => {
+            params.validate()?;
+            params
+        }
+        None => crypto::calibrate_kdf(DEFAULT_UNLOCK_TARGET)?,
+    };
+
+    let mut state = state.lock().unwrap();
+    let id = state.create(
+        &name,
+        &master_password,
+        kdf_params,
+        storage_kind.unwrap_or_default(),
+        path,
+    )?;
+
+    let _ = storage::delete_legacy_credentials();
+
+    Ok(id.to_string())
+}
+
+/// Registers an existing vault at `path`, such as one copied from another
+/// machine, or a KeePass KDBX 4 database.
+#[tauri::command]
+pub async fn add_vault(
+    name: String,
+    path: PathBuf,
+    state: State<'_, VaultStateMutex>,
+) -> Result<String, VaultError> {
+    let mut state = state.lock().unwrap();
+    Ok(state.add_existing(&name, path)?.to_string())
+}
+
+/// Switches to vault `id`. It still has to be unlocked unless it already
+/// was; other vaults keep their state.
+#[tauri::command]
+pub async fn open_vault(
+    id: String,
+    state: State<'_, VaultStateMutex>,
+) -> Result<VaultInfo, VaultError> {
+    let id = parse_id(&id)?;
+    let mut state = state.lock().unwrap();
+    state.open(id)?;
+    state
+        .list()
+        .into_iter()
+        .find(|v| v.record.id == id)
+        .ok_or_else(|| VaultError::NotFound("Vault".to_string()))
+}
+
+#[tauri::command]
+pub async fn close_vault(app: AppHandle, id: String) -> Result<(), VaultError> {
+    let id = parse_id(&id)?;
+    autolock::close_and_notify(&app, id)
+}
+
+#[tauri::command]
+pub async fn rename_vault(
+    id: String,
+    name: String,
+    state: State<'_, VaultStateMutex>,
+) -> Result<(), VaultError> {
+    let id = parse_id(&id)?;
+    let mut state = state.lock().unwrap();
+    state.rename(id, &name)
+}
+
+/// Forgets vault `id`. With `delete_files` its data and backups are deleted
+/// too, which requires it to be unlocked.
+#[tauri::command]
+pub async fn remove_vault(
+    id: String,
+    delete_files: bool,
+    state: State<'_, VaultStateMutex>,
+) -> Result<(), VaultError> {
+    let id = parse_id(&id)?;
+    let mut state = state.lock().unwrap();
+    state.remove(id, delete_files)
+}
+
+#[tauri::command]
+pub async fn unlock_vault(
+    master_password: String,
+    state: State<'_, VaultStateMutex>,
+) -> Result<bool, VaultError> {
+    let master_password = Zeroizing::new(master_password);
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+    vault.unlock(&master_password)?;
+
+    // Earlier versions kept the raw vault key and a device key shared by
+    // every vault in the keyring; drop them now that the password has been
+    // proven.
+    let _ = storage::delete_legacy_credentials();
+
+    Ok(true)
+}
+
+/// The keyring entry for the active vault's quick-unlock device key; each
+/// vault has its own, by registry id.
+fn device_key_entry(state: &VaultState) -> Result<keyring::Entry, VaultError> {
+    let id = state
+        .active_id()
+        .ok_or_else(|| VaultError::NotFound("Vault".to_string()))?;
+    storage::device_key_entry(id)
+}
+
+/// Unlocks with the key remembered by `enable_quick_unlock`. Fails with
+/// `NOT_FOUND` when there is no usable credential, in which case the
+/// frontend should ask for the master password.
+#[tauri::command]
+pub async fn quick_unlock(state: State<'_, VaultStateMutex>) -> Result<bool, VaultError> {
+    let mut state = state.lock().unwrap();
+    let device_key = device_key_entry(&state)?;
+    let vault = state.active()?;
+    vault.unlock_with_device_key(&device_key)?;
+    Ok(true)
+}
+
+#[tauri::command]
+pub async fn enable_quick_unlock(state: State<'_, VaultStateMutex>) -> Result<(), VaultError> {
+    let mut state = state.lock().unwrap();
+    let device_key = device_key_entry(&state)?;
+    let vault = state.active()?;
+    vault.enable_quick_unlock(&device_key)
+}
+
+#[tauri::command]
+pub async fn disable_quick_unlock(state: State<'_, VaultStateMutex>) -> Result<(), VaultError> {
+    let mut state = state.lock().unwrap();
+    let device_key = device_key_entry(&state)?;
+    let vault = state.active()?;
+    vault.disable_quick_unlock(&device_key)
+}
+
+#[tauri::command]
+pub async fn is_quick_unlock_enabled(
+    state: State<'_, VaultStateMutex>,
+) -> Result<bool, VaultError> {
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+    Ok(vault.quick_unlock_enabled())
+}
+
+/// Locks vault `id`, or every vault without one.
+#[tauri::command]
+pub async fn lock_vault(app: AppHandle, id: Option<String>) -> Result<(), VaultError> {
+    match id {
+        Some(id) => autolock::lock_and_notify(&app, Some(parse_id(&id)?)),
+        None => autolock::lock_and_notify(&app, None),
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn vault_exists(state: State<'_, VaultStateMutex>) -> Result<bool, VaultError> {
+    let mut state = state.lock().unwrap();
+    Ok(state.active().is_ok_and(|vault| vault.exists()))
+}
+
+#[tauri::command]
+pub async fn is_vault_unlocked(state: State<'_, VaultStateMutex>) -> Result<bool, VaultError> {
+    let mut state = state.lock().unwrap();
+    Ok(state.active().is_ok_and(|vault| vault.is_unlocked()))
+}
+
+#[tauri::command]
+pub async fn get_all_passwords(
+    state: State<'_, VaultStateMutex>,
+) -> Result<Vec<PasswordEntrySummary>, VaultError> {
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+
+    let summaries: Vec<PasswordEntrySummary> =
+        vault.entries()?.iter().map(|e| e.clone().into()).collect();
+
+    Ok(summaries)
+}
+
+/// Ranked fuzzy search for the command palette, so the webview need not
+/// fetch every summary to filter them.
+#[tauri::command]
+pub async fn search_entries(
+    query: String,
+    filters: Option<SearchFilters>,
+    state: State<'_, VaultStateMutex>,
+) -> Result<Vec<SearchResult>, VaultError> {
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+
+    vault.search_entries(&query, &filters.unwrap_or_default())
+}
+
+/// Entries to offer for the page at `url`, best match first, for autofill.
+#[tauri::command]
+pub async fn find_entries_for_url(
+    url: String,
+    state: State<'_, VaultStateMutex>,
+) -> Result<Vec<PasswordEntrySummary>, VaultError> {
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+
+    vault.find_entries_for_url(&url)
+}
+
+#[tauri::command]
+pub async fn get_password(
+    id: String,
+    state: State<'_, VaultStateMutex>,
+) -> Result<PasswordEntryDetails, VaultError> {
+    let uuid = parse_id(&id)?;
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+
+    Ok(vault.entry(uuid)?.clone().into())
+}
+
+#[tauri::command]
+pub async fn reveal_password(
+    id: String,
+    state: State<'_, VaultStateMutex>,
+) -> Result<String, VaultError> {
+    let uuid = parse_id(&id)?;
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+
+    Ok(vault.entry(uuid)?.password.as_str().to_string())
+}
+
+/// The value of a hidden custom field, which entry details leave out.
+#[tauri::command]
+pub async fn reveal_field(
+    id: String,
+    name: String,
+    state: State<'_, VaultStateMutex>,
+) -> Result<String, VaultError> {
+    let uuid = parse_id(&id)?;
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+
+    let field = vault
+        .entry(uuid)?
+        .field(&name)
+        .ok_or_else(|| VaultError::NotFound(format!("Field '{}'", name)))?;
+    Ok(field.value.as_str().to_string())
+}
+
+/// The fields each entry kind expects, for the entry editor.
+#[tauri::command]
+pub async fn get_entry_templates() -> Result<Vec<EntryTemplate>, VaultError> {
+    Ok(fields::templates())
+}
+
+/// The entry's current two-factor code and how long it stays valid.
+#[tauri::command]
+pub async fn get_totp_code(
+    id: String,
+    state: State<'_, VaultStateMutex>,
+) -> Result<TotpCode, VaultError> {
+    let uuid = parse_id(&id)?;
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+
+    vault.totp_code(uuid)
+}
+
+/// The entry's previous passwords, newest first.
+#[tauri::command]
+pub async fn get_password_history(
+    id: String,
+    state: State<'_, VaultStateMutex>,
+) -> Result<Vec<PasswordHistoryItem>, VaultError> {
+    let uuid = parse_id(&id)?;
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+
+    Ok(vault.password_history(uuid)?.to_vec())
+}
+
+/// Makes the password at `index` in the entry's history current again.
+#[tauri::command]
+pub async fn restore_password(
+    id: String,
+    index: usize,
+    state: State<'_, VaultStateMutex>,
+) -> Result<(), VaultError> {
+    let uuid = parse_id(&id)?;
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+
+    vault.restore_password(uuid, index)
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn create_password(
+    title: String,
+    username: String,
+    password: String,
+    url: Option<String>,
+    notes: Option<String>,
+    totp: Option<String>,
+    tags: Vec<String>,
+    kind: Option<EntryKind>,
+    fields: Option<Vec<CustomField>>,
+    urls: Option<Vec<String>>,
+    url_match: Option<UrlMatch>,
+    state: State<'_, VaultStateMutex>,
+) -> Result<String, VaultError> {
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+
+    let id = vault.add_entry(EntryInput {
+        kind: kind.unwrap_or_default(),
+        title,
+        username,
+        password: password.into(),
+        url,
+        urls: urls.unwrap_or_default(),
+        url_match: url_match.unwrap_or_default(),
+        notes,
+        totp: totp.map(Into::into),
+        fields: fields.unwrap_or_default(),
+        tags,
+        favorite: false,
+    })?;
+
+    Ok(id.to_string())
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn update_password(
+    id: String,
+    title: String,
+    username: String,
+    password: String,
+    url: Option<String>,
+    notes: Option<String>,
+    totp: Option<String>,
+    tags: Vec<String>,
+    favorite: bool,
+    kind: Option<EntryKind>,
+    fields: Option<Vec<CustomField>>,
+    urls: Option<Vec<String>>,
+    url_match: Option<UrlMatch>,
+    state: State<'_, VaultStateMutex>,
+) -> Result<(), VaultError> {
+    let uuid = parse_id(&id)?;
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+
+    // Kind, fields, the URL settings and the TOTP seed are left as they are
+    // unless given; an empty TOTP seed removes it.
+    let current = vault.entry(uuid)?;
+    let kind = kind.unwrap_or(current.kind);
+    let urls = urls.unwrap_or_else(|| current.urls.clone());
+    let url_match = url_match.unwrap_or(current.url_match);
+    let fields = match fields {
+        Some(fields) => keep_hidden_values(fields, &current.fields),
+        None => current.fields.clone(),
+    };
+
+    vault.update_entry(
+        uuid,
+        EntryInput {
+            kind,
+            title,
+            username,
+            password: password.into(),
+            url,
+            urls,
+            url_match,
+            notes,
+            totp: totp.map(Into::into),
+            fields,
+            tags,
+            favorite,
+        },
+    )
+}
+
+/// Entry details leave hidden values out, so an edited field list sends
+/// them back empty unless the user revealed or changed them; those keep
+/// their stored value.
+fn keep_hidden_values(mut fields: Vec<CustomField>, current: &[CustomField]) -> Vec<CustomField> {
+    for field in &mut fields {
+        if field.kind != FieldKind::Hidden || !field.value.as_str().is_empty() {
+            continue;
+        }
+        if let Some(stored) = current
+            .iter()
+            .find(|c| c.name == field.name && c.kind == FieldKind::Hidden)
+        {
+            field.value = stored.value.clone();
+        }
+    }
+    fields
+}
+
+/// Moves the entry to the trash.
+#[tauri::command]
+pub async fn delete_password(
+    id: String,
+    state: State<'_, VaultStateMutex>,
+) -> Result<(), VaultError> {
+    let uuid = parse_id(&id)?;
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+
+    vault.delete_entry(uuid)
+}
+
+/// Trashed entries, with when each was deleted.
+#[tauri::command]
+pub async fn get_trash(
+    state: State<'_, VaultStateMutex>,
+) -> Result<Vec<PasswordEntrySummary>, VaultError> {
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+
+    Ok(vault.trash()?.iter().map(|e| e.clone().into()).collect())
+}
+
+#[tauri::command]
+pub async fn restore_from_trash(
+    id: String,
+    state: State<'_, VaultStateMutex>,
+) -> Result<(), VaultError> {
+    let uuid = parse_id(&id)?;
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+
+    vault.restore_entry(uuid)
+}
+
+/// Deletes a trashed entry for good.
+#[tauri::command]
+pub async fn purge_from_trash(
+    id: String,
+    state: State<'_, VaultStateMutex>,
+) -> Result<(), VaultError> {
+    let uuid = parse_id(&id)?;
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+
+    vault.purge_entry(uuid)
+}
+
+/// Deletes every trashed entry for good, returning how many there were.
+#[tauri::command]
+pub async fn empty_trash(state: State<'_, VaultStateMutex>) -> Result<usize, VaultError> {
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+
+    vault.empty_trash()
+}
+
+/// Imports the export at `path`, written by another password manager in
+/// `format`. Run with `dry_run` first to preview the entries and see which
+/// look like duplicates.
+#[tauri::command]
+pub async fn import_entries(
+    path: PathBuf,
+    format: ImportFormat,
+    options: Option<ImportOptions>,
+    state: State<'_, VaultStateMutex>,
+) -> Result<ImportReport, VaultError> {
+    let data = Zeroizing::new(fs::read(&path)?);
+    let entries = import::parse(format, &data)?;
+
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+    vault.import_entries(entries, &options.unwrap_or_default())
+}
+
+/// Writes the active vault's entries to `path`, limited to those with any of
+/// `tags` if given. The encrypted format is protected by `passphrase`; the
+/// plaintext formats require `master_password` again.
+#[tauri::command]
+pub async fn export_vault(
+    path: PathBuf,
+    format: ExportFormat,
+    passphrase: Option<String>,
+    master_password: Option<String>,
+    tags: Option<Vec<String>>,
+    state: State<'_, VaultStateMutex>,
+) -> Result<(), VaultError> {
+    let passphrase = passphrase.map(Zeroizing::new);
+    let master_password = master_password.map(Zeroizing::new);
+    let tags = tags.unwrap_or_default();
+
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+    let data = match format {
+        ExportFormat::Encrypted => {
+            let passphrase = passphrase.ok_or_else(|| {
+                VaultError::InvalidInput("An export passphrase is required".to_string())
+            })?;
+            Zeroizing::new(vault.export_encrypted(&passphrase, &tags)?)
+        }
+        _ => {
+            let master_password = master_password.ok_or_else(|| {
+                VaultError::InvalidInput(
+                    "The master password is required for a plaintext export".to_string(),
+                )
+            })?;
+            vault.export_plaintext(&master_password, format, &tags)?
+        }
+    };
+
+    export::write_file(&path, &data)
+}
+
+#[tauri::command]
+pub async fn get_vault_settings(
+    state: State<'_, VaultStateMutex>,
+) -> Result<VaultSettings, VaultError> {
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+
+    Ok(vault.settings()?.clone())
+}
+
+#[tauri::command]
+pub async fn update_vault_settings(
+    settings: VaultSettings,
+    state: State<'_, VaultStateMutex>,
+) -> Result<(), VaultError> {
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+
+    vault.update_settings(settings)
+}
+
+#[tauri::command]
+pub async fn list_backups(
+    state: State<'_, VaultStateMutex>,
+) -> Result<Vec<BackupInfo>, VaultError> {
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+    vault.list_backups()
+}
+
+/// Replaces the current vault with backup `generation` once it has been
+/// verified to decrypt under `master_password`. The replaced file is itself
+/// rotated into the backup ring, so a restore can be undone.
+#[tauri::command]
+pub async fn restore_backup(
+    generation: u32,
+    master_password: String,
+    state: State<'_, VaultStateMutex>,
+) -> Result<(), VaultError> {
+    let master_password = Zeroizing::new(master_password);
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+
+    vault.restore_backup(generation, &master_password)
+}
+
+/// Re-encrypts the whole vault under `new_password` with a fresh salt. The
+/// old password is verified against the file on disk. Quick unlock is
+/// revoked and has to be enabled again.
+#[tauri::command]
+pub async fn change_master_password(
+    old_password: String,
+    new_password: String,
+    state: State<'_, VaultStateMutex>,
+) -> Result<(), VaultError> {
+    let old_password = Zeroizing::new(old_password);
+    let new_password = Zeroizing::new(new_password);
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+
+    vault.change_master_password(&old_password, &new_password)
+}
+
+/// Benchmarks this machine and returns KDF parameters whose derivation
+/// takes roughly `target_ms`, for use with `initialize_vault` or
+/// `update_kdf_params`.
+#[tauri::command]
+pub async fn calibrate_kdf(target_ms: u64) -> Result<KdfParams, VaultError> {
+    crypto::calibrate_kdf(Duration::from_millis(target_ms))
+}
+
+#[tauri::command]
+pub async fn update_kdf_params(
+    master_password: String,
+    kdf_params: KdfParams,
+    state: State<'_, VaultStateMutex>,
+) -> Result<(), VaultError> {
+    let master_password = Zeroizing::new(master_password);
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+
+    vault.update_kdf_params(&master_password, kdf_params)
+}
+
+#[tauri::command]
+pub async fn generate_password(options: PasswordOptions) -> Result<String, VaultError> {
+    generator::generate_password(&options)
+}
+
+/// A password for the site at `url`, following the password rules the
+/// vault settings or the bundled presets have for it, unless `options`
+/// brings its own.
+#[tauri::command]
+pub async fn generate_password_for_site(
+    url: String,
+    mut options: PasswordOptions,
+    state: State<'_, VaultStateMutex>,
+) -> Result<String, VaultError> {
+    let mut state = state.lock().unwrap();
+    let vault = state.active()?;
+
+    if options.rules.is_none() {
+        options.rules = vault.password_rules_for(&url)?;
+    }
+    generator::generate_password(&options)
+}
+
+#[tauri::command]
+pub async fn generate_passphrase(options: PassphraseOptions) -> Result<Passphrase, VaultError> {
+    generator::generate_passphrase(&options)
+}
+
+#[tauri::command]
+pub async fn check_password_strength(password: String) -> Result<StrengthScore, VaultError> {
+    let password = Zeroizing::new(password);
+    Ok(generator::check_strength(&password))
+}
@@ -1,39 +1,190 @@
 use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHasher, Salt, SaltString},
+    password_hash::rand_core::{OsRng, RngCore},
     Argon2, Params,
 };
 use chacha20poly1305::{
-    aead::{Aead, KeyInit, OsRng as ChaChaRng},
+    aead::{Aead, AeadCore, KeyInit, OsRng as ChaChaRng, Payload},
     ChaCha20Poly1305, Nonce,
 };
-use std::error::Error;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
 
-pub fn derive_key(password: &str, salt: &[u8; 32]) -> Result<[u8; 32], Box<dyn Error>> {
-    let params = Params::new(65536, 3, 4, Some(32))?;
-    let argon2 = Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+use super::error::VaultError;
 
-    let salt_str = SaltString::encode_b64(salt).map_err(|e| e.to_string())?;
-    let salt = Salt::from_b64(&salt_str).map_err(|e| e.to_string())?;
+/// A 256-bit vault key, wiped from memory when dropped.
+pub type VaultKey = Zeroizing<[u8; 32]>;
 
-    let hash = argon2
-        .hash_password(password.as_bytes(), salt)
-        .map_err(|e| e.to_string())?;
+/// Argon2id cost parameters. These are persisted in the plaintext vault
+/// header so the key can be re-derived before anything is decrypted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            memory_kib: 65536,
+            iterations: 3,
+            parallelism: 4,
+        }
+    }
+}
+
+impl KdfParams {
+    /// Weakest parameters a vault may be created with. Vaults found below
+    /// this are re-keyed on their next unlock. Matches OWASP's Argon2id
+    /// minimum (19 MiB, 2 passes).
+    pub const POLICY_FLOOR: KdfParams = KdfParams {
+        memory_kib: 19456,
+        iterations: 2,
+        parallelism: 1,
+    };
+
+    /// Upper bounds, so a crafted header cannot make unlock exhaust memory.
+    pub const MAX_MEMORY_KIB: u32 = 4 * 1024 * 1024;
+    pub const MAX_ITERATIONS: u32 = 64;
+    pub const MAX_PARALLELISM: u32 = 16;
+
+    pub fn meets_policy(&self) -> bool {
+        self.memory_kib >= Self::POLICY_FLOOR.memory_kib
+            && self.iterations >= Self::POLICY_FLOOR.iterations
+    }
+
+    pub fn within_limits(&self) -> bool {
+        self.memory_kib <= Self::MAX_MEMORY_KIB
+            && self.iterations <= Self::MAX_ITERATIONS
+            && (1..=Self::MAX_PARALLELISM).contains(&self.parallelism)
+    }
+
+    /// Checks caller-supplied parameters for a new or re-keyed vault.
+    pub fn validate(&self) -> Result<(), VaultError> {
+        if !self.meets_policy() {
+            return Err(VaultError::InvalidInput(format!(
+                "KDF parameters must be at least {} KiB and {} iterations",
+                Self::POLICY_FLOOR.memory_kib,
+                Self::POLICY_FLOOR.iterations
+            )));
+        }
+        if !self.within_limits() {
+            return Err(VaultError::InvalidInput(
+                "KDF parameters out of range".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The field-wise maximum of these parameters and the defaults, used
+    /// when transparently re-keying a vault that fell below the floor.
+    pub fn strengthened(&self) -> KdfParams {
+        let recommended = KdfParams::default();
+        KdfParams {
+            memory_kib: self.memory_kib.max(recommended.memory_kib),
+            iterations: self.iterations.max(recommended.iterations),
+            parallelism: self.parallelism.max(recommended.parallelism),
+        }
+    }
+}
+
+/// Unlock time new vaults are calibrated for when no parameters are given.
+pub const DEFAULT_UNLOCK_TARGET: Duration = Duration::from_millis(500);
+
+const CALIBRATION_MEMORY_KIB: u32 = 65536;
+const CALIBRATION_MAX_ITERATIONS: u32 = 10;
 
-    let hash_bytes = hash.hash.ok_or("Failed to get hash bytes")?;
-    let mut key = [0u8; 32];
-    key.copy_from_slice(hash_bytes.as_bytes());
+/// Benchmarks this machine and picks parameters whose derivation takes
+/// roughly `target`. Memory is preferred over passes: it starts at 64 MiB
+/// with a single pass, halves memory (down to the policy floor) while one
+/// pass is already too slow, then adds passes to fill the remaining budget.
+pub fn calibrate_kdf(target: Duration) -> Result<KdfParams, VaultError> {
+    let parallelism = std::thread::available_parallelism()
+        .map(|n| n.get() as u32)
+        .unwrap_or(1)
+        .min(4);
+
+    let mut params = KdfParams {
+        memory_kib: CALIBRATION_MEMORY_KIB,
+        iterations: 1,
+        parallelism,
+    };
+
+    loop {
+        let start = Instant::now();
+        derive_key_with_params("zims-calibration", &[0u8; 32], &params)?;
+        let per_pass = start.elapsed();
+
+        if per_pass <= target || params.memory_kib <= KdfParams::POLICY_FLOOR.memory_kib {
+            let passes = (target.as_secs_f64() / per_pass.as_secs_f64().max(1e-6)) as u32;
+            params.iterations = passes.clamp(
+                KdfParams::POLICY_FLOOR.iterations,
+                CALIBRATION_MAX_ITERATIONS,
+            );
+            return Ok(params);
+        }
+
+        params.memory_kib = (params.memory_kib / 2).max(KdfParams::POLICY_FLOOR.memory_kib);
+    }
+}
+
+pub fn derive_key(password: &str, salt: &[u8; 32]) -> Result<VaultKey, VaultError> {
+    derive_key_with_params(password, salt, &KdfParams::default())
+}
+
+/// Derives the key straight into a zeroizing buffer, so no copy of it
+/// outlives the returned value on either the success or the error path.
+pub fn derive_key_with_params(
+    password: &str,
+    salt: &[u8; 32],
+    kdf: &KdfParams,
+) -> Result<VaultKey, VaultError> {
+    derive_argon2(
+        argon2::Algorithm::Argon2id,
+        argon2::Version::V0x13,
+        password.as_bytes(),
+        salt,
+        kdf,
+    )
+}
+
+/// Argon2 over arbitrary key material and salt, for formats other than our
+/// own: KDBX hashes the password into a composite key first, and may use
+/// Argon2d.
+pub fn derive_argon2(
+    algorithm: argon2::Algorithm,
+    version: argon2::Version,
+    secret: &[u8],
+    salt: &[u8],
+    kdf: &KdfParams,
+) -> Result<VaultKey, VaultError> {
+    let params = Params::new(kdf.memory_kib, kdf.iterations, kdf.parallelism, Some(32))
+        .map_err(|e| VaultError::KdfFailure(e.to_string()))?;
+    let argon2 = Argon2::new(algorithm, version, params);
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    argon2
+        .hash_password_into(secret, salt, key.as_mut())
+        .map_err(|e| VaultError::KdfFailure(e.to_string()))?;
 
     Ok(key)
 }
 
-pub fn encrypt(data: &[u8], key: &[u8; 32]) -> Result<Vec<u8>, Box<dyn Error>> {
+pub fn encrypt(data: &[u8], key: &[u8; 32]) -> Result<Vec<u8>, VaultError> {
+    encrypt_with_aad(data, b"", key)
+}
+
+/// Encrypts `data`, binding `aad` (e.g. the vault header) into the tag so
+/// it cannot be altered without decryption failing.
+pub fn encrypt_with_aad(data: &[u8], aad: &[u8], key: &[u8; 32]) -> Result<Vec<u8>, VaultError> {
     let cipher = ChaCha20Poly1305::new(key.into());
 
     let nonce = ChaCha20Poly1305::generate_nonce(&mut ChaChaRng);
 
     let ciphertext = cipher
-        .encrypt(&nonce, data)
-        .map_err(|e| format!("Encryption error: {}", e))?;
+        .encrypt(&nonce, Payload { msg: data, aad })
+        .map_err(|e| VaultError::Crypto(e.to_string()))?;
 
     let mut result = nonce.to_vec();
     result.extend_from_slice(&ciphertext);
@@ -41,9 +192,21 @@ pub fn encrypt(data: &[u8], key: &[u8; 32]) -> Result<Vec<u8>, Box<dyn Error>> {
     Ok(result)
 }
 
-pub fn decrypt(encrypted_data: &[u8], key: &[u8; 32]) -> Result<Vec<u8>, Box<dyn Error>> {
+/// Decrypts `encrypted_data`. A failed tag check is reported as
+/// `WrongPassword`, since a wrong key and tampered data are indistinguishable.
+pub fn decrypt(encrypted_data: &[u8], key: &[u8; 32]) -> Result<Zeroizing<Vec<u8>>, VaultError> {
+    decrypt_with_aad(encrypted_data, b"", key)
+}
+
+pub fn decrypt_with_aad(
+    encrypted_data: &[u8],
+    aad: &[u8],
+    key: &[u8; 32],
+) -> Result<Zeroizing<Vec<u8>>, VaultError> {
     if encrypted_data.len() < 12 {
-        return Err("Encrypted data too short".into());
+        return Err(VaultError::Corrupted(
+            "Encrypted data too short".to_string(),
+        ));
     }
 
     let cipher = ChaCha20Poly1305::new(key.into());
@@ -53,19 +216,64 @@ pub fn decrypt(encrypted_data: &[u8], key: &[u8; 32]) -> Result<Vec<u8>, Box<dyn
     let ciphertext = &encrypted_data[12..];
 
     let plaintext = cipher
-        .decrypt(nonce, ciphertext)
-        .map_err(|e| format!("Decryption error: {}", e))?;
+        .decrypt(
+            nonce,
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| VaultError::WrongPassword)?;
 
-    Ok(plaintext)
+    Ok(Zeroizing::new(plaintext))
 }
 
 pub fn generate_salt() -> [u8; 32] {
     let mut salt = [0u8; 32];
-    use rand::RngCore;
     OsRng.fill_bytes(&mut salt);
     salt
 }
 
+/// Fills `buf` from the OS random number generator.
+pub fn fill_random(buf: &mut [u8]) {
+    OsRng.fill_bytes(buf);
+}
+
+/// A random key, used as the device key for quick unlock.
+pub fn generate_key() -> VaultKey {
+    let mut key = Zeroizing::new([0u8; 32]);
+    OsRng.fill_bytes(key.as_mut());
+    key
+}
+
+/// Encrypts `key` under `wrapping_key`. `aad` ties the wrapped key to its
+/// context (the vault salt), so it cannot be replayed against another vault.
+pub fn wrap_key(
+    key: &[u8; 32],
+    wrapping_key: &[u8; 32],
+    aad: &[u8],
+) -> Result<Vec<u8>, VaultError> {
+    encrypt_with_aad(key, aad, wrapping_key)
+}
+
+pub fn unwrap_key(
+    wrapped: &[u8],
+    wrapping_key: &[u8; 32],
+    aad: &[u8],
+) -> Result<VaultKey, VaultError> {
+    let plaintext = decrypt_with_aad(wrapped, aad, wrapping_key)?;
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    if plaintext.len() != key.len() {
+        return Err(VaultError::Corrupted(
+            "Wrapped key has wrong length".to_string(),
+        ));
+    }
+    key.copy_from_slice(&plaintext);
+
+    Ok(key)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -96,7 +304,7 @@ mod tests {
         let encrypted = encrypt(plaintext, &key1).unwrap();
 
         let result = decrypt(&encrypted, &key2);
-        assert!(result.is_err());
+        assert!(matches!(result, Err(VaultError::WrongPassword)));
     }
 
     #[test]
@@ -109,4 +317,80 @@ mod tests {
 
         assert_eq!(key1, key2);
     }
+
+    #[test]
+    fn test_key_derivation_known_answer() {
+        // Pins the derivation so existing vaults keep opening across
+        // changes to how the Argon2 output is produced.
+        let params = KdfParams {
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let key = derive_key_with_params("zims", &[7u8; 32], &params).unwrap();
+
+        assert_eq!(
+            hex::encode(key.as_ref()),
+            "44aea29ff93c7ac2d44b2e7f0caec6dff7177014ff68c229292fe56cb0cfd11b"
+        );
+    }
+
+    #[test]
+    fn test_kdf_policy() {
+        assert!(KdfParams::default().meets_policy());
+        assert!(KdfParams::default().validate().is_ok());
+
+        let weak = KdfParams {
+            memory_kib: 4096,
+            iterations: 1,
+            parallelism: 1,
+        };
+        assert!(!weak.meets_policy());
+        assert!(weak.within_limits());
+        assert!(weak.validate().is_err());
+        assert_eq!(weak.strengthened(), KdfParams::default());
+
+        let huge = KdfParams {
+            memory_kib: u32::MAX,
+            ..KdfParams::default()
+        };
+        assert!(!huge.within_limits());
+        assert!(huge.validate().is_err());
+    }
+
+    #[test]
+    fn test_calibration_respects_floor() {
+        let params = calibrate_kdf(Duration::from_millis(1)).unwrap();
+        assert!(params.validate().is_ok());
+        assert_eq!(params.iterations, KdfParams::POLICY_FLOOR.iterations);
+    }
+
+    #[test]
+    fn test_aad_mismatch_fails() {
+        let salt = generate_salt();
+        let key = derive_key("aad_password", &salt).unwrap();
+
+        let encrypted = encrypt_with_aad(b"payload", b"header-v1", &key).unwrap();
+        assert!(decrypt_with_aad(&encrypted, b"header-v1", &key).is_ok());
+        assert!(decrypt_with_aad(&encrypted, b"header-v2", &key).is_err());
+    }
+
+    #[test]
+    fn test_key_wrap_roundtrip() {
+        let key = generate_key();
+        let device_key = generate_key();
+        assert_ne!(key, device_key);
+
+        let wrapped = wrap_key(&key, &device_key, b"salt-a").unwrap();
+        assert_eq!(unwrap_key(&wrapped, &device_key, b"salt-a").unwrap(), key);
+
+        assert!(matches!(
+            unwrap_key(&wrapped, &device_key, b"salt-b"),
+            Err(VaultError::WrongPassword)
+        ));
+        assert!(matches!(
+            unwrap_key(&wrapped, &generate_key(), b"salt-a"),
+            Err(VaultError::WrongPassword)
+        ));
+    }
 }
@@ -0,0 +1,103 @@
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+
+/// Errors returned by the password module. Commands hand these straight to
+/// the frontend, which receives `{ code, message }` and should branch on
+/// `code` only; messages are for logs and fallback display.
+#[derive(Debug, thiserror::Error)]
+pub enum VaultError {
+    #[error("Vault is locked")]
+    Locked,
+    #[error("Invalid master password")]
+    WrongPassword,
+    #[error("{0} not found")]
+    NotFound(String),
+    #[error("Vault already exists")]
+    AlreadyExists,
+    #[error("Vault is open in another process (pid {0})")]
+    InUse(u32),
+    #[error("Vault file changed on disk since it was loaded")]
+    Conflict,
+    #[error("Vault data is corrupted: {0}")]
+    Corrupted(String),
+    #[error("Unsupported vault format version {0}")]
+    VersionUnsupported(u16),
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Key derivation failed: {0}")]
+    KdfFailure(String),
+    #[error("Encryption error: {0}")]
+    Crypto(String),
+    #[error("Keyring error: {0}")]
+    Keyring(String),
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
+}
+
+impl VaultError {
+    /// Stable identifier for the error kind. Never change an existing code;
+    /// the frontend keys translations off these.
+    pub fn code(&self) -> &'static str {
+        match self {
+            VaultError::Locked => "LOCKED",
+            VaultError::WrongPassword => "WRONG_PASSWORD",
+            VaultError::NotFound(_) => "NOT_FOUND",
+            VaultError::AlreadyExists => "ALREADY_EXISTS",
+            VaultError::InUse(_) => "IN_USE",
+            VaultError::Conflict => "CONFLICT",
+            VaultError::Corrupted(_) => "CORRUPTED",
+            VaultError::VersionUnsupported(_) => "VERSION_UNSUPPORTED",
+            VaultError::Unsupported(_) => "UNSUPPORTED",
+            VaultError::Io(_) => "IO",
+            VaultError::KdfFailure(_) => "KDF_FAILURE",
+            VaultError::Crypto(_) => "CRYPTO",
+            VaultError::Keyring(_) => "KEYRING",
+            VaultError::InvalidInput(_) => "INVALID_INPUT",
+        }
+    }
+}
+
+impl Serialize for VaultError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("VaultError", 2)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
+
+impl From<serde_json::Error> for VaultError {
+    fn from(e: serde_json::Error) -> Self {
+        VaultError::Corrupted(e.to_string())
+    }
+}
+
+impl From<keyring::Error> for VaultError {
+    fn from(e: keyring::Error) -> Self {
+        VaultError::Keyring(e.to_string())
+    }
+}
+
+impl From<uuid::Error> for VaultError {
+    fn from(e: uuid::Error) -> Self {
+        VaultError::InvalidInput(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serializes_code_and_message() {
+        let json = serde_json::to_value(VaultError::Locked).unwrap();
+        assert_eq!(json["code"], "LOCKED");
+        assert_eq!(json["message"], "Vault is locked");
+
+        let json = serde_json::to_value(VaultError::NotFound("Password entry".into())).unwrap();
+        assert_eq!(json["code"], "NOT_FOUND");
+        assert_eq!(json["message"], "Password entry not found");
+    }
+}
@@ -0,0 +1,701 @@
+use rand::rngs::OsRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, TryRngCore};
+use std::collections::HashSet;
+use std::iter;
+use std::sync::OnceLock;
+
+use super::error::VaultError;
+use super::rules::{PasswordRules, DIGIT, LOWER, UPPER};
+use super::types::{Passphrase, PassphraseOptions, PasswordOptions, StrengthScore, WordCase};
+
+const SYMBOLS: &str = "!@#$%^&*()-_=+[]{}|;:,.<>?";
+/// Left out when `exclude_ambiguous` is set, being easily confused.
+const AMBIGUOUS: &str = "iloIO01";
+
+/// The EFF's large diceware wordlist, <https://www.eff.org/dice>, in its
+/// published format: each word numbered by the five dice rolls that pick it.
+static EFF_LARGE_WORDLIST: &str = include_str!("eff_large_wordlist.txt");
+/// One word for every roll of five dice.
+const WORDLIST_SIZE: usize = 6 * 6 * 6 * 6 * 6;
+
+pub const MIN_PASSPHRASE_WORDS: u32 = 3;
+pub const MAX_PASSPHRASE_WORDS: u32 = 20;
+
+/// A password of `options.length` random characters. Randomness comes from
+/// the operating system, and a password always meets the options' minimums:
+/// that many positions, shuffled among the rest, draw from their own class.
+pub fn generate_password(options: &PasswordOptions) -> Result<String, VaultError> {
+    let policy = match &options.rules {
+        Some(rules) => rules_policy(options, &PasswordRules::parse(rules)?)?,
+        None => options_policy(options)?,
+    };
+    generate(&policy, &mut OsRng.unwrap_err())
+}
+
+/// What a generated password has to satisfy.
+struct Policy {
+    length: usize,
+    /// Sets to draw at least so many characters from.
+    required: Vec<(Vec<char>, usize)>,
+    /// Every character the password may use.
+    allowed: Vec<char>,
+    max_consecutive: Option<usize>,
+}
+
+fn options_policy(options: &PasswordOptions) -> Result<Policy, VaultError> {
+    let classes = [
+        (
+            options.include_lowercase,
+            LOWER,
+            options.min_lowercase,
+            "lowercase letters",
+        ),
+        (
+            options.include_uppercase,
+            UPPER,
+            options.min_uppercase,
+            "uppercase letters",
+        ),
+        (
+            options.include_numbers,
+            DIGIT,
+            options.min_numbers,
+            "numbers",
+        ),
+        (
+            options.include_symbols,
+            SYMBOLS,
+            options.min_symbols,
+            "symbols",
+        ),
+    ];
+
+    let mut required = Vec::new();
+    let mut allowed: Vec<char> = Vec::new();
+    for (included, chars, min, name) in classes {
+        if !included {
+            if min > 0 {
+                return Err(invalid(format!(
+                    "A minimum of {} needs them included",
+                    name
+                )));
+            }
+            continue;
+        }
+        let set: Vec<char> = chars.chars().filter(|&c| !excluded(options, c)).collect();
+        if set.is_empty() {
+            return Err(invalid(format!("All {} are excluded", name)));
+        }
+        allowed.extend(&set);
+        required.push((set, min.max(1) as usize));
+    }
+    for c in options.include_chars.chars() {
+        if !c.is_control() && !excluded(options, c) && !allowed.contains(&c) {
+            allowed.push(c);
+        }
+    }
+
+    if allowed.is_empty() {
+        return Err(invalid("No character types selected".to_string()));
+    }
+    policy(options, options.length, required, allowed, None)
+}
+
+fn rules_policy(options: &PasswordOptions, rules: &PasswordRules) -> Result<Policy, VaultError> {
+    let keep = |set: &[char]| -> Vec<char> {
+        set.iter()
+            .copied()
+            .filter(|&c| !excluded(options, c))
+            .collect()
+    };
+
+    let mut required = Vec::new();
+    for set in &rules.required {
+        let set = keep(set);
+        if set.is_empty() {
+            return Err(invalid(
+                "The rules require characters that are all excluded".to_string(),
+            ));
+        }
+        required.push((set, 1));
+    }
+    let allowed = keep(&rules.allowed);
+    if allowed.is_empty() {
+        return Err(invalid(
+            "The rules allow no characters that are not excluded".to_string(),
+        ));
+    }
+
+    let length = options.length.clamp(
+        rules.min_length.unwrap_or(0),
+        rules.max_length.unwrap_or(u32::MAX),
+    );
+    policy(options, length, required, allowed, rules.max_consecutive)
+}
+
+fn policy(
+    options: &PasswordOptions,
+    length: u32,
+    required: Vec<(Vec<char>, usize)>,
+    allowed: Vec<char>,
+    max_consecutive: Option<u32>,
+) -> Result<Policy, VaultError> {
+    let max_consecutive = match (options.max_consecutive, max_consecutive) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    if max_consecutive == Some(0) {
+        return Err(invalid(
+            "At least one of each character has to be allowed in a row".to_string(),
+        ));
+    }
+
+    let needed: usize = required.iter().map(|(_, count)| count).sum();
+    if needed > length as usize {
+        return Err(invalid(format!(
+            "The minimums need at least {} characters",
+            needed
+        )));
+    }
+
+    Ok(Policy {
+        length: length as usize,
+        required,
+        allowed,
+        max_consecutive: max_consecutive.map(|max| max as usize),
+    })
+}
+
+fn excluded(options: &PasswordOptions, c: char) -> bool {
+    options.exclude_chars.contains(c) || (options.exclude_ambiguous && AMBIGUOUS.contains(c))
+}
+
+fn generate<R: Rng + ?Sized>(policy: &Policy, rng: &mut R) -> Result<String, VaultError> {
+    let needed: usize = policy.required.iter().map(|(_, count)| count).sum();
+    let mut slots: Vec<&[char]> = policy
+        .required
+        .iter()
+        .flat_map(|(set, count)| iter::repeat_n(set.as_slice(), *count))
+        .chain(iter::repeat_n(
+            policy.allowed.as_slice(),
+            policy.length - needed,
+        ))
+        .collect();
+    slots.shuffle(rng);
+
+    let mut password: Vec<char> = Vec::with_capacity(policy.length);
+    for set in slots {
+        // The character that would make a run too long, if there is one.
+        let last = password.last().copied();
+        let run = password
+            .iter()
+            .rev()
+            .take_while(|&&c| Some(c) == last)
+            .count();
+        let banned = last.filter(|_| policy.max_consecutive.is_some_and(|max| run >= max));
+
+        if set.iter().all(|&c| Some(c) == banned) {
+            return Err(invalid(
+                "Too few characters to keep from repeating them".to_string(),
+            ));
+        }
+        let c = loop {
+            let c = set[rng.random_range(0..set.len())];
+            if Some(c) != banned {
+                break c;
+            }
+        };
+        password.push(c);
+    }
+
+    Ok(password.into_iter().collect())
+}
+
+fn invalid(message: String) -> VaultError {
+    VaultError::InvalidInput(message)
+}
+
+/// A passphrase of words from the EFF large wordlist, with its entropy.
+pub fn generate_passphrase(options: &PassphraseOptions) -> Result<Passphrase, VaultError> {
+    let words = eff_large_wordlist().ok_or_else(|| {
+        VaultError::Unsupported("This build has no passphrase wordlist".to_string())
+    })?;
+    passphrase_from(words, options)
+}
+
+fn eff_large_wordlist() -> Option<&'static [&'static str]> {
+    static WORDS: OnceLock<Option<Vec<&'static str>>> = OnceLock::new();
+    WORDS
+        .get_or_init(|| parse_wordlist(EFF_LARGE_WORDLIST))
+        .as_deref()
+}
+
+/// The words of a list in the EFF's format, one `<dice>\t<word>` per line,
+/// ordered by their dice. `None` unless the list numbers every roll of five
+/// dice exactly once and has no word twice, which the entropy relies on.
+/// Lines starting with `#` are ignored.
+fn parse_wordlist(text: &str) -> Option<Vec<&str>> {
+    let mut words = vec![""; WORDLIST_SIZE];
+    let mut seen = HashSet::with_capacity(WORDLIST_SIZE);
+
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (dice, word) = line.split_once(char::is_whitespace)?;
+        let index = dice_index(dice)?;
+        let word = word.trim();
+        if word.is_empty() || !words[index].is_empty() || !seen.insert(word) {
+            return None;
+        }
+        words[index] = word;
+    }
+
+    (seen.len() == WORDLIST_SIZE).then_some(words)
+}
+
+/// The position of a roll such as `16245` in a list ordered by rolls.
+fn dice_index(dice: &str) -> Option<usize> {
+    if dice.len() != 5 {
+        return None;
+    }
+    dice.bytes().try_fold(0, |index, die| match die {
+        b'1'..=b'6' => Some(index * 6 + (die - b'1') as usize),
+        _ => None,
+    })
+}
+
+fn passphrase_from(words: &[&str], options: &PassphraseOptions) -> Result<Passphrase, VaultError> {
+    if !(MIN_PASSPHRASE_WORDS..=MAX_PASSPHRASE_WORDS).contains(&options.words) {
+        return Err(VaultError::InvalidInput(format!(
+            "A passphrase needs {} to {} words",
+            MIN_PASSPHRASE_WORDS, MAX_PASSPHRASE_WORDS
+        )));
+    }
+
+    let mut rng = OsRng.unwrap_err();
+    let mut picked: Vec<String> = (0..options.words)
+        .map(|_| {
+            let word = words[rng.random_range(0..words.len())];
+            match options.case {
+                WordCase::Lowercase => word.to_string(),
+                WordCase::Capitalized => {
+                    let mut chars = word.chars();
+                    chars
+                        .next()
+                        .map(|first| first.to_uppercase().chain(chars).collect())
+                        .unwrap_or_default()
+                }
+                WordCase::Uppercase => word.to_uppercase(),
+            }
+        })
+        .collect();
+    let mut entropy = options.words as f64 * (words.len() as f64).log2();
+
+    if options.include_digit {
+        let word = rng.random_range(0..picked.len());
+        let digit = rng.random_range(0..10);
+        picked[word].push(char::from_digit(digit, 10).unwrap_or('0'));
+        entropy += (10.0 * picked.len() as f64).log2();
+    }
+
+    Ok(Passphrase {
+        passphrase: picked.join(&options.separator),
+        entropy,
+    })
+}
+
+pub fn check_strength(password: &str) -> StrengthScore {
+    let length = password.len();
+    let has_lowercase = password.chars().any(|c| c.is_lowercase());
+    let has_uppercase = password.chars().any(|c| c.is_uppercase());
+    let has_numbers = password.chars().any(|c| c.is_numeric());
+    let has_symbols = password.chars().any(|c| !c.is_alphanumeric());
+
+    let mut charset_size = 0;
+    if has_lowercase {
+        charset_size += 26;
+    }
+    if has_uppercase {
+        charset_size += 26;
+    }
+    if has_numbers {
+        charset_size += 10;
+    }
+    if has_symbols {
+        charset_size += 32;
+    }
+
+    let entropy = (length as f64) * (charset_size as f64).log2();
+
+    let score = if entropy < 28.0 {
+        0
+    } else if entropy < 36.0 {
+        1
+    } else if entropy < 60.0 {
+        2
+    } else if entropy < 128.0 {
+        3
+    } else {
+        4
+    };
+
+    let mut feedback = Vec::new();
+    if length < 8 {
+        feedback.push("Use at least 8 characters".to_string());
+    }
+    if !has_uppercase {
+        feedback.push("Add uppercase letters".to_string());
+    }
+    if !has_lowercase {
+        feedback.push("Add lowercase letters".to_string());
+    }
+    if !has_numbers {
+        feedback.push("Add numbers".to_string());
+    }
+    if !has_symbols {
+        feedback.push("Add symbols".to_string());
+    }
+    if length < 12 && feedback.is_empty() {
+        feedback.push("Consider using 12+ characters for better security".to_string());
+    }
+
+    StrengthScore {
+        score,
+        entropy,
+        feedback,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn letters_and(options: PasswordOptions) -> PasswordOptions {
+        PasswordOptions {
+            include_uppercase: false,
+            include_numbers: false,
+            include_symbols: false,
+            exclude_ambiguous: false,
+            ..options
+        }
+    }
+
+    /// The chi-squared statistic of `counts` against a uniform distribution.
+    fn chi_squared(counts: &[usize]) -> f64 {
+        let total: usize = counts.iter().sum();
+        let expected = total as f64 / counts.len() as f64;
+        counts
+            .iter()
+            .map(|&count| (count as f64 - expected).powi(2) / expected)
+            .sum()
+    }
+
+    #[test]
+    fn test_characters_are_uniform() {
+        let options = letters_and(PasswordOptions {
+            length: 26,
+            ..PasswordOptions::default()
+        });
+        let mut counts = [0; 26];
+        for _ in 0..2000 {
+            for c in generate_password(&options).unwrap().bytes() {
+                counts[(c - b'a') as usize] += 1;
+            }
+        }
+        // Exceeded by chance once in a million runs, for 25 degrees of
+        // freedom.
+        let statistic = chi_squared(&counts);
+        assert!(statistic < 73.9, "chi-squared {}: {:?}", statistic, counts);
+    }
+
+    #[test]
+    fn test_required_characters_are_shuffled() {
+        // A guaranteed digit favouring some position would show up as more
+        // digits there.
+        let options = PasswordOptions {
+            length: 8,
+            include_numbers: true,
+            ..letters_and(PasswordOptions::default())
+        };
+        let mut digits_at = [0; 8];
+        for _ in 0..5000 {
+            let password = generate_password(&options).unwrap();
+            for (i, c) in password.chars().enumerate() {
+                if c.is_ascii_digit() {
+                    digits_at[i] += 1;
+                }
+            }
+        }
+        // Once in a million runs for 7 degrees of freedom.
+        let statistic = chi_squared(&digits_at);
+        assert!(
+            statistic < 40.5,
+            "chi-squared {}: {:?}",
+            statistic,
+            digits_at
+        );
+    }
+
+    #[test]
+    fn test_minimums_are_met() {
+        let count =
+            |password: &str, class: &str| password.chars().filter(|&c| class.contains(c)).count();
+
+        // Each included class, even with 20 characters to share.
+        for _ in 0..1000 {
+            let password = generate_password(&PasswordOptions::default()).unwrap();
+            assert_eq!(password.chars().count(), 20);
+            for class in [LOWER, UPPER, DIGIT, SYMBOLS] {
+                assert!(count(&password, class) >= 1, "{}", password);
+            }
+        }
+
+        let options = PasswordOptions {
+            length: 10,
+            min_numbers: 4,
+            min_symbols: 3,
+            ..PasswordOptions::default()
+        };
+        for _ in 0..500 {
+            let password = generate_password(&options).unwrap();
+            assert!(count(&password, DIGIT) >= 4, "{}", password);
+            assert!(count(&password, SYMBOLS) >= 3, "{}", password);
+            assert!(count(&password, LOWER) >= 1, "{}", password);
+            assert!(count(&password, UPPER) >= 1, "{}", password);
+        }
+
+        for options in [
+            PasswordOptions {
+                min_numbers: 1,
+                include_numbers: false,
+                ..PasswordOptions::default()
+            },
+            PasswordOptions {
+                length: 6,
+                min_symbols: 4,
+                ..PasswordOptions::default()
+            },
+            PasswordOptions {
+                exclude_chars: DIGIT.to_string(),
+                ..PasswordOptions::default()
+            },
+            letters_and(PasswordOptions {
+                include_lowercase: false,
+                ..PasswordOptions::default()
+            }),
+        ] {
+            assert!(matches!(
+                generate_password(&options),
+                Err(VaultError::InvalidInput(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_include_and_exclude() {
+        let options = PasswordOptions {
+            exclude_chars: "!@#$%^&*".to_string(),
+            include_chars: "é€".to_string(),
+            ..PasswordOptions::default()
+        };
+        let mut seen = HashSet::new();
+        for _ in 0..500 {
+            seen.extend(generate_password(&options).unwrap().chars());
+        }
+        assert!(seen.contains(&'é') && seen.contains(&'€'));
+        assert!(seen.contains(&'('));
+        assert!(!seen.iter().any(|&c| options.exclude_chars.contains(c)));
+        assert!(!seen.iter().any(|&c| AMBIGUOUS.contains(c)));
+
+        // Extra characters alone are enough.
+        let options = letters_and(PasswordOptions {
+            include_lowercase: false,
+            include_chars: "xy".to_string(),
+            length: 12,
+            ..PasswordOptions::default()
+        });
+        let password = generate_password(&options).unwrap();
+        assert!(password.chars().all(|c| c == 'x' || c == 'y'));
+    }
+
+    #[test]
+    fn test_max_consecutive() {
+        let digits = |max_consecutive| PasswordOptions {
+            length: 100,
+            include_lowercase: false,
+            include_uppercase: false,
+            include_symbols: false,
+            exclude_ambiguous: false,
+            max_consecutive: Some(max_consecutive),
+            ..PasswordOptions::default()
+        };
+        for _ in 0..200 {
+            let password: Vec<char> = generate_password(&digits(1)).unwrap().chars().collect();
+            assert!(password.windows(2).all(|w| w[0] != w[1]));
+            let password: Vec<char> = generate_password(&digits(2)).unwrap().chars().collect();
+            assert!(password.windows(3).all(|w| w[0] != w[1] || w[1] != w[2]));
+        }
+
+        let one = letters_and(PasswordOptions {
+            include_lowercase: false,
+            include_chars: "a".to_string(),
+            length: 2,
+            max_consecutive: Some(1),
+            ..PasswordOptions::default()
+        });
+        assert!(matches!(
+            generate_password(&one),
+            Err(VaultError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_rules() {
+        let options = PasswordOptions {
+            rules: Some(
+                "minlength: 8; maxlength: 12; required: digit; required: [-!]; \
+                 allowed: lower; max-consecutive: 1"
+                    .to_string(),
+            ),
+            ..PasswordOptions::default()
+        };
+        for _ in 0..300 {
+            let password = generate_password(&options).unwrap();
+            let chars: Vec<char> = password.chars().collect();
+            assert_eq!(chars.len(), 12);
+            assert!(chars.iter().any(|c| c.is_ascii_digit()), "{}", password);
+            assert!(chars.iter().any(|&c| c == '-' || c == '!'), "{}", password);
+            assert!(chars
+                .iter()
+                .all(|&c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '!'));
+            assert!(!chars.iter().any(|&c| AMBIGUOUS.contains(c)));
+            assert!(chars.windows(2).all(|w| w[0] != w[1]));
+        }
+
+        let short = PasswordOptions {
+            length: 4,
+            ..options.clone()
+        };
+        assert_eq!(generate_password(&short).unwrap().chars().count(), 8);
+
+        let bad = PasswordOptions {
+            rules: Some("required: emoji".to_string()),
+            ..options
+        };
+        assert!(matches!(
+            generate_password(&bad),
+            Err(VaultError::InvalidInput(_))
+        ));
+    }
+
+    /// A list in the EFF's format with a made-up word for every roll: `w`
+    /// and the dice as letters, so `11112` is `waaaab`.
+    fn eff_format_list() -> String {
+        let mut text = String::new();
+        for index in 0..WORDLIST_SIZE {
+            let mut dice = [0u8; 5];
+            let mut rest = index;
+            for die in dice.iter_mut().rev() {
+                *die = (rest % 6) as u8;
+                rest /= 6;
+            }
+            let rolls: String = dice.iter().map(|d| char::from(b'1' + d)).collect();
+            let letters: String = dice.iter().map(|d| char::from(b'a' + d)).collect();
+            text.push_str(&format!("{}\tw{}\n", rolls, letters));
+        }
+        text
+    }
+
+    #[test]
+    fn test_parse_wordlist() {
+        let text = eff_format_list();
+        let words = parse_wordlist(&text).unwrap();
+        assert_eq!(words.len(), WORDLIST_SIZE);
+        assert_eq!(words[0], "waaaaa");
+        assert_eq!(words[7], "waaabb");
+        assert_eq!(words[WORDLIST_SIZE - 1], "wfffff");
+        assert_eq!(dice_index("11111"), Some(0));
+        assert_eq!(dice_index("11121"), Some(6));
+        assert_eq!(dice_index("66666"), Some(WORDLIST_SIZE - 1));
+        assert_eq!(dice_index("11117"), None);
+        assert_eq!(dice_index("1111"), None);
+
+        // Missing, repeated or misnumbered words make the list unusable.
+        let missing = text.replacen("11111\twaaaaa\n", "", 1);
+        assert!(parse_wordlist(&missing).is_none());
+        let repeated = text.replacen("11111\twaaaaa", "11111\twaaaab", 1);
+        assert!(parse_wordlist(&repeated).is_none());
+        let misnumbered = text.replacen("11111\twaaaaa", "11110\twaaaaa", 1);
+        assert!(parse_wordlist(&misnumbered).is_none());
+        assert!(parse_wordlist("# comments only\n").is_none());
+    }
+
+    #[test]
+    fn test_bundled_wordlist() {
+        let words = eff_large_wordlist().expect("the EFF large wordlist is bundled");
+        assert_eq!(words.len(), WORDLIST_SIZE);
+        assert_eq!(words.iter().collect::<HashSet<_>>().len(), WORDLIST_SIZE);
+        assert_eq!(words[0], "abacus");
+        assert_eq!(words[WORDLIST_SIZE - 1], "zoom");
+
+        // log2(7776) is about 12.925 bits a word.
+        let passphrase = generate_passphrase(&PassphraseOptions::default()).unwrap();
+        assert!((passphrase.entropy / 6.0 - 12.925).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_passphrase() {
+        let words = eff_large_wordlist().unwrap();
+        // Some of the words have hyphens in them.
+        let spaced = PassphraseOptions {
+            separator: " ".to_string(),
+            ..PassphraseOptions::default()
+        };
+
+        let passphrase = generate_passphrase(&spaced).unwrap();
+        let parts: Vec<&str> = passphrase.passphrase.split(' ').collect();
+        assert_eq!(parts.len(), 6);
+        assert!(parts.iter().all(|part| words.contains(part)));
+        assert!((passphrase.entropy - 6.0 * 12.925).abs() < 0.01);
+
+        let options = PassphraseOptions {
+            words: 4,
+            case: WordCase::Capitalized,
+            include_digit: true,
+            ..spaced.clone()
+        };
+        let passphrase = generate_passphrase(&options).unwrap();
+        let parts: Vec<&str> = passphrase.passphrase.split(' ').collect();
+        assert_eq!(parts.len(), 4);
+        assert!(parts.iter().all(|part| {
+            let word = part.trim_end_matches(|c: char| c.is_ascii_digit());
+            word.starts_with(|c: char| c.is_ascii_uppercase())
+                && words.contains(&word.to_lowercase().as_str())
+        }));
+        let with_digit = parts
+            .iter()
+            .filter(|part| part.ends_with(|c: char| c.is_ascii_digit()));
+        assert_eq!(with_digit.count(), 1);
+        assert!((passphrase.entropy - (4.0 * 12.925 + 40f64.log2())).abs() < 0.01);
+
+        let upper = PassphraseOptions {
+            case: WordCase::Uppercase,
+            ..PassphraseOptions::default()
+        };
+        let passphrase = generate_passphrase(&upper).unwrap();
+        assert_eq!(passphrase.passphrase, passphrase.passphrase.to_uppercase());
+
+        for words_count in [2, 21] {
+            let options = PassphraseOptions {
+                words: words_count,
+                ..PassphraseOptions::default()
+            };
+            assert!(matches!(
+                generate_passphrase(&options),
+                Err(VaultError::InvalidInput(_))
+            ));
+        }
+    }
+}
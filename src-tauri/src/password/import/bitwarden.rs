@@ -0,0 +1,460 @@
+//! Bitwarden's unencrypted JSON export. The types mirror Bitwarden's own
+//! field names so that the same shapes can be written back out.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use super::Draft;
+use crate::password::error::VaultError;
+use crate::password::fields;
+use crate::password::types::{EntryKind, FieldKind, PasswordEntry, UrlMatch};
+
+pub const ITEM_TYPE_LOGIN: u8 = 1;
+pub const ITEM_TYPE_SECURE_NOTE: u8 = 2;
+pub const ITEM_TYPE_CARD: u8 = 3;
+pub const ITEM_TYPE_IDENTITY: u8 = 4;
+pub const ITEM_TYPE_SSH_KEY: u8 = 5;
+
+pub const FIELD_TYPE_TEXT: u8 = 0;
+pub const FIELD_TYPE_HIDDEN: u8 = 1;
+
+/// `LoginUri::match_type` values other than 0, the base domain. `None` is
+/// the account's default, which is the base domain unless changed.
+const MATCH_HOST: u8 = 1;
+const MATCH_STARTS_WITH: u8 = 2;
+const MATCH_EXACT: u8 = 3;
+const MATCH_REGEX: u8 = 4;
+const MATCH_NEVER: u8 = 5;
+
+/// Card, identity and SSH key properties, and the fields they become.
+pub const CARD_FIELDS: &[(&str, &str, FieldKind)] = &[
+    ("cardholderName", fields::CARDHOLDER, FieldKind::Text),
+    ("number", fields::CARD_NUMBER, FieldKind::Hidden),
+    ("code", fields::SECURITY_CODE, FieldKind::Hidden),
+    ("brand", "Brand", FieldKind::Text),
+];
+pub const IDENTITY_FIELDS: &[(&str, &str, FieldKind)] = &[
+    ("email", fields::EMAIL, FieldKind::Text),
+    ("phone", fields::PHONE, FieldKind::Text),
+    ("company", "Company", FieldKind::Text),
+    ("ssn", "Social security number", FieldKind::Hidden),
+    ("passportNumber", "Passport number", FieldKind::Hidden),
+    ("licenseNumber", "License number", FieldKind::Hidden),
+];
+pub const SSH_KEY_FIELDS: &[(&str, &str, FieldKind)] = &[
+    ("privateKey", fields::PRIVATE_KEY, FieldKind::Hidden),
+    ("publicKey", fields::PUBLIC_KEY, FieldKind::Text),
+    ("keyFingerprint", "Fingerprint", FieldKind::Text),
+];
+
+/// Identity properties joined into the full name and the address.
+const IDENTITY_NAME: [&str; 4] = ["title", "firstName", "middleName", "lastName"];
+const IDENTITY_ADDRESS: [&str; 7] = [
+    "address1",
+    "address2",
+    "address3",
+    "city",
+    "state",
+    "postalCode",
+    "country",
+];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BitwardenExport {
+    #[serde(default)]
+    pub encrypted: bool,
+    #[serde(default)]
+    pub folders: Vec<Folder>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub collections: Vec<Folder>,
+    #[serde(default)]
+    pub items: Vec<Item>,
+}
+
+/// A folder, or an organization collection, which has the same shape as far
+/// as zims is concerned.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Folder {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Item {
+    pub id: Option<String>,
+    pub organization_id: Option<String>,
+    pub folder_id: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: u8,
+    #[serde(default)]
+    pub reprompt: u8,
+    #[serde(default)]
+    pub name: String,
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub favorite: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub login: Option<Login>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secure_note: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub card: Option<HashMap<String, Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<HashMap<String, Value>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_key: Option<HashMap<String, Value>>,
+    pub fields: Option<Vec<Field>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_history: Option<Vec<PasswordHistory>>,
+    pub collection_ids: Option<Vec<String>>,
+    pub creation_date: Option<DateTime<Utc>>,
+    pub revision_date: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Login {
+    pub uris: Option<Vec<LoginUri>>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub totp: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoginUri {
+    #[serde(rename = "match")]
+    pub match_type: Option<u8>,
+    pub uri: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Field {
+    pub name: Option<String>,
+    pub value: Option<String>,
+    #[serde(rename = "type", default)]
+    pub kind: u8,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordHistory {
+    pub last_used_date: Option<DateTime<Utc>>,
+    pub password: Option<String>,
+}
+
+/// Folders and collections become tags. Cards, identities and SSH keys
+/// keep their kind, with their details as fields.
+pub fn parse(data: &[u8]) -> Result<Vec<PasswordEntry>, VaultError> {
+    let export: BitwardenExport = serde_json::from_slice(data)?;
+    if export.encrypted {
+        return Err(VaultError::Unsupported(
+            "Encrypted Bitwarden exports cannot be imported; export as unencrypted JSON"
+                .to_string(),
+        ));
+    }
+
+    let folders: HashMap<&str, &str> = export
+        .folders
+        .iter()
+        .chain(&export.collections)
+        .map(|f| (f.id.as_str(), f.name.as_str()))
+        .collect();
+
+    let mut entries = Vec::new();
+    for item in export.items {
+        let mut draft = Draft {
+            kind: match item.kind {
+                ITEM_TYPE_SECURE_NOTE => EntryKind::SecureNote,
+                ITEM_TYPE_CARD => EntryKind::CreditCard,
+                ITEM_TYPE_IDENTITY => EntryKind::Identity,
+                ITEM_TYPE_SSH_KEY => EntryKind::SshKey,
+                _ => EntryKind::Login,
+            },
+            title: item.name,
+            notes: item.notes,
+            favorite: item.favorite,
+            created_at: item.creation_date,
+            updated_at: item.revision_date,
+            ..Draft::default()
+        };
+
+        let groups = item
+            .folder_id
+            .iter()
+            .chain(item.collection_ids.iter().flatten());
+        for id in groups {
+            if let Some(name) = folders.get(id.as_str()) {
+                draft.add_tag(name);
+            }
+        }
+
+        if let Some(login) = item.login {
+            draft.username = login.username.unwrap_or_default();
+            draft.password = login.password.unwrap_or_default().into();
+            // zims has one match mode per entry; the first URI's is taken.
+            let uris = login.uris.unwrap_or_default();
+            if let Some(first) = uris.first() {
+                draft.url_match = url_match(first.match_type);
+            }
+            for uri in uris.into_iter().filter_map(|u| u.uri) {
+                draft.add_url(&uri);
+            }
+            if let Some(totp) = login.totp {
+                draft.set_totp(&totp);
+            }
+        }
+
+        if let Some(card) = &item.card {
+            if let (Some(month), Some(year)) = (
+                text(card, "expMonth").and_then(|m| m.parse::<u32>().ok()),
+                text(card, "expYear"),
+            ) {
+                let expiry = format!("{}-{:02}", year, month);
+                draft.add_field(fields::EXPIRY, FieldKind::Date, &expiry);
+            }
+            read_details(&mut draft, card, CARD_FIELDS, &["expMonth", "expYear"]);
+        }
+
+        if let Some(identity) = &item.identity {
+            let join = |keys: &[&str], separator| {
+                let parts: Vec<&str> = keys.iter().filter_map(|key| text(identity, key)).collect();
+                parts.join(separator)
+            };
+            draft.add_field(
+                fields::FULL_NAME,
+                FieldKind::Text,
+                &join(&IDENTITY_NAME, " "),
+            );
+            draft.add_field(
+                fields::ADDRESS,
+                FieldKind::Text,
+                &join(&IDENTITY_ADDRESS, ", "),
+            );
+            if let Some(username) = text(identity, "username") {
+                draft.username = username.to_string();
+            }
+
+            let mut handled = vec!["username"];
+            handled.extend(IDENTITY_NAME);
+            handled.extend(IDENTITY_ADDRESS);
+            read_details(&mut draft, identity, IDENTITY_FIELDS, &handled);
+        }
+
+        if let Some(key) = &item.ssh_key {
+            read_details(&mut draft, key, SSH_KEY_FIELDS, &[]);
+        }
+
+        for field in item.fields.into_iter().flatten() {
+            let kind = match field.kind {
+                FIELD_TYPE_HIDDEN => FieldKind::Hidden,
+                _ => FieldKind::Text,
+            };
+            draft.add_field(
+                field.name.as_deref().unwrap_or("Field"),
+                kind,
+                field.value.as_deref().unwrap_or_default(),
+            );
+        }
+
+        for previous in item.password_history.into_iter().flatten() {
+            draft.add_history(
+                previous.password.as_deref().unwrap_or_default(),
+                previous.last_used_date,
+            );
+        }
+
+        if !draft.is_empty() {
+            entries.push(draft.into_entry());
+        }
+    }
+
+    Ok(entries)
+}
+
+/// A non-empty string property.
+fn text<'a>(details: &'a HashMap<String, Value>, key: &str) -> Option<&'a str> {
+    details
+        .get(key)
+        .and_then(Value::as_str)
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+/// Adds the properties in `known` as fields. Those neither known nor
+/// `handled` go into the notes.
+fn read_details(
+    draft: &mut Draft,
+    details: &HashMap<String, Value>,
+    known: &[(&str, &str, FieldKind)],
+    handled: &[&str],
+) {
+    let mut keys: Vec<&String> = details.keys().collect();
+    keys.sort();
+    for key in keys {
+        let Some(value) = text(details, key) else {
+            continue;
+        };
+        match known.iter().find(|(name, ..)| name == key) {
+            Some((_, field, kind)) => draft.add_field(field, *kind, value),
+            None if !handled.contains(&key.as_str()) => draft.add_extra(key, value),
+            None => {}
+        }
+    }
+}
+
+/// The match mode for a URI's `match` value. zims has no exact match, so
+/// that becomes the nearest it has.
+fn url_match(match_type: Option<u8>) -> UrlMatch {
+    match match_type {
+        Some(MATCH_HOST) => UrlMatch::Host,
+        Some(MATCH_STARTS_WITH | MATCH_EXACT) => UrlMatch::StartsWith,
+        Some(MATCH_REGEX) => UrlMatch::Regex,
+        Some(MATCH_NEVER) => UrlMatch::Never,
+        _ => UrlMatch::BaseDomain,
+    }
+}
+
+/// The `match` value for an entry's URLs; the base domain is left to the
+/// account's default, as Bitwarden itself does.
+pub fn match_type(url_match: UrlMatch) -> Option<u8> {
+    match url_match {
+        UrlMatch::BaseDomain => None,
+        UrlMatch::Host => Some(MATCH_HOST),
+        UrlMatch::StartsWith => Some(MATCH_STARTS_WITH),
+        UrlMatch::Regex => Some(MATCH_REGEX),
+        UrlMatch::Never => Some(MATCH_NEVER),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORT: &str = r#"{
+        "encrypted": false,
+        "folders": [{ "id": "f1", "name": "Work" }],
+        "items": [
+            {
+                "id": "i1",
+                "organizationId": null,
+                "folderId": "f1",
+                "type": 1,
+                "reprompt": 0,
+                "name": "GitHub",
+                "notes": "2FA on phone",
+                "favorite": true,
+                "login": {
+                    "uris": [
+                        { "match": 1, "uri": "https://github.com/login" },
+                        { "match": null, "uri": "https://gist.github.com" }
+                    ],
+                    "username": "alice",
+                    "password": "hunter2",
+                    "totp": null
+                },
+                "fields": [{ "name": "Recovery", "value": "abcd-efgh", "type": 1 }],
+                "passwordHistory": [
+                    { "lastUsedDate": "2022-05-01T00:00:00.000Z", "password": "hunter1" },
+                    { "lastUsedDate": "2023-01-01T00:00:00.000Z", "password": "hunter1b" }
+                ],
+                "collectionIds": null,
+                "creationDate": "2021-03-01T10:00:00.000Z",
+                "revisionDate": "2023-06-15T08:30:00.000Z"
+            },
+            {
+                "id": "i2",
+                "folderId": null,
+                "type": 3,
+                "name": "Visa",
+                "notes": null,
+                "card": {
+                    "cardholderName": "Alice",
+                    "number": "4111111111111111",
+                    "expMonth": "4",
+                    "expYear": "2029",
+                    "code": null
+                },
+                "fields": null,
+                "collectionIds": null,
+                "creationDate": "2022-01-01T00:00:00.000Z",
+                "revisionDate": "2022-01-01T00:00:00.000Z"
+            },
+            {
+                "id": "i3",
+                "type": 4,
+                "name": "Me",
+                "identity": {
+                    "title": "Dr",
+                    "firstName": "Alice",
+                    "lastName": "Smith",
+                    "address1": "1 Main St",
+                    "city": "Springfield",
+                    "email": "alice@example.com",
+                    "passportNumber": "X123",
+                    "username": "asmith",
+                    "nickname": "Al"
+                },
+                "fields": null,
+                "collectionIds": null,
+                "creationDate": "2022-01-01T00:00:00.000Z",
+                "revisionDate": "2022-01-01T00:00:00.000Z"
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn test_parse_bitwarden() {
+        let entries = parse(EXPORT.as_bytes()).unwrap();
+        assert_eq!(entries.len(), 3);
+
+        let github = &entries[0];
+        assert_eq!(github.title, "GitHub");
+        assert_eq!(github.username, "alice");
+        assert_eq!(github.password.as_str(), "hunter2");
+        assert_eq!(github.url.as_deref(), Some("https://github.com/login"));
+        assert_eq!(github.tags, vec!["Work"]);
+        assert!(github.favorite);
+        assert_eq!(github.kind, EntryKind::Login);
+        assert_eq!(github.urls, vec!["https://gist.github.com"]);
+        assert_eq!(github.url_match, UrlMatch::Host);
+        assert_eq!(github.notes.as_deref(), Some("2FA on phone"));
+        let recovery = github.field("Recovery").unwrap();
+        assert_eq!(recovery.kind, FieldKind::Hidden);
+        assert_eq!(recovery.value.as_str(), "abcd-efgh");
+        assert_eq!(github.created_at.to_rfc3339(), "2021-03-01T10:00:00+00:00");
+        assert_eq!(github.updated_at.to_rfc3339(), "2023-06-15T08:30:00+00:00");
+        let history: Vec<&str> = github.history.iter().map(|h| h.password.as_str()).collect();
+        assert_eq!(history, vec!["hunter1b", "hunter1"]);
+
+        let card = &entries[1];
+        assert_eq!(card.kind, EntryKind::CreditCard);
+        assert_eq!(card.notes, None);
+        let value =
+            |entry: &PasswordEntry, name| entry.field(name).unwrap().value.as_str().to_string();
+        assert_eq!(value(card, fields::CARDHOLDER), "Alice");
+        assert_eq!(value(card, fields::CARD_NUMBER), "4111111111111111");
+        assert_eq!(value(card, fields::EXPIRY), "2029-04");
+        assert_eq!(card.fields.len(), 3);
+
+        let identity = &entries[2];
+        assert_eq!(identity.kind, EntryKind::Identity);
+        assert_eq!(identity.username, "asmith");
+        assert_eq!(value(identity, fields::FULL_NAME), "Dr Alice Smith");
+        assert_eq!(value(identity, fields::ADDRESS), "1 Main St, Springfield");
+        assert_eq!(value(identity, fields::EMAIL), "alice@example.com");
+        assert_eq!(
+            identity.field("Passport number").unwrap().kind,
+            FieldKind::Hidden
+        );
+        assert_eq!(identity.notes.as_deref(), Some("nickname: Al"));
+    }
+
+    #[test]
+    fn test_encrypted_export_is_refused() {
+        let data = br#"{ "encrypted": true, "passwordProtected": true, "data": "..." }"#;
+        assert!(matches!(parse(data), Err(VaultError::Unsupported(_))));
+    }
+}
@@ -0,0 +1,162 @@
+//! CSV exports from 1Password, Chrome and Firefox (and Bitwarden's CSV,
+//! which happens to fit). Columns are recognised by header name, so the
+//! exact column order and any extra columns do not matter.
+
+use super::{from_unix_millis, Draft};
+use crate::password::error::VaultError;
+use crate::password::types::{EntryKind, FieldKind, PasswordEntry};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Column {
+    Title,
+    Url,
+    Username,
+    Password,
+    Notes,
+    Tags,
+    Favorite,
+    Totp,
+    /// Bitwarden's item type, `login` or `note`.
+    Kind,
+    /// Bitwarden's custom fields, one `name: value` per line.
+    Fields,
+    /// Firefox, in milliseconds since the epoch.
+    CreatedMillis,
+    UpdatedMillis,
+    Ignored,
+}
+
+fn column(header: &str) -> Column {
+    match header.trim().to_lowercase().as_str() {
+        "title" | "name" => Column::Title,
+        "url" | "website" | "login_uri" => Column::Url,
+        "username" | "login_username" => Column::Username,
+        "password" | "login_password" => Column::Password,
+        "notes" | "note" => Column::Notes,
+        "tags" | "folder" => Column::Tags,
+        "favorite" => Column::Favorite,
+        "otpauth" | "login_totp" => Column::Totp,
+        "type" => Column::Kind,
+        "fields" => Column::Fields,
+        "timecreated" => Column::CreatedMillis,
+        "timepasswordchanged" => Column::UpdatedMillis,
+        _ => Column::Ignored,
+    }
+}
+
+pub fn parse(data: &[u8]) -> Result<Vec<PasswordEntry>, VaultError> {
+    let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+    let mut reader = ::csv::ReaderBuilder::new().flexible(true).from_reader(data);
+
+    let columns: Vec<Column> = reader
+        .headers()
+        .map_err(csv_error)?
+        .iter()
+        .map(column)
+        .collect();
+    if !columns.contains(&Column::Password) {
+        return Err(VaultError::InvalidInput(
+            "Not a password export: the CSV has no password column".to_string(),
+        ));
+    }
+
+    let mut entries = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(csv_error)?;
+        let mut draft = Draft::default();
+
+        for (column, value) in columns.iter().zip(record.iter()) {
+            match column {
+                Column::Title => draft.title = value.to_string(),
+                Column::Url => draft.url = Some(value.to_string()),
+                Column::Username => draft.username = value.to_string(),
+                Column::Password => draft.password = value.to_string().into(),
+                Column::Notes => draft.notes = Some(value.to_string()),
+                Column::Tags => value.split([',', ';']).for_each(|tag| draft.add_tag(tag)),
+                Column::Favorite => {
+                    draft.favorite = matches!(value.trim().to_lowercase().as_str(), "1" | "true")
+                }
+                Column::Totp => draft.set_totp(value),
+                Column::Kind => {
+                    if value.trim().eq_ignore_ascii_case("note") {
+                        draft.kind = EntryKind::SecureNote;
+                    }
+                }
+                Column::Fields => {
+                    for line in value.lines() {
+                        match line.split_once(": ") {
+                            Some((name, value)) => draft.add_field(name, FieldKind::Text, value),
+                            None => draft.add_extra("Field", line),
+                        }
+                    }
+                }
+                Column::CreatedMillis => draft.created_at = millis(value),
+                Column::UpdatedMillis => draft.updated_at = millis(value),
+                Column::Ignored => {}
+            }
+        }
+
+        if !draft.is_empty() {
+            entries.push(draft.into_entry());
+        }
+    }
+
+    Ok(entries)
+}
+
+fn millis(value: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    value.trim().parse().ok().and_then(from_unix_millis)
+}
+
+fn csv_error(e: ::csv::Error) -> VaultError {
+    VaultError::InvalidInput(format!("Malformed CSV: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_chrome() {
+        let data = "name,url,username,password,note\n\
+                    github.com,https://github.com/login,alice,\"p,w\"\"1\",\n\
+                    ,,,,\n";
+        let entries = parse(data.as_bytes()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].title, "github.com");
+        assert_eq!(entries[0].password.as_str(), "p,w\"1");
+        assert_eq!(entries[0].notes, None);
+    }
+
+    #[test]
+    fn test_parse_firefox() {
+        let data = "\u{feff}\"url\",\"username\",\"password\",\"httpRealm\",\"formActionOrigin\",\"guid\",\"timeCreated\",\"timeLastUsed\",\"timePasswordChanged\"\n\
+                    \"https://www.example.com\",\"bob\",\"s3cret\",,\"https://www.example.com\",\"{abc}\",\"1600000000000\",\"1700000000000\",\"1650000000000\"\n";
+        let entries = parse(data.as_bytes()).unwrap();
+        let entry = &entries[0];
+        assert_eq!(entry.title, "example.com");
+        assert_eq!(entry.username, "bob");
+        assert_eq!(entry.created_at.timestamp(), 1_600_000_000);
+        assert_eq!(entry.updated_at.timestamp(), 1_650_000_000);
+    }
+
+    #[test]
+    fn test_parse_1password() {
+        let data = "Title,Url,Username,Password,OTPAuth,Favorite,Archived,Tags,Notes\n\
+                    Bank,https://bank.example,alice,pw,,true,false,\"finance,personal\",Branch 12\n";
+        let entries = parse(data.as_bytes()).unwrap();
+        let entry = &entries[0];
+        assert_eq!(entry.title, "Bank");
+        assert!(entry.favorite);
+        assert_eq!(entry.tags, vec!["finance", "personal"]);
+        assert_eq!(entry.notes.as_deref(), Some("Branch 12"));
+    }
+
+    #[test]
+    fn test_rejects_other_csv() {
+        assert!(matches!(
+            parse(b"a,b,c\n1,2,3\n"),
+            Err(VaultError::InvalidInput(_))
+        ));
+    }
+}
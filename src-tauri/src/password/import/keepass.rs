@@ -0,0 +1,326 @@
+//! KeePass 2 XML, as written by "Export > KeePass XML (2.x)" and found
+//! inside KDBX files.
+
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use roxmltree::{Document, Node};
+
+use super::Draft;
+use crate::password::error::VaultError;
+use crate::password::types::{FieldKind, PasswordEntry};
+
+/// Seconds from 0001-01-01, KDBX 4's epoch, to the Unix epoch.
+const KDBX_EPOCH_OFFSET: i64 = -62_135_596_800;
+
+/// The all-zero UUID KeePass writes when there is no recycle bin.
+pub(crate) const NULL_UUID: &str = "AAAAAAAAAAAAAAAAAAAAAA==";
+
+/// KeePassXC keeps an entry's further URLs in strings named `KP2A_URL`,
+/// `KP2A_URL_1`, `KP2A_URL_2` and so on.
+pub(crate) const EXTRA_URL: &str = "KP2A_URL";
+
+/// Groups become tags, as their path below the root group joined with `/`.
+/// Entries in the recycle bin are left out, and of an entry's history only
+/// the passwords it replaced are kept.
+pub fn parse_xml(data: &[u8]) -> Result<Vec<PasswordEntry>, VaultError> {
+    let text = std::str::from_utf8(data)
+        .map_err(|_| VaultError::InvalidInput("KeePass XML must be UTF-8".to_string()))?;
+    let doc = Document::parse(text)
+        .map_err(|e| VaultError::InvalidInput(format!("Malformed KeePass XML: {}", e)))?;
+
+    let file = doc.root_element();
+    if !file.has_tag_name("KeePassFile") {
+        return Err(VaultError::InvalidInput(
+            "Not a KeePass XML file".to_string(),
+        ));
+    }
+
+    let recycle_bin = child(file, "Meta")
+        .and_then(|meta| child_text(meta, "RecycleBinUUID"))
+        .filter(|uuid| *uuid != NULL_UUID);
+
+    let root_group = child(file, "Root")
+        .and_then(|root| child(root, "Group"))
+        .ok_or_else(|| VaultError::InvalidInput("KeePass XML has no root group".to_string()))?;
+
+    let mut entries = Vec::new();
+    walk_group(root_group, &mut Vec::new(), recycle_bin, &mut entries);
+    Ok(entries)
+}
+
+fn walk_group(
+    group: Node,
+    path: &mut Vec<String>,
+    recycle_bin: Option<&str>,
+    entries: &mut Vec<PasswordEntry>,
+) {
+    for node in group.children().filter(Node::is_element) {
+        match node.tag_name().name() {
+            "Entry" => {
+                let draft = read_entry(node, path);
+                if !draft.is_empty() {
+                    entries.push(draft.into_entry());
+                }
+            }
+            "Group" => {
+                if recycle_bin.is_some() && child_text(node, "UUID") == recycle_bin {
+                    continue;
+                }
+                path.push(child_text(node, "Name").unwrap_or_default().to_string());
+                walk_group(node, path, recycle_bin, entries);
+                path.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+fn read_entry(entry: Node, path: &[String]) -> Draft {
+    let mut draft = Draft::default();
+
+    if !path.is_empty() {
+        draft.add_tag(&path.join("/"));
+    }
+    if let Some(tags) = child_text(entry, "Tags") {
+        tags.split([',', ';']).for_each(|tag| draft.add_tag(tag));
+    }
+
+    if let Some(times) = child(entry, "Times") {
+        draft.created_at = child_text(times, "CreationTime").and_then(parse_time);
+        draft.updated_at = child_text(times, "LastModificationTime").and_then(parse_time);
+    }
+
+    for string in entry.children().filter(|n| n.has_tag_name("String")) {
+        let key = child_text(string, "Key").unwrap_or_default();
+        let value = child_text(string, "Value").unwrap_or_default();
+        let protected = child(string, "Value")
+            .and_then(|v| v.attribute("ProtectInMemory"))
+            .is_some_and(|p| p == "True");
+        match key {
+            "Title" => draft.title = value.to_string(),
+            "UserName" => draft.username = value.to_string(),
+            "Password" => draft.password = value.to_string().into(),
+            "URL" => draft.url = Some(value.to_string()),
+            "Notes" => draft.notes = Some(value.to_string()),
+            "otp" => draft.set_totp(value),
+            _ if is_extra_url(key) => draft.add_url(value),
+            _ if protected => draft.add_field(key, FieldKind::Hidden, value),
+            _ => draft.add_field(key, FieldKind::Text, value),
+        }
+    }
+
+    // Snapshots are taken before each edit, oldest first, so a snapshot's
+    // password was replaced when the next version was saved.
+    let snapshots: Vec<(&str, Option<DateTime<Utc>>)> = child(entry, "History")
+        .into_iter()
+        .flat_map(|history| history.children().filter(|n| n.has_tag_name("Entry")))
+        .map(|snapshot| {
+            let password = snapshot
+                .children()
+                .filter(|n| n.has_tag_name("String"))
+                .find(|string| child_text(*string, "Key") == Some("Password"))
+                .and_then(|string| child_text(string, "Value"))
+                .unwrap_or_default();
+            let modified = child(snapshot, "Times")
+                .and_then(|times| child_text(times, "LastModificationTime"))
+                .and_then(parse_time);
+            (password, modified)
+        })
+        .collect();
+    for (i, (password, _)) in snapshots.iter().enumerate() {
+        let (next, replaced_at) = snapshots
+            .get(i + 1)
+            .copied()
+            .unwrap_or((draft.password.as_str(), draft.updated_at));
+        if *password != next {
+            draft.add_history(password, replaced_at);
+        }
+    }
+
+    draft
+}
+
+/// KeePass writes ISO 8601 times in XML exports, and base64 little-endian
+/// seconds since 0001-01-01 inside KDBX 4 files; both are accepted.
+pub fn parse_time(text: &str) -> Option<DateTime<Utc>> {
+    let text = text.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time.with_timezone(&Utc));
+    }
+
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(text)
+        .ok()?;
+    let secs = i64::from_le_bytes(bytes.try_into().ok()?);
+    DateTime::from_timestamp(secs.checked_add(KDBX_EPOCH_OFFSET)?, 0)
+}
+
+/// The KDBX 4 encoding read by `parse_time`.
+pub fn format_time(time: DateTime<Utc>) -> String {
+    let secs = time.timestamp() - KDBX_EPOCH_OFFSET;
+    base64::engine::general_purpose::STANDARD.encode(secs.to_le_bytes())
+}
+
+pub(crate) fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| n.has_tag_name(name))
+}
+
+pub(crate) fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    child(node, name).map(|n| n.text().unwrap_or_default())
+}
+
+/// Whether an entry string is one of KeePassXC's further URLs.
+pub(crate) fn is_extra_url(key: &str) -> bool {
+    match key.strip_prefix(EXTRA_URL) {
+        Some("") => true,
+        Some(index) => index
+            .strip_prefix('_')
+            .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit())),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORT: &str = r#"<?xml version="1.0" encoding="utf-8" standalone="yes"?>
+<KeePassFile>
+    <Meta>
+        <Generator>KeePass</Generator>
+        <RecycleBinUUID>cmVjeWNsZWJpbnV1aWQxMg==</RecycleBinUUID>
+    </Meta>
+    <Root>
+        <Group>
+            <UUID>cm9vdHJvb3Ryb290cm9vdA==</UUID>
+            <Name>Database</Name>
+            <Entry>
+                <UUID>ZW50cnllbnRyeWVudHJ5MQ==</UUID>
+                <Tags>ops;vpn</Tags>
+                <Times>
+                    <CreationTime>2020-05-01T12:00:00Z</CreationTime>
+                    <LastModificationTime>2021-05-01T12:00:00Z</LastModificationTime>
+                </Times>
+                <String><Key>Title</Key><Value>VPN</Value></String>
+                <String><Key>UserName</Key><Value>alice</Value></String>
+                <String><Key>Password</Key><Value ProtectInMemory="True">&lt;s3cret&gt;</Value></String>
+                <String><Key>URL</Key><Value>https://vpn.example.com</Value></String>
+                <String><Key>Notes</Key><Value /></String>
+                <String><Key>Server</Key><Value>10.0.0.1</Value></String>
+                <String><Key>KP2A_URL</Key><Value>https://vpn2.example.com</Value></String>
+                <String><Key>KP2A_URL_1</Key><Value>https://vpn3.example.com</Value></String>
+                <String><Key>PIN</Key><Value ProtectInMemory="True">1234</Value></String>
+                <History>
+                    <Entry>
+                        <Times><LastModificationTime>2020-05-01T12:00:00Z</LastModificationTime></Times>
+                        <String><Key>Title</Key><Value>VPN (old)</Value></String>
+                        <String><Key>Password</Key><Value ProtectInMemory="True">first</Value></String>
+                    </Entry>
+                    <Entry>
+                        <Times><LastModificationTime>2020-09-01T12:00:00Z</LastModificationTime></Times>
+                        <String><Key>Title</Key><Value>VPN</Value></String>
+                        <String><Key>Password</Key><Value ProtectInMemory="True">second</Value></String>
+                    </Entry>
+                    <Entry>
+                        <Times><LastModificationTime>2021-01-01T12:00:00Z</LastModificationTime></Times>
+                        <String><Key>Title</Key><Value>VPN</Value></String>
+                        <String><Key>Password</Key><Value ProtectInMemory="True">second</Value></String>
+                    </Entry>
+                </History>
+            </Entry>
+            <Group>
+                <UUID>d29ya3dvcmt3b3Jrd29yaw==</UUID>
+                <Name>Work</Name>
+                <Group>
+                    <UUID>c2VydmVyc3NlcnZlcnNzZQ==</UUID>
+                    <Name>Servers</Name>
+                    <Entry>
+                        <Times>
+                            <CreationTime>AAAAAAAAAAA=</CreationTime>
+                            <LastModificationTime>gF9J2g4AAAA=</LastModificationTime>
+                        </Times>
+                        <String><Key>Title</Key><Value>db01</Value></String>
+                        <String><Key>Password</Key><Value>pw</Value></String>
+                    </Entry>
+                </Group>
+            </Group>
+            <Group>
+                <UUID>cmVjeWNsZWJpbnV1aWQxMg==</UUID>
+                <Name>Recycle Bin</Name>
+                <Entry>
+                    <String><Key>Title</Key><Value>Deleted</Value></String>
+                </Entry>
+            </Group>
+        </Group>
+    </Root>
+</KeePassFile>"#;
+
+    #[test]
+    fn test_parse_keepass_xml() {
+        let entries = parse_xml(EXPORT.as_bytes()).unwrap();
+        let titles: Vec<&str> = entries.iter().map(|e| e.title.as_str()).collect();
+        assert_eq!(titles, vec!["VPN", "db01"]);
+
+        let vpn = &entries[0];
+        assert_eq!(vpn.password.as_str(), "<s3cret>");
+        assert_eq!(vpn.tags, vec!["ops", "vpn"]);
+        assert_eq!(vpn.notes, None);
+        assert_eq!(
+            vpn.urls,
+            vec!["https://vpn2.example.com", "https://vpn3.example.com"]
+        );
+        let fields: Vec<(&str, FieldKind, &str)> = vpn
+            .fields
+            .iter()
+            .map(|f| (f.name.as_str(), f.kind, f.value.as_str()))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("Server", FieldKind::Text, "10.0.0.1"),
+                ("PIN", FieldKind::Hidden, "1234"),
+            ]
+        );
+        assert_eq!(vpn.created_at.to_rfc3339(), "2020-05-01T12:00:00+00:00");
+        assert_eq!(vpn.updated_at.to_rfc3339(), "2021-05-01T12:00:00+00:00");
+        let history: Vec<(&str, String)> = vpn
+            .history
+            .iter()
+            .map(|h| (h.password.as_str(), h.changed_at.to_rfc3339()))
+            .collect();
+        assert_eq!(
+            history,
+            vec![
+                ("second", "2021-05-01T12:00:00+00:00".to_string()),
+                ("first", "2020-09-01T12:00:00+00:00".to_string()),
+            ]
+        );
+
+        let db = &entries[1];
+        assert_eq!(db.tags, vec!["Work/Servers"]);
+    }
+
+    #[test]
+    fn test_is_extra_url() {
+        assert!(is_extra_url("KP2A_URL"));
+        assert!(is_extra_url("KP2A_URL_12"));
+        assert!(!is_extra_url("KP2A_URL_"));
+        assert!(!is_extra_url("KP2A_URL_x"));
+        assert!(!is_extra_url("URL"));
+    }
+
+    #[test]
+    fn test_parse_time() {
+        // 63_745_056_000 seconds after 0001-01-01 is 2021-01-01.
+        let encoded =
+            base64::engine::general_purpose::STANDARD.encode(63_745_056_000i64.to_le_bytes());
+        assert_eq!(
+            parse_time(&encoded).unwrap().to_rfc3339(),
+            "2021-01-01T00:00:00+00:00"
+        );
+        assert!(parse_time("yesterday").is_none());
+
+        let time = parse_time("2023-06-15T08:30:00Z").unwrap();
+        assert_eq!(parse_time(&format_time(time)), Some(time));
+    }
+}
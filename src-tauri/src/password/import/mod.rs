@@ -0,0 +1,452 @@
+//! Reading exports from other password managers into `PasswordEntry` values.
+//! Parsers only turn bytes into entries; `Vault::import_entries` decides
+//! what gets added.
+
+pub mod bitwarden;
+pub mod csv;
+pub mod keepass;
+pub mod onepassword;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+
+use super::error::VaultError;
+use super::fields;
+use super::totp::Totp;
+use super::types::{
+    CustomField, EntryKind, FieldKind, PasswordEntry, PasswordEntrySummary, PasswordHistoryItem,
+    SecureString, UrlMatch,
+};
+use super::urls;
+
+/// The export formats that can be imported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportFormat {
+    /// Unencrypted Bitwarden `.json` export.
+    BitwardenJson,
+    /// 1Password 8 `.1pux` export.
+    OnePasswordPux,
+    /// 1Password `.csv` export.
+    OnePasswordCsv,
+    /// KeePass 2 "KeePass XML (2.x)" export.
+    KeepassXml,
+    /// Chrome, Edge and other Chromium browsers' password `.csv`.
+    ChromeCsv,
+    /// Firefox's `logins.csv`.
+    FirefoxCsv,
+}
+
+impl ImportFormat {
+    pub const ALL: [ImportFormat; 6] = [
+        ImportFormat::BitwardenJson,
+        ImportFormat::OnePasswordPux,
+        ImportFormat::OnePasswordCsv,
+        ImportFormat::KeepassXml,
+        ImportFormat::ChromeCsv,
+        ImportFormat::FirefoxCsv,
+    ];
+
+    /// The serialized name, e.g. `bitwarden_json`.
+    pub fn name(self) -> &'static str {
+        match self {
+            ImportFormat::BitwardenJson => "bitwarden_json",
+            ImportFormat::OnePasswordPux => "one_password_pux",
+            ImportFormat::OnePasswordCsv => "one_password_csv",
+            ImportFormat::KeepassXml => "keepass_xml",
+            ImportFormat::ChromeCsv => "chrome_csv",
+            ImportFormat::FirefoxCsv => "firefox_csv",
+        }
+    }
+}
+
+impl fmt::Display for ImportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ImportFormat {
+    type Err = VaultError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.name() == s)
+            .ok_or_else(|| VaultError::InvalidInput(format!("Unknown import format '{}'", s)))
+    }
+}
+
+/// Parses an export in `format` into new entries with fresh ids.
+pub fn parse(format: ImportFormat, data: &[u8]) -> Result<Vec<PasswordEntry>, VaultError> {
+    match format {
+        ImportFormat::BitwardenJson => bitwarden::parse(data),
+        ImportFormat::OnePasswordPux => onepassword::parse_pux(data),
+        ImportFormat::KeepassXml => keepass::parse_xml(data),
+        ImportFormat::OnePasswordCsv | ImportFormat::ChromeCsv | ImportFormat::FirefoxCsv => {
+            csv::parse(data)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImportOptions {
+    /// Report what would be imported without changing the vault.
+    pub dry_run: bool,
+    /// Leave out entries that look like duplicates.
+    pub skip_duplicates: bool,
+}
+
+/// One parsed entry, as shown in the import preview.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportedEntry {
+    #[serde(flatten)]
+    pub summary: PasswordEntrySummary,
+    /// The existing entry, or earlier entry in the same import, with the
+    /// same site and username.
+    pub duplicate_of: Option<Uuid>,
+    pub skipped: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub entries: Vec<ImportedEntry>,
+    /// Entries added, or that would be added on a dry run.
+    pub imported: usize,
+    pub skipped: usize,
+}
+
+/// For each imported entry, the entry it duplicates, if any. Two entries are
+/// duplicates when they have the same username for the same site, the site
+/// being the URL's host or, without a URL, the title.
+pub fn find_duplicates(
+    existing: &[PasswordEntry],
+    imported: &[PasswordEntry],
+) -> Vec<Option<Uuid>> {
+    let mut seen: HashMap<(String, String), Uuid> = HashMap::new();
+    for entry in existing {
+        seen.entry(duplicate_key(entry)).or_insert(entry.id);
+    }
+
+    imported
+        .iter()
+        .map(|entry| match seen.get(&duplicate_key(entry)) {
+            Some(id) => Some(*id),
+            None => {
+                seen.insert(duplicate_key(entry), entry.id);
+                None
+            }
+        })
+        .collect()
+}
+
+fn duplicate_key(entry: &PasswordEntry) -> (String, String) {
+    let site = entry
+        .url
+        .as_deref()
+        .map(host)
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| entry.title.trim().to_lowercase());
+    (site, entry.username.trim().to_lowercase())
+}
+
+/// The lowercased host of `url` without `www.`, tolerating a missing scheme.
+pub(crate) fn host(url: &str) -> String {
+    let rest = url
+        .trim()
+        .split_once("://")
+        .map_or(url.trim(), |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority.rsplit('@').next().unwrap_or_default();
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    };
+    let host = host.to_lowercase();
+    host.strip_prefix("www.")
+        .map(str::to_string)
+        .unwrap_or(host)
+}
+
+/// Fields read from an export, before they become a `PasswordEntry`.
+#[derive(Debug, Default)]
+struct Draft {
+    kind: EntryKind,
+    title: String,
+    username: String,
+    password: SecureString,
+    url: Option<String>,
+    urls: Vec<String>,
+    url_match: UrlMatch,
+    notes: Option<String>,
+    totp: Option<SecureString>,
+    fields: Vec<CustomField>,
+    /// Previous passwords and when they were replaced, in any order.
+    history: Vec<(SecureString, Option<DateTime<Utc>>)>,
+    tags: Vec<String>,
+    favorite: bool,
+    created_at: Option<DateTime<Utc>>,
+    updated_at: Option<DateTime<Utc>>,
+    /// Anything without a field of its own, appended to the notes.
+    extra: Vec<(String, String)>,
+}
+
+impl Draft {
+    fn add_extra(&mut self, name: &str, value: &str) {
+        if !value.trim().is_empty() {
+            self.extra
+                .push((name.trim().to_string(), value.to_string()));
+        }
+    }
+
+    /// Adds a custom field, of the kind the entry's template gives it if it
+    /// has one. URL and date values that zims cannot read are kept as text,
+    /// and a second field of the same name goes into the notes.
+    fn add_field(&mut self, name: &str, kind: FieldKind, value: &str) {
+        let name = name.trim();
+        if value.trim().is_empty() {
+            return;
+        }
+        if name.is_empty()
+            || self
+                .fields
+                .iter()
+                .any(|f| f.name.eq_ignore_ascii_case(name))
+        {
+            self.add_extra(name, value);
+            return;
+        }
+
+        let template = self.kind.template().iter().find(|t| t.name == name);
+        let kind = match template.map_or(kind, |t| t.kind) {
+            FieldKind::Url if url::Url::parse(value.trim()).is_err() => FieldKind::Text,
+            FieldKind::Date if fields::parse_date(value).is_none() => FieldKind::Text,
+            kind => kind,
+        };
+        self.fields.push(CustomField {
+            name: name.to_string(),
+            kind,
+            value: value.to_string().into(),
+        });
+    }
+
+    /// The first URL becomes the entry's `url`, any others its further URLs.
+    fn add_url(&mut self, url: &str) {
+        if self.url.as_deref().is_none_or(|u| u.trim().is_empty()) {
+            self.url = Some(url.to_string());
+        } else {
+            self.urls.push(url.to_string());
+        }
+    }
+
+    /// Adds a previous password. One without a date is taken to have been
+    /// replaced when the entry was last modified.
+    fn add_history(&mut self, password: &str, changed_at: Option<DateTime<Utc>>) {
+        if !password.is_empty() {
+            self.history.push((password.to_string().into(), changed_at));
+        }
+    }
+
+    /// Keeps the first TOTP seed zims can generate codes from; any other
+    /// goes into the notes.
+    fn set_totp(&mut self, value: &str) {
+        if self.totp.is_none() && Totp::parse(value).is_ok() {
+            self.totp = Some(value.trim().to_string().into());
+        } else {
+            self.add_extra("TOTP", value);
+        }
+    }
+
+    fn add_tag(&mut self, tag: &str) {
+        let tag = tag.trim();
+        if !tag.is_empty() && !self.tags.iter().any(|t| t == tag) {
+            self.tags.push(tag.to_string());
+        }
+    }
+
+    /// True when there is nothing worth importing, e.g. a blank CSV row.
+    fn is_empty(&self) -> bool {
+        self.title.trim().is_empty()
+            && self.username.is_empty()
+            && self.password.as_str().is_empty()
+            && self.url.as_deref().is_none_or(|u| u.trim().is_empty())
+            && self.notes.as_deref().is_none_or(|n| n.trim().is_empty())
+            && self.fields.is_empty()
+    }
+
+    fn into_entry(self) -> PasswordEntry {
+        let url = non_empty(self.url);
+        let urls = urls::clean(url.as_deref(), self.urls);
+        // A pattern zims cannot compile would match nothing anyway.
+        let url_match = match urls::check(self.url_match, url.as_deref(), &urls) {
+            Ok(()) => self.url_match,
+            Err(_) => UrlMatch::Never,
+        };
+        let title = match self.title.trim() {
+            "" => url.as_deref().map(host).unwrap_or_default(),
+            title => title.to_string(),
+        };
+        let title = if title.is_empty() {
+            "Untitled".to_string()
+        } else {
+            title
+        };
+
+        let mut notes = non_empty(self.notes).unwrap_or_default();
+        for (name, value) in &self.extra {
+            if !notes.is_empty() {
+                notes.push('\n');
+            }
+            notes.push_str(&format!("{}: {}", name, value));
+        }
+
+        let now = Utc::now();
+        let created_at = self.created_at.or(self.updated_at).unwrap_or(now);
+        let updated_at = self.updated_at.unwrap_or(created_at).max(created_at);
+
+        let mut history: Vec<PasswordHistoryItem> = self
+            .history
+            .into_iter()
+            .map(|(password, changed_at)| PasswordHistoryItem {
+                password,
+                changed_at: changed_at.unwrap_or(updated_at),
+            })
+            .collect();
+        history.sort_by_key(|item| std::cmp::Reverse(item.changed_at));
+
+        let mut entry = PasswordEntry {
+            id: Uuid::new_v4(),
+            kind: self.kind,
+            title,
+            username: self.username,
+            password: self.password,
+            url,
+            urls,
+            url_match,
+            notes: non_empty(Some(notes)),
+            totp: self.totp,
+            fields: self.fields,
+            history,
+            tags: self.tags,
+            favorite: self.favorite,
+            created_at,
+            updated_at,
+            deleted_at: None,
+        };
+
+        // An item that lacks what its kind needs, say a card without a
+        // number, is kept as a login so that it can still be edited.
+        if fields::validate_entry(&entry).is_err() {
+            entry.kind = EntryKind::Login;
+        }
+        entry
+    }
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.filter(|v| !v.trim().is_empty())
+}
+
+fn from_unix(secs: i64) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(secs, 0)
+}
+
+fn from_unix_millis(millis: i64) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp_millis(millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(title: &str, username: &str, url: Option<&str>) -> PasswordEntry {
+        Draft {
+            title: title.to_string(),
+            username: username.to_string(),
+            url: url.map(str::to_string),
+            ..Draft::default()
+        }
+        .into_entry()
+    }
+
+    #[test]
+    fn test_host() {
+        assert_eq!(
+            host("https://www.Example.com:8443/login?x=1"),
+            "example.com"
+        );
+        assert_eq!(host("user@mail.example.com/inbox"), "mail.example.com");
+        assert_eq!(host("example.com"), "example.com");
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let existing = vec![entry("GitHub", "alice", Some("https://github.com"))];
+        let imported = vec![
+            entry(
+                "GitHub (old)",
+                "Alice",
+                Some("https://www.github.com/login"),
+            ),
+            entry("GitHub", "bob", Some("https://github.com")),
+            entry("Router", "admin", None),
+            entry("router", "admin", None),
+        ];
+
+        let duplicates = find_duplicates(&existing, &imported);
+        assert_eq!(
+            duplicates,
+            vec![Some(existing[0].id), None, None, Some(imported[2].id)]
+        );
+    }
+
+    #[test]
+    fn test_draft_defaults() {
+        let entry = Draft {
+            url: Some("https://example.com/login".to_string()),
+            notes: Some("  ".to_string()),
+            extra: vec![("PIN".to_string(), "1234".to_string())],
+            ..Draft::default()
+        }
+        .into_entry();
+
+        assert_eq!(entry.title, "example.com");
+        assert_eq!(entry.notes.as_deref(), Some("PIN: 1234"));
+        assert!(entry.updated_at >= entry.created_at);
+    }
+
+    #[test]
+    fn test_set_totp() {
+        let mut draft = Draft::default();
+        draft.set_totp("steam://ABCDEF");
+        draft.set_totp(" JBSWY3DPEHPK3PXP ");
+        draft.set_totp("otpauth://totp/x?secret=GEZDGNBV");
+        let entry = draft.into_entry();
+
+        assert_eq!(
+            entry.totp.as_ref().map(|t| t.as_str()),
+            Some("JBSWY3DPEHPK3PXP")
+        );
+        assert_eq!(
+            entry.notes.as_deref(),
+            Some("TOTP: steam://ABCDEF\nTOTP: otpauth://totp/x?secret=GEZDGNBV")
+        );
+    }
+
+    #[test]
+    fn test_format_names() {
+        for format in ImportFormat::ALL {
+            assert_eq!(format.name().parse::<ImportFormat>().unwrap(), format);
+            assert_eq!(
+                serde_json::to_value(format).unwrap(),
+                serde_json::Value::String(format.name().to_string())
+            );
+        }
+    }
+}
@@ -0,0 +1,409 @@
+//! 1Password 8's `.1pux` export: a zip archive whose `export.data` holds
+//! every account, vault and item as JSON. 1Password's CSV export is read by
+//! `import::csv`.
+
+use serde::Deserialize;
+use std::io::{Cursor, Read};
+
+use super::{from_unix, Draft};
+use crate::password::error::VaultError;
+use crate::password::fields;
+use crate::password::types::{EntryKind, FieldKind, PasswordEntry};
+
+const EXPORT_DATA: &str = "export.data";
+
+const CATEGORY_CREDIT_CARD: &str = "002";
+const CATEGORY_SECURE_NOTE: &str = "003";
+const CATEGORY_IDENTITY: &str = "004";
+/// The Password category, whose password lives in `details.password`
+/// rather than in a login field.
+const CATEGORY_PASSWORD: &str = "005";
+const CATEGORY_WIRELESS_ROUTER: &str = "109";
+const CATEGORY_API_CREDENTIAL: &str = "112";
+
+/// Section fields that become template fields, by category and field id.
+const TEMPLATE_FIELDS: &[(&str, &str, &str)] = &[
+    (CATEGORY_CREDIT_CARD, "cardholder", fields::CARDHOLDER),
+    (CATEGORY_CREDIT_CARD, "ccnum", fields::CARD_NUMBER),
+    (CATEGORY_CREDIT_CARD, "expiry", fields::EXPIRY),
+    (CATEGORY_CREDIT_CARD, "cvv", fields::SECURITY_CODE),
+    (CATEGORY_IDENTITY, "email", fields::EMAIL),
+    (CATEGORY_IDENTITY, "birthdate", fields::BIRTHDAY),
+    (CATEGORY_WIRELESS_ROUTER, "network_name", fields::SSID),
+    (
+        CATEGORY_WIRELESS_ROUTER,
+        "wireless_security",
+        fields::SECURITY,
+    ),
+    (CATEGORY_API_CREDENTIAL, "expires", fields::EXPIRES),
+];
+
+/// Section fields that hold the item's secret, by category and field id.
+const PASSWORD_FIELDS: &[(&str, &str)] = &[
+    (CATEGORY_WIRELESS_ROUTER, "wireless_password"),
+    (CATEGORY_API_CREDENTIAL, "credential"),
+];
+
+/// Identity name parts, joined into the full name.
+const IDENTITY_NAME: [&str; 3] = ["firstname", "initial", "lastname"];
+
+#[derive(Deserialize)]
+struct Export {
+    #[serde(default)]
+    accounts: Vec<Account>,
+}
+
+#[derive(Deserialize)]
+struct Account {
+    #[serde(default)]
+    vaults: Vec<Vault>,
+}
+
+#[derive(Deserialize)]
+struct Vault {
+    attrs: VaultAttrs,
+    #[serde(default)]
+    items: Vec<Item>,
+}
+
+#[derive(Deserialize)]
+struct VaultAttrs {
+    #[serde(default)]
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Item {
+    #[serde(default)]
+    fav_index: i64,
+    created_at: Option<i64>,
+    updated_at: Option<i64>,
+    #[serde(default)]
+    category_uuid: String,
+    #[serde(default)]
+    details: Details,
+    overview: Overview,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Details {
+    #[serde(default)]
+    login_fields: Vec<LoginField>,
+    notes_plain: Option<String>,
+    #[serde(default)]
+    sections: Vec<Section>,
+    password: Option<String>,
+    #[serde(default)]
+    password_history: Vec<PreviousPassword>,
+}
+
+#[derive(Deserialize)]
+struct PreviousPassword {
+    #[serde(default)]
+    value: String,
+    time: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct LoginField {
+    #[serde(default)]
+    value: String,
+    designation: Option<String>,
+    #[serde(default)]
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Section {
+    #[serde(default)]
+    fields: Vec<SectionField>,
+}
+
+#[derive(Deserialize)]
+struct SectionField {
+    #[serde(default)]
+    id: String,
+    #[serde(default)]
+    title: String,
+    /// A single-key object such as `{"string": "..."}` or `{"totp": "..."}`.
+    #[serde(default)]
+    value: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct Overview {
+    #[serde(default)]
+    title: String,
+    url: Option<String>,
+    #[serde(default)]
+    urls: Vec<OverviewUrl>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct OverviewUrl {
+    #[serde(default)]
+    url: String,
+}
+
+/// Each 1Password vault's name becomes a tag alongside the item's own tags.
+pub fn parse_pux(data: &[u8]) -> Result<Vec<PasswordEntry>, VaultError> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data))
+        .map_err(|e| VaultError::InvalidInput(format!("Not a 1PUX archive: {}", e)))?;
+    let mut file = archive
+        .by_name(EXPORT_DATA)
+        .map_err(|_| VaultError::InvalidInput(format!("1PUX archive has no {}", EXPORT_DATA)))?;
+    let mut json = Vec::new();
+    file.read_to_end(&mut json)?;
+
+    let export: Export = serde_json::from_slice(&json)?;
+    let mut entries = Vec::new();
+    for vault in export.accounts.into_iter().flat_map(|a| a.vaults) {
+        for item in vault.items {
+            let draft = read_item(item, &vault.attrs.name);
+            if !draft.is_empty() {
+                entries.push(draft.into_entry());
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+fn read_item(item: Item, vault_name: &str) -> Draft {
+    let category = item.category_uuid.as_str();
+    let mut draft = Draft {
+        kind: match category {
+            CATEGORY_CREDIT_CARD => EntryKind::CreditCard,
+            CATEGORY_SECURE_NOTE => EntryKind::SecureNote,
+            CATEGORY_IDENTITY => EntryKind::Identity,
+            CATEGORY_WIRELESS_ROUTER => EntryKind::Wifi,
+            CATEGORY_API_CREDENTIAL => EntryKind::ApiToken,
+            _ => EntryKind::Login,
+        },
+        title: item.overview.title,
+        notes: item.details.notes_plain,
+        favorite: item.fav_index > 0,
+        created_at: item.created_at.and_then(from_unix),
+        updated_at: item.updated_at.and_then(from_unix),
+        ..Draft::default()
+    };
+
+    draft.add_tag(vault_name);
+    for tag in &item.overview.tags {
+        draft.add_tag(tag);
+    }
+
+    // The overview's own URL is the primary one, and usually also listed.
+    let urls = item.overview.urls.into_iter().map(|u| u.url);
+    for url in item.overview.url.into_iter().chain(urls) {
+        draft.add_url(&url);
+    }
+
+    for field in item.details.login_fields {
+        match field.designation.as_deref() {
+            Some("username") => draft.username = field.value,
+            Some("password") => draft.password = field.value.into(),
+            _ => draft.add_extra(&field.name, &field.value),
+        }
+    }
+    if category == CATEGORY_PASSWORD {
+        if let Some(password) = item.details.password {
+            draft.password = password.into();
+        }
+    }
+    for previous in item.details.password_history {
+        draft.add_history(&previous.value, previous.time.and_then(from_unix));
+    }
+
+    let mut name_parts = Vec::new();
+    for field in item.details.sections.into_iter().flat_map(|s| s.fields) {
+        let Some((kind, value)) = field.value.as_object().and_then(|v| v.iter().next()) else {
+            continue;
+        };
+        let value = match (kind.as_str(), value) {
+            ("date", value) => value
+                .as_i64()
+                .and_then(from_unix)
+                .map(|date| date.format("%Y-%m-%d").to_string()),
+            // `202904` for April 2029.
+            ("monthYear", value) => value
+                .as_i64()
+                .map(|date| format!("{:04}-{:02}", date / 100, date % 100)),
+            (_, serde_json::Value::String(value)) => Some(value.clone()),
+            (_, serde_json::Value::Number(value)) => Some(value.to_string()),
+            _ => None,
+        };
+        let Some(value) = value else {
+            continue;
+        };
+
+        let id = field.id.as_str();
+        let title = match field.title.as_str() {
+            "" => id,
+            title => title,
+        };
+        let name_part = IDENTITY_NAME.iter().position(|part| *part == id);
+        if let (CATEGORY_IDENTITY, Some(position)) = (category, name_part) {
+            name_parts.push((position, value));
+        } else if PASSWORD_FIELDS.contains(&(category, id)) {
+            draft.password = value.into();
+        } else if let Some((.., name)) = TEMPLATE_FIELDS
+            .iter()
+            .find(|f| (f.0, f.1) == (category, id))
+        {
+            draft.add_field(name, FieldKind::Text, &value);
+        } else {
+            match kind.as_str() {
+                "totp" => draft.set_totp(&value),
+                "concealed" => draft.add_field(title, FieldKind::Hidden, &value),
+                "url" => draft.add_field(title, FieldKind::Url, &value),
+                "date" | "monthYear" => draft.add_field(title, FieldKind::Date, &value),
+                _ => draft.add_field(title, FieldKind::Text, &value),
+            }
+        }
+    }
+
+    name_parts.sort_by_key(|(position, _)| *position);
+    let name: Vec<String> = name_parts.into_iter().map(|(_, part)| part).collect();
+    draft.add_field(fields::FULL_NAME, FieldKind::Text, &name.join(" "));
+
+    draft
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const EXPORT_JSON: &str = r#"{
+        "accounts": [{
+            "attrs": { "accountName": "Alice" },
+            "vaults": [{
+                "attrs": { "uuid": "v1", "name": "Private" },
+                "items": [
+                    {
+                        "uuid": "a",
+                        "favIndex": 1,
+                        "createdAt": 1600000000,
+                        "updatedAt": 1650000000,
+                        "state": "active",
+                        "categoryUuid": "001",
+                        "details": {
+                            "loginFields": [
+                                { "value": "alice", "name": "username", "fieldType": "T", "designation": "username" },
+                                { "value": "hunter2", "name": "password", "fieldType": "P", "designation": "password" }
+                            ],
+                            "notesPlain": "Security questions in the safe",
+                            "sections": [{
+                                "title": "",
+                                "fields": [
+                                    { "title": "one-time password", "id": "TOTP_1", "value": { "totp": "otpauth://totp/x?secret=ABC" } }
+                                ]
+                            }],
+                            "passwordHistory": [{ "value": "hunter1", "time": 1610000000 }]
+                        },
+                        "overview": {
+                            "title": "Example",
+                            "url": "https://example.com",
+                            "urls": [{ "label": "", "url": "https://example.com" }, { "label": "", "url": "https://login.example.com" }],
+                            "tags": ["web"]
+                        }
+                    },
+                    {
+                        "uuid": "b",
+                        "favIndex": 0,
+                        "createdAt": 1600000000,
+                        "updatedAt": 1600000000,
+                        "categoryUuid": "005",
+                        "details": { "password": "router-pw" },
+                        "overview": { "title": "Router" }
+                    },
+                    {
+                        "uuid": "c",
+                        "categoryUuid": "002",
+                        "details": {
+                            "sections": [{
+                                "title": "",
+                                "fields": [
+                                    { "title": "cardholder name", "id": "cardholder", "value": { "string": "Alice" } },
+                                    { "title": "number", "id": "ccnum", "value": { "creditCardNumber": "4111111111111111" } },
+                                    { "title": "verification number", "id": "cvv", "value": { "concealed": "123" } },
+                                    { "title": "expiry date", "id": "expiry", "value": { "monthYear": 202904 } },
+                                    { "title": "PIN", "id": "pin", "value": { "concealed": "1234" } },
+                                    { "title": "issued", "id": "valid_from", "value": { "date": 1600000000 } }
+                                ]
+                            }]
+                        },
+                        "overview": { "title": "Visa" }
+                    }
+                ]
+            }]
+        }]
+    }"#;
+
+    fn pux(json: &str) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file(EXPORT_DATA, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(json.as_bytes()).unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_parse_pux() {
+        let entries = parse_pux(&pux(EXPORT_JSON)).unwrap();
+        assert_eq!(entries.len(), 3);
+
+        let login = &entries[0];
+        assert_eq!(login.username, "alice");
+        assert_eq!(login.password.as_str(), "hunter2");
+        assert_eq!(login.history[0].password.as_str(), "hunter1");
+        assert_eq!(login.history[0].changed_at.timestamp(), 1_610_000_000);
+        assert_eq!(login.url.as_deref(), Some("https://example.com"));
+        assert_eq!(login.tags, vec!["Private", "web"]);
+        assert!(login.favorite);
+        assert_eq!(login.urls, vec!["https://login.example.com"]);
+        assert_eq!(
+            login.notes.as_deref(),
+            Some("Security questions in the safe")
+        );
+        assert_eq!(
+            login.totp.as_ref().map(|t| t.as_str()),
+            Some("otpauth://totp/x?secret=ABC")
+        );
+        assert_eq!(login.created_at.timestamp(), 1_600_000_000);
+
+        assert_eq!(entries[1].password.as_str(), "router-pw");
+
+        let card = &entries[2];
+        assert_eq!(card.kind, EntryKind::CreditCard);
+        let card_fields: Vec<(&str, FieldKind, &str)> = card
+            .fields
+            .iter()
+            .map(|f| (f.name.as_str(), f.kind, f.value.as_str()))
+            .collect();
+        assert_eq!(
+            card_fields,
+            vec![
+                (fields::CARDHOLDER, FieldKind::Text, "Alice"),
+                (fields::CARD_NUMBER, FieldKind::Hidden, "4111111111111111"),
+                (fields::SECURITY_CODE, FieldKind::Hidden, "123"),
+                (fields::EXPIRY, FieldKind::Date, "2029-04"),
+                ("PIN", FieldKind::Hidden, "1234"),
+                ("issued", FieldKind::Date, "2020-09-13"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rejects_other_archives() {
+        assert!(matches!(
+            parse_pux(b"not a zip"),
+            Err(VaultError::InvalidInput(_))
+        ));
+    }
+}
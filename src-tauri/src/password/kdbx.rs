@@ -0,0 +1,1696 @@
+//! KeePass KDBX 4 databases, which zims can open and save in place of its
+//! own vault format. Only the variants built from primitives zims already
+//! uses are supported: an Argon2d or Argon2id KDF and the ChaCha20 cipher,
+//! with the master password as the only key (no key file).
+//!
+//! Groups map onto tags as in `import::keepass`: an entry is tagged with its
+//! group's path below the root, and a new entry is filed into the first
+//! existing group one of its tags names. Custom strings become custom
+//! fields, hidden if protected, except KeePassXC's `KP2A_URL` strings, which
+//! are an entry's further URLs; an entry's kind, URL match mode and which
+//! fields are URLs or dates are kept in its `CustomData`. Everything else zims has no model
+//! for (attachments, icons, auto-type, database metadata) is carried along
+//! and written back unchanged. Entry history is reduced to the snapshots
+//! that changed the password, which become the entry's password history.
+//! The recycle bin holds the trash: its entries are read as trashed, and
+//! groups inside it are dropped when zims saves.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::ChaCha20;
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use hmac::{Hmac, Mac};
+use roxmltree::{Document, Node, NodeId};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use super::crypto::{self, KdfParams, VaultKey};
+use super::error::VaultError;
+use super::import::keepass::{
+    child, child_text, format_time, is_extra_url, parse_time, EXTRA_URL, NULL_UUID,
+};
+use super::storage::VaultData;
+use super::types::{
+    CustomField, EntryKind, FieldKind, PasswordEntry, PasswordHistoryItem, SecureString, UrlMatch,
+    VaultSettings,
+};
+
+/// The two little-endian signature words every KeePass 2 file starts with.
+const SIGNATURE: [u8; 8] = [0x03, 0xd9, 0xa2, 0x9a, 0x67, 0xfb, 0x4b, 0xb5];
+const VERSION_MAJOR: u16 = 4;
+const VERSION_MINOR: u16 = 0;
+
+const CIPHER_CHACHA20: Uuid = Uuid::from_u128(0xd6038a2b_8b6f_4cb5_a524_339a31dbb59a);
+const CIPHER_AES256: Uuid = Uuid::from_u128(0x31c1f2e6_bf71_4350_be58_05216afc5aff);
+const KDF_ARGON2D: Uuid = Uuid::from_u128(0xef636ddf_8c29_444b_91f7_a9a403e30a0c);
+const KDF_ARGON2ID: Uuid = Uuid::from_u128(0x9e298b19_56db_4773_b23d_fc3ec6f0a1e6);
+
+// Outer header fields.
+const END_OF_HEADER: u8 = 0;
+const CIPHER_ID: u8 = 2;
+const COMPRESSION_FLAGS: u8 = 3;
+const MASTER_SEED: u8 = 4;
+const ENCRYPTION_IV: u8 = 7;
+const KDF_PARAMETERS: u8 = 11;
+const PUBLIC_CUSTOM_DATA: u8 = 12;
+
+// Inner header fields, at the start of the decrypted payload.
+const INNER_STREAM_ID: u8 = 1;
+const INNER_STREAM_KEY: u8 = 2;
+const INNER_BINARY: u8 = 3;
+const INNER_STREAM_CHACHA20: u32 = 3;
+
+// Variant map value types, as used for the KDF parameters.
+const VARIANT_MAP_VERSION: u16 = 0x0100;
+const VARIANT_U32: u8 = 0x04;
+const VARIANT_U64: u8 = 0x05;
+const VARIANT_BYTES: u8 = 0x42;
+
+/// Payload is split into HMAC-authenticated blocks of this size.
+const BLOCK_SIZE: usize = 1 << 20;
+
+/// Meta custom data item zims keeps its vault settings in.
+const SETTINGS_KEY: &str = "zims.settings";
+/// Entry custom data item zims keeps an `EntryMeta` in.
+const ENTRY_KEY: &str = "zims.entry";
+/// KeePass's recycle bin icon.
+const RECYCLE_BIN_ICON: u32 = 43;
+
+/// Standard entry strings, which map onto `PasswordEntry` fields.
+const TITLE: &str = "Title";
+const USER_NAME: &str = "UserName";
+const PASSWORD: &str = "Password";
+const URL: &str = "URL";
+const NOTES: &str = "Notes";
+/// Where KeePassXC keeps an entry's `otpauth://` URI.
+const OTP: &str = "otp";
+const STANDARD_KEYS: [&str; 6] = [TITLE, USER_NAME, PASSWORD, URL, NOTES, OTP];
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Whether `data` looks like a KeePass 2 database of any version.
+pub fn is_kdbx(data: &[u8]) -> bool {
+    data.starts_with(&SIGNATURE)
+}
+
+/// An unlocked KDBX database: the derived key, and everything needed to
+/// write the file back that does not live in zims's entries.
+#[derive(Clone)]
+pub struct KdbxDatabase {
+    kdf: Kdf,
+    transformed_key: VaultKey,
+    minor_version: u16,
+    compressed: bool,
+    public_custom_data: Option<Vec<u8>>,
+    /// Attachments from the inner header, verbatim and in order, since
+    /// entries refer to them by index.
+    binaries: Vec<Zeroizing<Vec<u8>>>,
+    /// Raw XML of the `Meta` elements zims does not write itself.
+    meta: String,
+    /// Raw `Meta/CustomData` items other than zims's settings.
+    custom_data: String,
+    root: Group,
+    groups: Vec<Group>,
+    recycle_bin: Option<String>,
+    /// UUID for a recycle bin, should the trash need one and the database
+    /// have none.
+    new_recycle_bin: String,
+    /// Raw XML of `Root`'s children other than the root group.
+    deleted_objects: String,
+    extras: HashMap<Uuid, EntryExtras>,
+    /// SHA-256 of the file as last read or written.
+    file_hash: [u8; 32],
+}
+
+#[derive(Clone)]
+struct Group {
+    /// Names of the groups below the root down to this one; empty for the
+    /// root itself.
+    path: Vec<String>,
+    name: String,
+    uuid: String,
+    extra: String,
+}
+
+impl Group {
+    fn new(path: Vec<String>, name: &str) -> Self {
+        Self {
+            path,
+            name: name.to_string(),
+            uuid: encode_uuid(Uuid::new_v4()),
+            extra: String::new(),
+        }
+    }
+}
+
+/// The parts of a KeePass entry that `PasswordEntry` has no field for.
+#[derive(Clone, Default)]
+struct EntryExtras {
+    /// Raw `Times` children other than creation and modification time.
+    times: String,
+    /// Raw `CustomData` items other than zims's `EntryMeta`.
+    custom_data: String,
+    /// Raw entry children zims does not write itself.
+    extra: String,
+}
+
+/// What KeePass has no place for in a `PasswordEntry`.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct EntryMeta {
+    kind: EntryKind,
+    #[serde(skip_serializing_if = "is_default")]
+    url_match: UrlMatch,
+    /// URL and date fields; the rest are text, or hidden if protected.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    field_kinds: BTreeMap<String, FieldKind>,
+    /// When zims moved the entry to the recycle bin.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone)]
+struct Kdf {
+    algorithm: argon2::Algorithm,
+    version: argon2::Version,
+    salt: Vec<u8>,
+    params: KdfParams,
+}
+
+impl KdbxDatabase {
+    /// A new, empty database using Argon2id with `kdf_params`.
+    pub fn create(password: &str, kdf_params: KdfParams) -> Result<Self, VaultError> {
+        let kdf = Kdf::argon2id(kdf_params);
+        let transformed_key = kdf.transform(password)?;
+
+        Ok(Self {
+            kdf,
+            transformed_key,
+            minor_version: VERSION_MINOR,
+            compressed: true,
+            public_custom_data: None,
+            binaries: Vec::new(),
+            meta: String::new(),
+            custom_data: String::new(),
+            root: Group::new(Vec::new(), "Root"),
+            groups: Vec::new(),
+            recycle_bin: None,
+            new_recycle_bin: encode_uuid(Uuid::new_v4()),
+            deleted_objects: String::new(),
+            extras: HashMap::new(),
+            file_hash: [0; 32],
+        })
+    }
+
+    /// Decrypts a KDBX 4 file. A wrong password fails with `WrongPassword`;
+    /// unsupported ciphers, KDFs and versions with `Unsupported` or
+    /// `VersionUnsupported`.
+    pub fn open(data: &[u8], password: &str) -> Result<(Self, VaultData), VaultError> {
+        let mut reader = Reader::new(data);
+        if reader.take(SIGNATURE.len())? != SIGNATURE {
+            return Err(VaultError::Corrupted("Not a KDBX file".to_string()));
+        }
+        let minor_version = reader.u16()?;
+        let major_version = reader.u16()?;
+        if major_version != VERSION_MAJOR {
+            return Err(VaultError::VersionUnsupported(major_version));
+        }
+
+        let mut cipher = None;
+        let mut compressed = None;
+        let mut master_seed = None;
+        let mut iv = None;
+        let mut kdf = None;
+        let mut public_custom_data = None;
+        loop {
+            let id = reader.u8()?;
+            let len = reader.u32()? as usize;
+            let value = reader.take(len)?;
+            match id {
+                END_OF_HEADER => break,
+                CIPHER_ID => cipher = Uuid::from_slice(value).ok(),
+                COMPRESSION_FLAGS => {
+                    compressed = match value {
+                        [0, 0, 0, 0] => Some(false),
+                        [1, 0, 0, 0] => Some(true),
+                        _ => {
+                            return Err(VaultError::Unsupported(
+                                "Unknown KDBX compression".to_string(),
+                            ))
+                        }
+                    }
+                }
+                MASTER_SEED => master_seed = <[u8; 32]>::try_from(value).ok(),
+                ENCRYPTION_IV => iv = <[u8; 12]>::try_from(value).ok(),
+                KDF_PARAMETERS => kdf = Some(Kdf::from_map(&read_variant_map(value)?)?),
+                PUBLIC_CUSTOM_DATA => public_custom_data = Some(value.to_vec()),
+                _ => {}
+            }
+        }
+        let header = &data[..reader.pos];
+
+        match cipher {
+            Some(CIPHER_CHACHA20) => {}
+            Some(CIPHER_AES256) => {
+                return Err(VaultError::Unsupported(
+                    "KDBX files encrypted with AES-256 are not supported; \
+                     switch the database to ChaCha20 in KeePass"
+                        .to_string(),
+                ))
+            }
+            _ => return Err(VaultError::Unsupported("Unknown KDBX cipher".to_string())),
+        }
+        let missing = |field: &str| VaultError::Corrupted(format!("KDBX header has no {}", field));
+        let compressed = compressed.ok_or_else(|| missing("compression flags"))?;
+        let master_seed = master_seed.ok_or_else(|| missing("master seed"))?;
+        let iv = iv.ok_or_else(|| missing("encryption IV"))?;
+        let kdf = kdf.ok_or_else(|| missing("KDF parameters"))?;
+
+        let header_hash = reader.take(32)?;
+        if Sha256::digest(header)[..] != *header_hash {
+            return Err(VaultError::Corrupted(
+                "KDBX header checksum mismatch".to_string(),
+            ));
+        }
+        let header_mac = reader.take(32)?;
+
+        let transformed_key = kdf.transform(password)?;
+        let (cipher_key, hmac_key) = derive_keys(&master_seed, &transformed_key);
+
+        let mut mac = block_mac(&hmac_key, u64::MAX);
+        mac.update(header);
+        mac.verify_slice(header_mac)
+            .map_err(|_| VaultError::WrongPassword)?;
+
+        let mut payload = Zeroizing::new(Vec::with_capacity(data.len()));
+        for index in 0u64.. {
+            let block_hash = reader.take(32)?;
+            let len = reader.u32()?;
+            let block = reader.take(len as usize)?;
+
+            let mut mac = block_mac(&hmac_key, index);
+            mac.update(&index.to_le_bytes());
+            mac.update(&len.to_le_bytes());
+            mac.update(block);
+            mac.verify_slice(block_hash).map_err(|_| {
+                VaultError::Corrupted(format!("KDBX block {} failed authentication", index))
+            })?;
+
+            if block.is_empty() {
+                break;
+            }
+            payload.extend_from_slice(block);
+        }
+
+        ChaCha20::new(&(*cipher_key).into(), &iv.into()).apply_keystream(&mut payload);
+
+        let plain = if compressed {
+            let mut plain = Zeroizing::new(Vec::new());
+            GzDecoder::new(payload.as_slice())
+                .read_to_end(&mut plain)
+                .map_err(|e| VaultError::Corrupted(format!("KDBX payload: {}", e)))?;
+            plain
+        } else {
+            payload
+        };
+
+        let mut reader = Reader::new(&plain);
+        let mut stream_key = None;
+        let mut binaries = Vec::new();
+        loop {
+            let id = reader.u8()?;
+            let len = reader.u32()? as usize;
+            let value = reader.take(len)?;
+            match id {
+                END_OF_HEADER => break,
+                INNER_STREAM_ID if value != INNER_STREAM_CHACHA20.to_le_bytes() => {
+                    return Err(VaultError::Unsupported(
+                        "KDBX protected values must use ChaCha20".to_string(),
+                    ))
+                }
+                INNER_STREAM_KEY => stream_key = Some(Zeroizing::new(value.to_vec())),
+                INNER_BINARY => binaries.push(Zeroizing::new(value.to_vec())),
+                _ => {}
+            }
+        }
+        let stream_key = stream_key.ok_or_else(|| missing("protected stream key"))?;
+        let xml = std::str::from_utf8(&plain[reader.pos..])
+            .map_err(|_| VaultError::Corrupted("KDBX XML must be UTF-8".to_string()))?;
+
+        let mut db = Self {
+            kdf,
+            transformed_key,
+            minor_version,
+            compressed,
+            public_custom_data,
+            binaries,
+            meta: String::new(),
+            custom_data: String::new(),
+            root: Group::new(Vec::new(), "Root"),
+            groups: Vec::new(),
+            recycle_bin: None,
+            new_recycle_bin: encode_uuid(Uuid::new_v4()),
+            deleted_objects: String::new(),
+            extras: HashMap::new(),
+            file_hash: Sha256::digest(data).into(),
+        };
+        let vault_data = db.read_xml(xml, &mut ProtectedStream::new(&stream_key))?;
+
+        Ok((db, vault_data))
+    }
+
+    /// Encrypts `vault_data` into a complete KDBX file, with a fresh master
+    /// seed, IV and protected stream key.
+    pub fn seal(&self, vault_data: &VaultData) -> Result<Vec<u8>, VaultError> {
+        let mut stream_key = Zeroizing::new([0u8; 64]);
+        crypto::fill_random(stream_key.as_mut());
+
+        let xml = self.write_xml(vault_data, &mut ProtectedStream::new(stream_key.as_ref()));
+
+        self.seal_xml(stream_key.as_ref(), &xml)
+    }
+
+    /// Records `file`, returned by `seal`, as written.
+    pub fn mark_saved(&mut self, file: &[u8]) {
+        self.file_hash = Sha256::digest(file).into();
+    }
+
+    /// Fails with `Conflict` if `on_disk` is not the file this database was
+    /// last read from or written to.
+    pub fn check_unchanged(&self, on_disk: &[u8]) -> Result<(), VaultError> {
+        if Sha256::digest(on_disk)[..] != self.file_hash {
+            return Err(VaultError::Conflict);
+        }
+        Ok(())
+    }
+
+    /// Switches to `password` and Argon2id with `kdf_params` and a fresh
+    /// salt, taking effect with the next `seal`.
+    pub fn rekey(&mut self, password: &str, kdf_params: KdfParams) -> Result<(), VaultError> {
+        let kdf = Kdf::argon2id(kdf_params);
+        self.transformed_key = kdf.transform(password)?;
+        self.kdf = kdf;
+        Ok(())
+    }
+
+    pub fn kdf_params(&self) -> KdfParams {
+        self.kdf.params
+    }
+
+    fn seal_xml(&self, stream_key: &[u8], xml: &str) -> Result<Vec<u8>, VaultError> {
+        let mut plain = Zeroizing::new(Vec::with_capacity(
+            xml.len() + self.binaries.iter().map(|b| b.len() + 5).sum::<usize>() + 128,
+        ));
+        write_field(
+            &mut plain,
+            INNER_STREAM_ID,
+            &INNER_STREAM_CHACHA20.to_le_bytes(),
+        );
+        write_field(&mut plain, INNER_STREAM_KEY, stream_key);
+        for binary in &self.binaries {
+            write_field(&mut plain, INNER_BINARY, binary);
+        }
+        write_field(&mut plain, END_OF_HEADER, &[]);
+        plain.extend_from_slice(xml.as_bytes());
+
+        let mut payload = if self.compressed {
+            let mut encoder =
+                GzEncoder::new(Vec::with_capacity(plain.len()), Compression::default());
+            encoder.write_all(&plain)?;
+            Zeroizing::new(encoder.finish()?)
+        } else {
+            plain
+        };
+
+        let mut master_seed = [0u8; 32];
+        let mut iv = [0u8; 12];
+        crypto::fill_random(&mut master_seed);
+        crypto::fill_random(&mut iv);
+        let (cipher_key, hmac_key) = derive_keys(&master_seed, &self.transformed_key);
+
+        ChaCha20::new(&(*cipher_key).into(), &iv.into()).apply_keystream(&mut payload);
+
+        let mut file = Vec::with_capacity(payload.len() + 512);
+        file.extend_from_slice(&SIGNATURE);
+        file.extend_from_slice(&self.minor_version.to_le_bytes());
+        file.extend_from_slice(&VERSION_MAJOR.to_le_bytes());
+        write_field(&mut file, CIPHER_ID, CIPHER_CHACHA20.as_bytes());
+        write_field(
+            &mut file,
+            COMPRESSION_FLAGS,
+            &u32::from(self.compressed).to_le_bytes(),
+        );
+        write_field(&mut file, MASTER_SEED, &master_seed);
+        write_field(&mut file, ENCRYPTION_IV, &iv);
+        write_field(
+            &mut file,
+            KDF_PARAMETERS,
+            &write_variant_map(&self.kdf.to_map()),
+        );
+        if let Some(data) = &self.public_custom_data {
+            write_field(&mut file, PUBLIC_CUSTOM_DATA, data);
+        }
+        write_field(&mut file, END_OF_HEADER, b"\r\n\r\n");
+
+        let header_hash = Sha256::digest(&file);
+        let mut mac = block_mac(&hmac_key, u64::MAX);
+        mac.update(&file);
+        file.extend_from_slice(&header_hash);
+        file.extend_from_slice(&mac.finalize().into_bytes());
+
+        // The empty block marks the end of the stream.
+        let blocks = payload.chunks(BLOCK_SIZE).chain(std::iter::once(&[][..]));
+        for (index, block) in (0u64..).zip(blocks) {
+            let len = (block.len() as u32).to_le_bytes();
+            let mut mac = block_mac(&hmac_key, index);
+            mac.update(&index.to_le_bytes());
+            mac.update(&len);
+            mac.update(block);
+            file.extend_from_slice(&mac.finalize().into_bytes());
+            file.extend_from_slice(&len);
+            file.extend_from_slice(block);
+        }
+
+        Ok(file)
+    }
+
+    /// Reads the database XML, remembering what zims does not model.
+    fn read_xml(
+        &mut self,
+        xml: &str,
+        stream: &mut ProtectedStream,
+    ) -> Result<VaultData, VaultError> {
+        let doc = Document::parse(xml)
+            .map_err(|e| VaultError::Corrupted(format!("Malformed KDBX XML: {}", e)))?;
+
+        // Protected values are encrypted with one stream in document order,
+        // so all of them are decrypted up front, history included.
+        let mut protected = HashMap::new();
+        for node in doc.descendants() {
+            if node.has_tag_name("Value") && node.attribute("Protected") == Some("True") {
+                let mut value = Zeroizing::new(
+                    BASE64
+                        .decode(node.text().unwrap_or_default().trim())
+                        .map_err(|_| {
+                            VaultError::Corrupted("Malformed KDBX protected value".to_string())
+                        })?,
+                );
+                stream.apply(&mut value);
+                let value = std::str::from_utf8(&value).map_err(|_| {
+                    VaultError::Corrupted("Malformed KDBX protected value".to_string())
+                })?;
+                protected.insert(node.id(), SecureString::new(value.to_string()));
+            }
+        }
+
+        let file = doc.root_element();
+        let mut settings = VaultSettings::default();
+        if let Some(meta) = child(file, "Meta") {
+            self.recycle_bin = child_text(meta, "RecycleBinUUID")
+                .filter(|uuid| *uuid != NULL_UUID)
+                .map(str::to_string);
+            self.meta = raw_children(
+                xml,
+                meta,
+                &["Generator", "HeaderHash", "RecycleBinUUID", "CustomData"],
+            );
+
+            for item in child(meta, "CustomData")
+                .into_iter()
+                .flat_map(|data| data.children().filter(|n| n.has_tag_name("Item")))
+            {
+                if child_text(item, "Key") == Some(SETTINGS_KEY) {
+                    settings = child_text(item, "Value")
+                        .and_then(|value| serde_json::from_str(value).ok())
+                        .unwrap_or_default();
+                } else {
+                    self.custom_data.push_str(&xml[item.range()]);
+                }
+            }
+        }
+
+        let root = child(file, "Root")
+            .ok_or_else(|| VaultError::Corrupted("KDBX XML has no root".to_string()))?;
+        let root_group = child(root, "Group")
+            .ok_or_else(|| VaultError::Corrupted("KDBX XML has no root group".to_string()))?;
+        self.deleted_objects = raw_children(xml, root, &["Group"]);
+
+        let mut entries = Vec::new();
+        self.root = self.read_group(xml, root_group, Vec::new(), &protected, &mut entries);
+        for entry in &mut entries {
+            entry.history.truncate(settings.password_history as usize);
+        }
+        let (trash, entries) = entries
+            .into_iter()
+            .partition(|entry| entry.deleted_at.is_some());
+
+        Ok(VaultData {
+            version: 1,
+            settings,
+            entries,
+            trash,
+        })
+    }
+
+    fn read_group(
+        &mut self,
+        xml: &str,
+        node: Node,
+        path: Vec<String>,
+        protected: &HashMap<NodeId, SecureString>,
+        entries: &mut Vec<PasswordEntry>,
+    ) -> Group {
+        let group = Group {
+            name: child_text(node, "Name").unwrap_or_default().to_string(),
+            uuid: child_text(node, "UUID").unwrap_or_default().to_string(),
+            extra: raw_children(xml, node, &["UUID", "Name", "Entry", "Group"]),
+            path,
+        };
+
+        // Everything in the recycle bin is trashed, including the entries
+        // of groups deleted in KeePass.
+        if self.recycle_bin.is_some() && self.recycle_bin.as_deref() == Some(&group.uuid) {
+            let trashed = node.descendants().filter(|n| {
+                n.has_tag_name("Entry") && n.parent().is_some_and(|p| p.has_tag_name("Group"))
+            });
+            for child in trashed {
+                entries.push(self.read_entry(xml, child, &[], protected, true));
+            }
+            return group;
+        }
+
+        for child in node.children().filter(Node::is_element) {
+            match child.tag_name().name() {
+                "Entry" => entries.push(self.read_entry(xml, child, &group.path, protected, false)),
+                "Group" => {
+                    let mut path = group.path.clone();
+                    path.push(child_text(child, "Name").unwrap_or_default().to_string());
+                    let subgroup = self.read_group(xml, child, path, protected, entries);
+                    self.groups.push(subgroup);
+                }
+                _ => {}
+            }
+        }
+
+        group
+    }
+
+    fn read_entry(
+        &mut self,
+        xml: &str,
+        node: Node,
+        path: &[String],
+        protected: &HashMap<NodeId, SecureString>,
+        trashed: bool,
+    ) -> PasswordEntry {
+        let now = Utc::now();
+        let mut entry = PasswordEntry {
+            id: child_text(node, "UUID")
+                .and_then(decode_uuid)
+                .unwrap_or_else(Uuid::new_v4),
+            title: String::new(),
+            username: String::new(),
+            password: SecureString::default(),
+            url: None,
+            urls: Vec::new(),
+            url_match: UrlMatch::default(),
+            notes: None,
+            totp: None,
+            kind: EntryKind::Login,
+            fields: Vec::new(),
+            history: Vec::new(),
+            tags: Vec::new(),
+            favorite: false,
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+        };
+        let mut extras = EntryExtras {
+            extra: raw_children(
+                xml,
+                node,
+                &["UUID", "Tags", "Times", "String", "History", "CustomData"],
+            ),
+            ..EntryExtras::default()
+        };
+
+        if !path.is_empty() {
+            entry.tags.push(path.join("/"));
+        }
+        for tag in child_text(node, "Tags")
+            .unwrap_or_default()
+            .split([',', ';'])
+        {
+            let tag = tag.trim();
+            if !tag.is_empty() && !entry.tags.iter().any(|t| t == tag) {
+                entry.tags.push(tag.to_string());
+            }
+        }
+
+        let mut moved_at = None;
+        if let Some(times) = child(node, "Times") {
+            let time = |name| child_text(times, name).and_then(parse_time);
+            entry.created_at = time("CreationTime").unwrap_or(now);
+            entry.updated_at = time("LastModificationTime").unwrap_or(entry.created_at);
+            moved_at = time("LocationChanged");
+            extras.times = raw_children(xml, times, &["CreationTime", "LastModificationTime"]);
+        }
+
+        for string in node.children().filter(|n| n.has_tag_name("String")) {
+            let key = child_text(string, "Key").unwrap_or_default();
+            let value_node = child(string, "Value");
+            let value = match value_node.and_then(|v| protected.get(&v.id())) {
+                Some(value) => value.clone(),
+                None => SecureString::new(
+                    value_node
+                        .and_then(|v| v.text())
+                        .unwrap_or_default()
+                        .to_string(),
+                ),
+            };
+            let non_empty =
+                |value: SecureString| Some(value.into_inner()).filter(|value| !value.is_empty());
+
+            match key {
+                TITLE => entry.title = value.into_inner(),
+                USER_NAME => entry.username = value.into_inner(),
+                PASSWORD => entry.password = value,
+                URL => entry.url = non_empty(value),
+                NOTES => entry.notes = non_empty(value),
+                OTP if !value.as_str().is_empty() => entry.totp = Some(value),
+                key if is_extra_url(key) => entry.urls.extend(non_empty(value)),
+                _ => entry.fields.push(CustomField {
+                    name: key.to_string(),
+                    kind: match value_node.and_then(|v| v.attribute("Protected")) {
+                        Some("True") => FieldKind::Hidden,
+                        _ => FieldKind::Text,
+                    },
+                    value,
+                }),
+            }
+        }
+
+        let mut meta = EntryMeta::default();
+        for item in child(node, "CustomData")
+            .into_iter()
+            .flat_map(|data| data.children().filter(|n| n.has_tag_name("Item")))
+        {
+            if child_text(item, "Key") == Some(ENTRY_KEY) {
+                meta = child_text(item, "Value")
+                    .and_then(|value| serde_json::from_str(value).ok())
+                    .unwrap_or_default();
+            } else {
+                extras.custom_data.push_str(&xml[item.range()]);
+            }
+        }
+        entry.kind = meta.kind;
+        entry.url_match = meta.url_match;
+        for field in &mut entry.fields {
+            if let Some(kind) = meta.field_kinds.get(&field.name) {
+                field.kind = *kind;
+            }
+        }
+        // KeePass records the move into the recycle bin as a location
+        // change; whichever deletion is later is the current one.
+        if trashed {
+            entry.deleted_at = Some(meta.deleted_at.max(moved_at).unwrap_or(entry.updated_at));
+        }
+
+        // KeePass snapshots an entry before each edit, oldest first. A
+        // snapshot's password was replaced when the next version of the
+        // entry was saved, if that version has a different one.
+        let snapshots: Vec<(SecureString, Option<DateTime<Utc>>)> = child(node, "History")
+            .into_iter()
+            .flat_map(|history| history.children().filter(|n| n.has_tag_name("Entry")))
+            .map(|snapshot| {
+                let modified = child(snapshot, "Times")
+                    .and_then(|times| child_text(times, "LastModificationTime"))
+                    .and_then(parse_time);
+                (read_password(snapshot, protected), modified)
+            })
+            .collect();
+        for (i, (password, _)) in snapshots.iter().enumerate() {
+            let (next, replaced_at) = match snapshots.get(i + 1) {
+                Some((next, modified)) => (next, *modified),
+                None => (&entry.password, Some(entry.updated_at)),
+            };
+            if !password.as_str().is_empty() && password != next {
+                entry.history.insert(
+                    0,
+                    PasswordHistoryItem {
+                        password: password.clone(),
+                        changed_at: replaced_at.unwrap_or(entry.updated_at),
+                    },
+                );
+            }
+        }
+
+        self.extras.insert(entry.id, extras);
+        entry
+    }
+
+    fn write_xml(&self, vault_data: &VaultData, stream: &mut ProtectedStream) -> Zeroizing<String> {
+        let mut xml = Zeroizing::new(String::with_capacity(
+            64 * 1024 + vault_data.entries.len() * 2048,
+        ));
+
+        xml.push_str("<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>\n");
+        // Index 0 is the root group, `i + 1` is `self.groups[i]`, and the
+        // last is a recycle bin zims adds if the trash needs one.
+        let mut by_group: Vec<Vec<&PasswordEntry>> = vec![Vec::new(); self.groups.len() + 2];
+        for entry in &vault_data.entries {
+            by_group[self.group_of(entry).map_or(0, |i| i + 1)].push(entry);
+        }
+        let recycle_bin = self
+            .recycle_bin
+            .as_ref()
+            .and_then(|uuid| self.groups.iter().position(|group| group.uuid == *uuid));
+        let bin_index = recycle_bin.map_or(self.groups.len() + 1, |i| i + 1);
+        by_group[bin_index].extend(&vault_data.trash);
+
+        xml.push_str("<KeePassFile><Meta><Generator>zims</Generator>");
+        xml.push_str(&self.meta);
+        xml.push_str("<RecycleBinUUID>");
+        xml.push_str(match recycle_bin {
+            Some(i) => &self.groups[i].uuid,
+            None if !vault_data.trash.is_empty() => &self.new_recycle_bin,
+            None => NULL_UUID,
+        });
+        xml.push_str("</RecycleBinUUID>");
+        xml.push_str("<CustomData>");
+        xml.push_str(&self.custom_data);
+        xml.push_str("<Item><Key>");
+        xml.push_str(SETTINGS_KEY);
+        xml.push_str("</Key><Value>");
+        push_escaped(
+            &mut xml,
+            &serde_json::to_string(&vault_data.settings).unwrap_or_default(),
+        );
+        xml.push_str("</Value></Item></CustomData></Meta><Root>");
+        self.write_group(&mut xml, None, &by_group, stream);
+
+        xml.push_str(&self.deleted_objects);
+        xml.push_str("</Root></KeePassFile>");
+        xml
+    }
+
+    /// The first existing group, other than the recycle bin, one of the
+    /// entry's tags names by its path.
+    fn group_of(&self, entry: &PasswordEntry) -> Option<usize> {
+        entry.tags.iter().find_map(|tag| {
+            self.groups.iter().position(|group| {
+                group.path.join("/") == *tag && self.recycle_bin.as_ref() != Some(&group.uuid)
+            })
+        })
+    }
+
+    fn write_group(
+        &self,
+        xml: &mut String,
+        index: Option<usize>,
+        by_group: &[Vec<&PasswordEntry>],
+        stream: &mut ProtectedStream,
+    ) {
+        let group = index.map_or(&self.root, |i| &self.groups[i]);
+
+        xml.push_str("<Group><UUID>");
+        xml.push_str(&group.uuid);
+        xml.push_str("</UUID><Name>");
+        push_escaped(xml, &group.name);
+        xml.push_str("</Name>");
+        xml.push_str(&group.extra);
+
+        let group_tag = index.map(|_| group.path.join("/"));
+        for entry in &by_group[index.map_or(0, |i| i + 1)] {
+            self.write_entry(xml, entry, group_tag.as_deref(), stream);
+        }
+
+        for (i, subgroup) in self.groups.iter().enumerate() {
+            if subgroup.path.len() == group.path.len() + 1 && subgroup.path.starts_with(&group.path)
+            {
+                self.write_group(xml, Some(i), by_group, stream);
+            }
+        }
+
+        let new_bin = &by_group[self.groups.len() + 1];
+        if index.is_none() && !new_bin.is_empty() {
+            xml.push_str("<Group><UUID>");
+            xml.push_str(&self.new_recycle_bin);
+            xml.push_str("</UUID><Name>Recycle Bin</Name><IconID>");
+            xml.push_str(&RECYCLE_BIN_ICON.to_string());
+            xml.push_str(
+                "</IconID><EnableAutoType>false</EnableAutoType>\
+                 <EnableSearching>false</EnableSearching>",
+            );
+            for entry in new_bin {
+                self.write_entry(xml, entry, None, stream);
+            }
+            xml.push_str("</Group>");
+        }
+
+        xml.push_str("</Group>");
+    }
+
+    fn write_entry(
+        &self,
+        xml: &mut String,
+        entry: &PasswordEntry,
+        group_tag: Option<&str>,
+        stream: &mut ProtectedStream,
+    ) {
+        let extras = self.extras.get(&entry.id);
+
+        xml.push_str("<Entry><UUID>");
+        xml.push_str(&encode_uuid(entry.id));
+        xml.push_str("</UUID>");
+        if let Some(extras) = extras {
+            xml.push_str(&extras.extra);
+        }
+
+        let tags: Vec<&str> = entry
+            .tags
+            .iter()
+            .map(String::as_str)
+            .filter(|tag| Some(*tag) != group_tag)
+            .collect();
+        if !tags.is_empty() {
+            xml.push_str("<Tags>");
+            push_escaped(xml, &tags.join(";"));
+            xml.push_str("</Tags>");
+        }
+
+        xml.push_str("<Times><CreationTime>");
+        xml.push_str(&format_time(entry.created_at));
+        xml.push_str("</CreationTime><LastModificationTime>");
+        xml.push_str(&format_time(entry.updated_at));
+        xml.push_str("</LastModificationTime>");
+        match extras {
+            Some(extras) => xml.push_str(&extras.times),
+            None => push_default_times(xml, entry.updated_at),
+        }
+        xml.push_str("</Times>");
+
+        write_string(xml, TITLE, &entry.title, false, stream);
+        write_string(xml, USER_NAME, &entry.username, false, stream);
+        write_string(xml, PASSWORD, entry.password.as_str(), true, stream);
+        write_string(
+            xml,
+            URL,
+            entry.url.as_deref().unwrap_or_default(),
+            false,
+            stream,
+        );
+        write_string(
+            xml,
+            NOTES,
+            entry.notes.as_deref().unwrap_or_default(),
+            false,
+            stream,
+        );
+        if let Some(totp) = &entry.totp {
+            write_string(xml, OTP, totp.as_str(), true, stream);
+        }
+        for (i, url) in entry.urls.iter().enumerate() {
+            let key = match i {
+                0 => EXTRA_URL.to_string(),
+                i => format!("{}_{}", EXTRA_URL, i),
+            };
+            write_string(xml, &key, url, false, stream);
+        }
+        let mut meta = EntryMeta {
+            kind: entry.kind,
+            url_match: entry.url_match,
+            deleted_at: entry.deleted_at,
+            ..EntryMeta::default()
+        };
+        for field in &entry.fields {
+            // KeePass keys are unique, so a custom field cannot take the
+            // name of a standard one.
+            let key = if STANDARD_KEYS.contains(&field.name.as_str()) || is_extra_url(&field.name) {
+                format!("{} (custom)", field.name)
+            } else {
+                field.name.clone()
+            };
+            write_string(
+                xml,
+                &key,
+                field.value.as_str(),
+                field.kind == FieldKind::Hidden,
+                stream,
+            );
+            if matches!(field.kind, FieldKind::Url | FieldKind::Date) {
+                meta.field_kinds.insert(key, field.kind);
+            }
+        }
+
+        let custom_data = extras.map_or("", |extras| extras.custom_data.as_str());
+        let write_meta = meta.kind != EntryKind::Login
+            || meta.url_match != UrlMatch::default()
+            || !meta.field_kinds.is_empty()
+            || meta.deleted_at.is_some();
+        if write_meta || !custom_data.is_empty() {
+            xml.push_str("<CustomData>");
+            xml.push_str(custom_data);
+            if write_meta {
+                xml.push_str("<Item><Key>");
+                xml.push_str(ENTRY_KEY);
+                xml.push_str("</Key><Value>");
+                push_escaped(xml, &serde_json::to_string(&meta).unwrap_or_default());
+                xml.push_str("</Value></Item>");
+            }
+            xml.push_str("</CustomData>");
+        }
+
+        if !entry.history.is_empty() {
+            // One snapshot per previous password, oldest first, each last
+            // modified when its password was set.
+            xml.push_str("<History>");
+            for (i, item) in entry.history.iter().enumerate().rev() {
+                let set_at = entry
+                    .history
+                    .get(i + 1)
+                    .map_or(entry.created_at, |older| older.changed_at);
+                xml.push_str("<Entry><UUID>");
+                xml.push_str(&encode_uuid(entry.id));
+                xml.push_str("</UUID><Times><CreationTime>");
+                xml.push_str(&format_time(entry.created_at));
+                xml.push_str("</CreationTime><LastModificationTime>");
+                xml.push_str(&format_time(set_at));
+                xml.push_str("</LastModificationTime>");
+                push_default_times(xml, set_at);
+                xml.push_str("</Times>");
+                write_string(xml, TITLE, &entry.title, false, stream);
+                write_string(xml, USER_NAME, &entry.username, false, stream);
+                write_string(xml, PASSWORD, item.password.as_str(), true, stream);
+                xml.push_str("</Entry>");
+            }
+            xml.push_str("</History>");
+        }
+
+        xml.push_str("</Entry>");
+    }
+}
+
+impl Kdf {
+    fn argon2id(params: KdfParams) -> Self {
+        Self {
+            algorithm: argon2::Algorithm::Argon2id,
+            version: argon2::Version::V0x13,
+            salt: crypto::generate_salt().to_vec(),
+            params,
+        }
+    }
+
+    fn from_map(map: &VariantMap) -> Result<Self, VaultError> {
+        let algorithm = match map.get("$UUID").and_then(|v| Uuid::from_slice(&v.1).ok()) {
+            Some(KDF_ARGON2D) => argon2::Algorithm::Argon2d,
+            Some(KDF_ARGON2ID) => argon2::Algorithm::Argon2id,
+            _ => {
+                return Err(VaultError::Unsupported(
+                    "Only Argon2 KDBX databases are supported; switch the key derivation \
+                     function to Argon2 in KeePass"
+                        .to_string(),
+                ))
+            }
+        };
+
+        let invalid = || VaultError::Corrupted("Malformed KDBX KDF parameters".to_string());
+        let u32_value = |key: &str| {
+            map.get(key)
+                .filter(|v| v.0 == VARIANT_U32)
+                .and_then(|v| v.1.as_slice().try_into().ok())
+                .map(u32::from_le_bytes)
+                .ok_or_else(invalid)
+        };
+        let u64_value = |key: &str| {
+            map.get(key)
+                .filter(|v| v.0 == VARIANT_U64)
+                .and_then(|v| v.1.as_slice().try_into().ok())
+                .map(u64::from_le_bytes)
+                .ok_or_else(invalid)
+        };
+
+        let out_of_range = || VaultError::Unsupported("KDF parameters out of range".to_string());
+        let params = KdfParams {
+            memory_kib: u32::try_from(u64_value("M")? / 1024).map_err(|_| out_of_range())?,
+            iterations: u32::try_from(u64_value("I")?).map_err(|_| out_of_range())?,
+            parallelism: u32_value("P")?,
+        };
+        if !params.within_limits() {
+            return Err(out_of_range());
+        }
+
+        Ok(Self {
+            algorithm,
+            version: argon2::Version::try_from(u32_value("V")?).map_err(|_| invalid())?,
+            salt: map
+                .get("S")
+                .filter(|v| v.0 == VARIANT_BYTES)
+                .map(|v| v.1.clone())
+                .ok_or_else(invalid)?,
+            params,
+        })
+    }
+
+    fn to_map(&self) -> VariantMap {
+        let uuid = match self.algorithm {
+            argon2::Algorithm::Argon2d => KDF_ARGON2D,
+            _ => KDF_ARGON2ID,
+        };
+        let memory = u64::from(self.params.memory_kib) * 1024;
+
+        VariantMap::from([
+            (
+                "$UUID".to_string(),
+                (VARIANT_BYTES, uuid.as_bytes().to_vec()),
+            ),
+            ("S".to_string(), (VARIANT_BYTES, self.salt.clone())),
+            (
+                "P".to_string(),
+                (VARIANT_U32, self.params.parallelism.to_le_bytes().to_vec()),
+            ),
+            (
+                "M".to_string(),
+                (VARIANT_U64, memory.to_le_bytes().to_vec()),
+            ),
+            (
+                "I".to_string(),
+                (
+                    VARIANT_U64,
+                    u64::from(self.params.iterations).to_le_bytes().to_vec(),
+                ),
+            ),
+            (
+                "V".to_string(),
+                (VARIANT_U32, (self.version as u32).to_le_bytes().to_vec()),
+            ),
+        ])
+    }
+
+    /// KeePass hashes each key component, hashes the concatenation into the
+    /// composite key, and runs that through the KDF. A password is the only
+    /// component here.
+    fn transform(&self, password: &str) -> Result<VaultKey, VaultError> {
+        let password_hash = Zeroizing::new(<[u8; 32]>::from(Sha256::digest(password.as_bytes())));
+        let composite = Zeroizing::new(<[u8; 32]>::from(Sha256::digest(password_hash.as_ref())));
+
+        crypto::derive_argon2(
+            self.algorithm,
+            self.version,
+            composite.as_ref(),
+            &self.salt,
+            &self.params,
+        )
+    }
+}
+
+/// Decrypts and encrypts protected values: ChaCha20 keyed with the SHA-512
+/// of the inner header's stream key, run across all values in order.
+struct ProtectedStream(ChaCha20);
+
+impl ProtectedStream {
+    fn new(stream_key: &[u8]) -> Self {
+        let hash = Zeroizing::new(<[u8; 64]>::from(Sha512::digest(stream_key)));
+        let mut key = Zeroizing::new([0u8; 32]);
+        let mut nonce = [0u8; 12];
+        key.copy_from_slice(&hash[..32]);
+        nonce.copy_from_slice(&hash[32..44]);
+        Self(ChaCha20::new(&(*key).into(), &nonce.into()))
+    }
+
+    fn apply(&mut self, data: &mut [u8]) {
+        self.0.apply_keystream(data);
+    }
+}
+
+/// The payload cipher key and the base key for the header and block HMACs.
+fn derive_keys(
+    master_seed: &[u8; 32],
+    transformed_key: &[u8; 32],
+) -> (VaultKey, Zeroizing<[u8; 64]>) {
+    let cipher_key = Sha256::new()
+        .chain_update(master_seed)
+        .chain_update(transformed_key)
+        .finalize();
+    let hmac_key = Sha512::new()
+        .chain_update(master_seed)
+        .chain_update(transformed_key)
+        .chain_update([1])
+        .finalize();
+    (
+        Zeroizing::new(cipher_key.into()),
+        Zeroizing::new(hmac_key.into()),
+    )
+}
+
+/// HMAC for block `index`; the header uses `u64::MAX`.
+fn block_mac(hmac_key: &[u8; 64], index: u64) -> HmacSha256 {
+    let key = Zeroizing::new(<[u8; 64]>::from(
+        Sha512::new()
+            .chain_update(index.to_le_bytes())
+            .chain_update(hmac_key)
+            .finalize(),
+    ));
+    HmacSha256::new_from_slice(key.as_ref()).expect("HMAC accepts any key length")
+}
+
+/// Type and value bytes by key.
+type VariantMap = BTreeMap<String, (u8, Vec<u8>)>;
+
+fn read_variant_map(data: &[u8]) -> Result<VariantMap, VaultError> {
+    let mut reader = Reader::new(data);
+    if reader.u16()? >> 8 != VARIANT_MAP_VERSION >> 8 {
+        return Err(VaultError::Unsupported(
+            "Unknown KDBX parameter format".to_string(),
+        ));
+    }
+
+    let mut map = VariantMap::new();
+    loop {
+        let kind = reader.u8()?;
+        if kind == 0 {
+            return Ok(map);
+        }
+        let len = reader.u32()? as usize;
+        let key = String::from_utf8_lossy(reader.take(len)?).into_owned();
+        let len = reader.u32()? as usize;
+        map.insert(key, (kind, reader.take(len)?.to_vec()));
+    }
+}
+
+fn write_variant_map(map: &VariantMap) -> Vec<u8> {
+    let mut data = VARIANT_MAP_VERSION.to_le_bytes().to_vec();
+    for (key, (kind, value)) in map {
+        data.push(*kind);
+        data.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        data.extend_from_slice(key.as_bytes());
+        data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        data.extend_from_slice(value);
+    }
+    data.push(0);
+    data
+}
+
+/// A type-length-value header field, as used by both headers.
+fn write_field(out: &mut Vec<u8>, id: u8, value: &[u8]) {
+    out.push(id);
+    out.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    out.extend_from_slice(value);
+}
+
+fn write_string(
+    xml: &mut String,
+    key: &str,
+    value: &str,
+    protected: bool,
+    stream: &mut ProtectedStream,
+) {
+    xml.push_str("<String><Key>");
+    push_escaped(xml, key);
+    xml.push_str("</Key>");
+    if protected {
+        let mut value = Zeroizing::new(value.as_bytes().to_vec());
+        stream.apply(&mut value);
+        xml.push_str("<Value Protected=\"True\">");
+        xml.push_str(&BASE64.encode(value.as_slice()));
+    } else {
+        xml.push_str("<Value>");
+        push_escaped(xml, value);
+    }
+    xml.push_str("</Value></String>");
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// The password string of an entry or history snapshot.
+fn read_password(node: Node, protected: &HashMap<NodeId, SecureString>) -> SecureString {
+    node.children()
+        .filter(|n| n.has_tag_name("String"))
+        .find(|string| child_text(*string, "Key") == Some(PASSWORD))
+        .and_then(|string| child(string, "Value"))
+        .map(|value| match protected.get(&value.id()) {
+            Some(password) => password.clone(),
+            None => SecureString::new(value.text().unwrap_or_default().to_string()),
+        })
+        .unwrap_or_default()
+}
+
+/// The `Times` KeePass expects on an entry zims created itself.
+fn push_default_times(xml: &mut String, time: DateTime<Utc>) {
+    let time = format_time(time);
+    xml.push_str("<LastAccessTime>");
+    xml.push_str(&time);
+    xml.push_str("</LastAccessTime><ExpiryTime>");
+    xml.push_str(&time);
+    xml.push_str(
+        "</ExpiryTime><Expires>False</Expires><UsageCount>0</UsageCount><LocationChanged>",
+    );
+    xml.push_str(&time);
+    xml.push_str("</LocationChanged>");
+}
+
+/// Escapes `text` for element content or attribute values, dropping the
+/// control characters XML 1.0 cannot represent.
+fn push_escaped(xml: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => xml.push_str("&amp;"),
+            '<' => xml.push_str("&lt;"),
+            '>' => xml.push_str("&gt;"),
+            '"' => xml.push_str("&quot;"),
+            '\'' => xml.push_str("&apos;"),
+            '\t' | '\n' | '\r' => xml.push(c),
+            c if c < ' ' => {}
+            c => xml.push(c),
+        }
+    }
+}
+
+/// The source XML of `node`'s child elements, except those named in `skip`.
+fn raw_children(xml: &str, node: Node, skip: &[&str]) -> String {
+    node.children()
+        .filter(|n| n.is_element() && !skip.contains(&n.tag_name().name()))
+        .map(|n| &xml[n.range()])
+        .collect()
+}
+
+fn encode_uuid(id: Uuid) -> String {
+    BASE64.encode(id.as_bytes())
+}
+
+fn decode_uuid(text: &str) -> Option<Uuid> {
+    Uuid::from_slice(&BASE64.decode(text.trim()).ok()?).ok()
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], VaultError> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| VaultError::Corrupted("KDBX file is truncated".to_string()))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, VaultError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, VaultError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, VaultError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const FAST_KDF: KdfParams = KdfParams {
+        memory_kib: 1024,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    fn entry(title: &str, tags: &[&str]) -> PasswordEntry {
+        let time = Utc.with_ymd_and_hms(2022, 3, 4, 5, 6, 7).unwrap();
+        PasswordEntry {
+            id: Uuid::new_v4(),
+            title: title.to_string(),
+            username: "alice".to_string(),
+            password: "<p&ss>".to_string().into(),
+            url: Some("https://example.com".to_string()),
+            urls: Vec::new(),
+            url_match: Default::default(),
+            notes: Some("line one\nline two".to_string()),
+            totp: Some(
+                "otpauth://totp/x?secret=JBSWY3DPEHPK3PXP"
+                    .to_string()
+                    .into(),
+            ),
+            kind: EntryKind::Login,
+            fields: vec![CustomField {
+                name: "Recovery".to_string(),
+                kind: FieldKind::Hidden,
+                value: "abcd efgh".to_string().into(),
+            }],
+            history: Vec::new(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            favorite: false,
+            created_at: time,
+            updated_at: time,
+            deleted_at: None,
+        }
+    }
+
+    fn vault_data(entries: Vec<PasswordEntry>) -> VaultData {
+        VaultData {
+            version: 1,
+            settings: VaultSettings {
+                backup_generations: 2,
+                auto_lock_secs: 60,
+                ..VaultSettings::default()
+            },
+            entries,
+            trash: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let db = KdbxDatabase::create("hunter2", FAST_KDF).unwrap();
+        let mut entries = vec![entry("GitHub", &["dev", "work"]), entry("Bank", &[])];
+        entries[0].urls = vec![
+            "https://gist.github.com".to_string(),
+            "https://github.dev".to_string(),
+        ];
+        entries[0].url_match = UrlMatch::Host;
+        let file = db.seal(&vault_data(entries.clone())).unwrap();
+        assert!(is_kdbx(&file));
+
+        let (db, opened) = KdbxDatabase::open(&file, "hunter2").unwrap();
+        assert_eq!(db.kdf_params(), FAST_KDF);
+        assert_eq!(opened.settings.auto_lock_secs, 60);
+        assert_eq!(opened.entries.len(), 2);
+        for (opened, original) in opened.entries.iter().zip(&entries) {
+            assert_eq!(opened.id, original.id);
+            assert_eq!(opened.title, original.title);
+            assert_eq!(opened.username, original.username);
+            assert_eq!(opened.password, original.password);
+            assert_eq!(opened.url, original.url);
+            assert_eq!(opened.urls, original.urls);
+            assert_eq!(opened.url_match, original.url_match);
+            assert_eq!(opened.notes, original.notes);
+            assert_eq!(opened.totp, original.totp);
+            assert_eq!(opened.kind, original.kind);
+            assert_eq!(opened.fields, original.fields);
+            assert_eq!(opened.tags, original.tags);
+            assert_eq!(opened.created_at, original.created_at);
+            assert_eq!(opened.updated_at, original.updated_at);
+        }
+
+        assert!(matches!(
+            KdbxDatabase::open(&file, "wrong"),
+            Err(VaultError::WrongPassword)
+        ));
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let mut db = KdbxDatabase::create("hunter2", FAST_KDF).unwrap();
+        let mut file = db.seal(&vault_data(vec![entry("A", &[])])).unwrap();
+        db.mark_saved(&file);
+        db.check_unchanged(&file).unwrap();
+
+        let last = file.len() - 40;
+        file[last] ^= 1;
+        assert!(matches!(
+            KdbxDatabase::open(&file, "hunter2"),
+            Err(VaultError::Corrupted(_))
+        ));
+        assert!(matches!(
+            db.check_unchanged(&file),
+            Err(VaultError::Conflict)
+        ));
+    }
+
+    #[test]
+    fn test_rekey_and_argon2d() {
+        let mut db = KdbxDatabase::create("old", FAST_KDF).unwrap();
+        db.kdf.algorithm = argon2::Algorithm::Argon2d;
+        db.transformed_key = db.kdf.transform("old").unwrap();
+        let file = db.seal(&vault_data(Vec::new())).unwrap();
+        let (mut db, _) = KdbxDatabase::open(&file, "old").unwrap();
+        assert_eq!(db.kdf.algorithm, argon2::Algorithm::Argon2d);
+
+        db.rekey("new", KdfParams::POLICY_FLOOR).unwrap();
+        let file = db.seal(&vault_data(Vec::new())).unwrap();
+        assert!(KdbxDatabase::open(&file, "old").is_err());
+        let (db, _) = KdbxDatabase::open(&file, "new").unwrap();
+        assert_eq!(db.kdf_params(), KdfParams::POLICY_FLOOR);
+        assert_eq!(db.kdf.algorithm, argon2::Algorithm::Argon2id);
+    }
+
+    /// A database as KeePass would write it, with groups, a protected custom
+    /// field, an attachment reference and a recycle bin.
+    fn keepass_xml(stream_key: &[u8]) -> String {
+        let mut stream = ProtectedStream::new(stream_key);
+        let mut protect = |value: &str| {
+            let mut bytes = value.as_bytes().to_vec();
+            stream.apply(&mut bytes);
+            BASE64.encode(bytes)
+        };
+        let password = protect("s3cret");
+        let pin = protect("1234");
+        let older = protect("older");
+        let deleted = protect("gone");
+
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8" standalone="yes"?>
+<KeePassFile>
+    <Meta>
+        <Generator>KeePassXC</Generator>
+        <DatabaseName>Team</DatabaseName>
+        <RecycleBinUUID>cmVjeWNsZWJpbnV1aWQxMg==</RecycleBinUUID>
+        <CustomData><Item><Key>KPXC_DECRYPTION_TIME_PREFERENCE</Key><Value>1000</Value></Item></CustomData>
+    </Meta>
+    <Root>
+        <Group>
+            <UUID>cm9vdHJvb3Ryb290cm9vdA==</UUID>
+            <Name>Root</Name>
+            <IsExpanded>True</IsExpanded>
+            <Group>
+                <UUID>d29ya3dvcmt3b3Jrd29yaw==</UUID>
+                <Name>Work</Name>
+                <Entry>
+                    <UUID>ZW50cnllbnRyeWVudHJ5MQ==</UUID>
+                    <IconID>12</IconID>
+                    <Tags>vpn</Tags>
+                    <Times>
+                        <CreationTime>gF9J2g4AAAA=</CreationTime>
+                        <LastModificationTime>gF9J2g4AAAA=</LastModificationTime>
+                        <Expires>True</Expires>
+                    </Times>
+                    <String><Key>Title</Key><Value>VPN</Value></String>
+                    <String><Key>Password</Key><Value Protected="True">{password}</Value></String>
+                    <String><Key>PIN</Key><Value Protected="True">{pin}</Value></String>
+                    <String><Key>Server</Key><Value>10.0.0.1</Value></String>
+                    <Binary><Key>cert.pem</Key><Value Ref="0" /></Binary>
+                    <CustomData><Item><Key>KPXC_LINKED</Key><Value>1</Value></Item></CustomData>
+                    <History>
+                        <Entry>
+                            <UUID>ZW50cnllbnRyeWVudHJ5MQ==</UUID>
+                            <Times><LastModificationTime>gF9J2g4AAAA=</LastModificationTime></Times>
+                            <String><Key>Title</Key><Value>VPN</Value></String>
+                            <String><Key>Password</Key><Value Protected="True">{older}</Value></String>
+                        </Entry>
+                    </History>
+                </Entry>
+            </Group>
+            <Group>
+                <UUID>cmVjeWNsZWJpbnV1aWQxMg==</UUID>
+                <Name>Recycle Bin</Name>
+                <Entry>
+                    <String><Key>Title</Key><Value>Deleted</Value></String>
+                    <String><Key>Password</Key><Value Protected="True">{deleted}</Value></String>
+                </Entry>
+            </Group>
+        </Group>
+        <DeletedObjects />
+    </Root>
+</KeePassFile>"#
+        )
+    }
+
+    #[test]
+    fn test_keeps_keepass_data() {
+        let stream_key = [7u8; 64];
+        let mut db = KdbxDatabase::create("hunter2", FAST_KDF).unwrap();
+        db.binaries
+            .push(Zeroizing::new(b"\x01certificate".to_vec()));
+        let file = db.seal_xml(&stream_key, &keepass_xml(&stream_key)).unwrap();
+
+        let (db, mut opened) = KdbxDatabase::open(&file, "hunter2").unwrap();
+        assert_eq!(opened.entries.len(), 1);
+        assert_eq!(opened.trash.len(), 1);
+        assert_eq!(opened.trash[0].password.as_str(), "gone");
+        assert!(opened.trash[0].deleted_at.is_some());
+        let vpn = &opened.entries[0];
+        assert_eq!(vpn.title, "VPN");
+        assert_eq!(vpn.password.as_str(), "s3cret");
+        assert_eq!(vpn.tags, vec!["Work", "vpn"]);
+        assert_eq!(vpn.created_at.timestamp(), 1_656_187_008);
+        assert_eq!(vpn.history.len(), 1);
+        assert_eq!(vpn.history[0].password.as_str(), "older");
+        assert_eq!(opened.settings.backup_generations, 5);
+
+        // A new entry tagged with a group's path is filed into that group.
+        opened.entries.push(entry("Wiki", &["Work"]));
+        let file = db.seal(&opened).unwrap();
+        let (db, reopened) = KdbxDatabase::open(&file, "hunter2").unwrap();
+
+        assert_eq!(reopened.entries[0].tags, vec!["Work", "vpn"]);
+        assert_eq!(reopened.entries[1].tags, vec!["Work"]);
+        assert_eq!(reopened.entries[0].history, opened.entries[0].history);
+        assert_eq!(reopened.trash[0].title, "Deleted");
+        assert_eq!(db.root.uuid, "cm9vdHJvb3Ryb290cm9vdA==");
+        assert_eq!(db.groups.len(), 2);
+        assert_eq!(db.binaries.len(), 1);
+        assert!(db.meta.contains("<DatabaseName>Team</DatabaseName>"));
+        assert!(db.custom_data.contains("KPXC_DECRYPTION_TIME_PREFERENCE"));
+
+        let extras = &db.extras[&reopened.entries[0].id];
+        assert!(extras.extra.contains("<IconID>12</IconID>"));
+        assert!(extras.extra.contains("Ref=\"0\""));
+        assert!(extras.times.contains("<Expires>True</Expires>"));
+        assert!(extras.custom_data.contains("KPXC_LINKED"));
+        let fields: Vec<(&str, FieldKind, &str)> = reopened.entries[0]
+            .fields
+            .iter()
+            .map(|f| (f.name.as_str(), f.kind, f.value.as_str()))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("PIN", FieldKind::Hidden, "1234"),
+                ("Server", FieldKind::Text, "10.0.0.1")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_entry_kinds_and_fields() {
+        let db = KdbxDatabase::create("hunter2", FAST_KDF).unwrap();
+        let mut card = entry("Visa", &[]);
+        card.kind = EntryKind::CreditCard;
+        card.fields = vec![
+            CustomField {
+                name: "Expiry".to_string(),
+                kind: FieldKind::Date,
+                value: "2029-04".to_string().into(),
+            },
+            CustomField {
+                name: "Password".to_string(),
+                kind: FieldKind::Text,
+                value: "clash".to_string().into(),
+            },
+            CustomField {
+                name: "KP2A_URL".to_string(),
+                kind: FieldKind::Text,
+                value: "not a URL".to_string().into(),
+            },
+        ];
+        let file = db.seal(&vault_data(vec![card])).unwrap();
+
+        let (_, opened) = KdbxDatabase::open(&file, "hunter2").unwrap();
+        let card = &opened.entries[0];
+        assert_eq!(card.kind, EntryKind::CreditCard);
+        assert_eq!(card.password.as_str(), "<p&ss>");
+        assert_eq!(card.field("Expiry").unwrap().kind, FieldKind::Date);
+        assert_eq!(
+            card.field("Password (custom)").unwrap().value.as_str(),
+            "clash"
+        );
+        assert!(card.field("KP2A_URL (custom)").is_some());
+        assert!(card.urls.is_empty());
+    }
+
+    #[test]
+    fn test_trash_goes_to_recycle_bin() {
+        let db = KdbxDatabase::create("hunter2", FAST_KDF).unwrap();
+        let mut data = vault_data(vec![entry("Kept", &[])]);
+        let file = db.seal(&data).unwrap();
+        let (db, opened) = KdbxDatabase::open(&file, "hunter2").unwrap();
+        assert!(opened.trash.is_empty());
+        assert!(db.recycle_bin.is_none());
+
+        // A recycle bin is added once something is trashed.
+        let mut trashed = entry("Old", &["Work"]);
+        let deleted_at = Utc.with_ymd_and_hms(2024, 2, 3, 4, 5, 6).unwrap();
+        trashed.deleted_at = Some(deleted_at);
+        data.trash.push(trashed.clone());
+        let file = db.seal(&data).unwrap();
+        let bin = db.new_recycle_bin.clone();
+
+        let (db, opened) = KdbxDatabase::open(&file, "hunter2").unwrap();
+        assert_eq!(opened.entries.len(), 1);
+        assert_eq!(opened.trash.len(), 1);
+        assert_eq!(opened.trash[0].id, trashed.id);
+        assert_eq!(opened.trash[0].tags, vec!["Work"]);
+        assert_eq!(opened.trash[0].deleted_at, Some(deleted_at));
+        assert_eq!(db.recycle_bin, Some(bin));
+        assert_eq!(db.groups.len(), 1);
+    }
+
+    #[test]
+    fn test_password_history() {
+        let db = KdbxDatabase::create("hunter2", FAST_KDF).unwrap();
+        let mut login = entry("GitHub", &[]);
+        let time = |day| Utc.with_ymd_and_hms(2023, 1, day, 0, 0, 0).unwrap();
+        login.history = vec![
+            PasswordHistoryItem {
+                password: "second".to_string().into(),
+                changed_at: time(3),
+            },
+            PasswordHistoryItem {
+                password: "first".to_string().into(),
+                changed_at: time(2),
+            },
+        ];
+        login.updated_at = time(3);
+        let mut data = vault_data(vec![login.clone()]);
+        let file = db.seal(&data).unwrap();
+
+        let (db, opened) = KdbxDatabase::open(&file, "hunter2").unwrap();
+        assert_eq!(opened.entries[0].history, login.history);
+        assert_eq!(opened.entries[0].password, login.password);
+        assert_eq!(opened.entries[0].fields, login.fields);
+
+        // Histories longer than the vault allows are cut when opened.
+        data.settings.password_history = 1;
+        let file = db.seal(&data).unwrap();
+        let (_, opened) = KdbxDatabase::open(&file, "hunter2").unwrap();
+        assert_eq!(opened.entries[0].history, login.history[..1]);
+    }
+
+    #[test]
+    fn test_rejects_other_files() {
+        assert!(!is_kdbx(b"ZIMS"));
+        assert!(matches!(
+            KdbxDatabase::open(&SIGNATURE, "pw"),
+            Err(VaultError::Corrupted(_))
+        ));
+
+        let mut kdbx3 = SIGNATURE.to_vec();
+        kdbx3.extend_from_slice(&[1, 0, 3, 0]);
+        assert!(matches!(
+            KdbxDatabase::open(&kdbx3, "pw"),
+            Err(VaultError::VersionUnsupported(3))
+        ));
+    }
+}
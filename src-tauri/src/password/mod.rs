@@ -1,7 +1,24 @@
+#[cfg(feature = "gui")]
+pub mod autolock;
+pub mod backend;
+#[cfg(feature = "gui")]
+pub mod commands;
 pub mod crypto;
+pub mod error;
+pub mod export;
+pub mod fields;
+pub mod generator;
+pub mod import;
+pub mod kdbx;
+pub mod registry;
+pub mod rules;
+pub mod search;
 pub mod storage;
+pub mod totp;
 pub mod types;
+pub mod urls;
 pub mod vault;
 
+pub use error::VaultError;
 pub use types::{PasswordEntry, PasswordOptions, StrengthScore};
 pub use vault::Vault;
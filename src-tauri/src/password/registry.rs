@@ -0,0 +1,610 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use uuid::Uuid;
+
+use super::backend::StorageKind;
+use super::crypto::KdfParams;
+use super::error::VaultError;
+use super::storage::{self, LEGACY_VAULT_FILE_NAME};
+use super::vault::Vault;
+
+const REGISTRY_FILE_NAME: &str = "vaults.json";
+const VAULTS_DIR_NAME: &str = "vaults";
+
+/// Name given to the vault of earlier versions when it is first registered.
+const LEGACY_VAULT_NAME: &str = "Personal";
+
+/// A vault known to the app. The registry only records where vaults are;
+/// nothing in it is secret.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VaultRecord {
+    pub id: Uuid,
+    pub name: String,
+    pub path: PathBuf,
+    #[serde(default)]
+    pub storage_kind: StorageKind,
+    pub created_at: DateTime<Utc>,
+    pub last_opened: Option<DateTime<Utc>>,
+}
+
+/// What the vault switcher shows for each vault.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VaultInfo {
+    #[serde(flatten)]
+    pub record: VaultRecord,
+    pub unlocked: bool,
+    pub active: bool,
+}
+
+/// The list of vaults, kept in `vaults.json` in the app data directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct VaultRegistry {
+    pub vaults: Vec<VaultRecord>,
+}
+
+impl VaultRegistry {
+    pub fn path(data_dir: &Path) -> PathBuf {
+        data_dir.join(REGISTRY_FILE_NAME)
+    }
+
+    /// Reads the registry from `data_dir`. Without one, a vault left at the
+    /// old single-vault location is registered, and the registry written, so
+    /// that it keeps working.
+    pub fn load(data_dir: &Path) -> Result<Self, VaultError> {
+        match fs::read(Self::path(data_dir)) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let mut registry = Self::default();
+                let legacy = data_dir.join(LEGACY_VAULT_FILE_NAME);
+                if legacy.is_file() {
+                    registry.vaults.push(VaultRecord {
+                        id: Uuid::new_v4(),
+                        name: LEGACY_VAULT_NAME.to_string(),
+                        path: legacy,
+                        storage_kind: StorageKind::File,
+                        created_at: Utc::now(),
+                        last_opened: None,
+                    });
+                    registry.save(data_dir)?;
+                }
+                Ok(registry)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Replaces `vaults.json` via a temporary file, so a crash leaves either
+    /// the old or the new list.
+    pub fn save(&self, data_dir: &Path) -> Result<(), VaultError> {
+        fs::create_dir_all(data_dir)?;
+        let path = Self::path(data_dir);
+        let tmp_path = path.with_extension("json.tmp");
+
+        let data = serde_json::to_vec_pretty(self)?;
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        drop(file);
+
+        fs::rename(&tmp_path, &path)?;
+        storage::sync_parent_dir(&path)
+    }
+
+    pub fn get(&self, id: Uuid) -> Result<&VaultRecord, VaultError> {
+        self.vaults
+            .iter()
+            .find(|v| v.id == id)
+            .ok_or_else(|| VaultError::NotFound("Vault".to_string()))
+    }
+
+    fn get_mut(&mut self, id: Uuid) -> Result<&mut VaultRecord, VaultError> {
+        self.vaults
+            .iter_mut()
+            .find(|v| v.id == id)
+            .ok_or_else(|| VaultError::NotFound("Vault".to_string()))
+    }
+
+    /// The vault opened most recently, falling back to the first one.
+    pub fn most_recent(&self) -> Option<&VaultRecord> {
+        self.vaults
+            .iter()
+            .filter(|v| v.last_opened.is_some())
+            .max_by_key(|v| v.last_opened)
+            .or_else(|| self.vaults.first())
+    }
+
+    /// Names are trimmed, must not be empty and must be unique ignoring
+    /// case, so vaults can be told apart in the switcher.
+    fn check_name(&self, name: &str, except: Option<Uuid>) -> Result<String, VaultError> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(VaultError::InvalidInput(
+                "Vault name must not be empty".to_string(),
+            ));
+        }
+        let taken = self
+            .vaults
+            .iter()
+            .any(|v| Some(v.id) != except && v.name.eq_ignore_ascii_case(name));
+        if taken {
+            return Err(VaultError::AlreadyExists);
+        }
+        Ok(name.to_string())
+    }
+
+    /// Returns `path` in canonical form, so that one vault cannot be
+    /// registered twice under different spellings of its path.
+    fn check_path(&self, path: &Path) -> Result<PathBuf, VaultError> {
+        let path = canonical_path(path);
+        if self.vaults.iter().any(|v| canonical_path(&v.path) == path) {
+            return Err(VaultError::AlreadyExists);
+        }
+        Ok(path)
+    }
+}
+
+/// `path` with symlinks, `.` and `..` resolved. A vault that does not exist
+/// yet is resolved through its parent directory.
+fn canonical_path(path: &Path) -> PathBuf {
+    if let Ok(path) = fs::canonicalize(path) {
+        return path;
+    }
+    let resolved = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = if parent.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                parent
+            };
+            fs::canonicalize(parent)
+                .map(|parent| parent.join(name))
+                .ok()
+        }
+        _ => None,
+    };
+    resolved
+        .or_else(|| std::path::absolute(path).ok())
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Every registered vault and, for those that have been opened, its `Vault`.
+/// Each vault has its own key and lock, so several can be unlocked at once;
+/// the entry commands act on the active one.
+pub struct VaultState {
+    data_dir: PathBuf,
+    registry: VaultRegistry,
+    open: HashMap<Uuid, Vault>,
+    active: Option<Uuid>,
+}
+
+impl VaultState {
+    /// Loads the registry in `data_dir` and makes the most recently opened
+    /// vault active.
+    pub fn load(data_dir: impl Into<PathBuf>) -> Result<Self, VaultError> {
+        let data_dir = data_dir.into();
+        let registry = VaultRegistry::load(&data_dir)?;
+        let active = registry.most_recent().map(|v| v.id);
+        Ok(Self {
+            data_dir,
+            registry,
+            open: HashMap::new(),
+            active,
+        })
+    }
+
+    /// Like `load`, except that a `vaults.json` that cannot be read does not
+    /// keep the app from starting: it is moved aside, with a timestamp, and
+    /// the registry starts out empty. The vaults themselves are untouched and
+    /// can be added again by path. Returns the error recovered from, if any.
+    pub fn load_or_reset(data_dir: impl Into<PathBuf>) -> (Self, Option<VaultError>) {
+        let data_dir = data_dir.into();
+        let error = match Self::load(data_dir.clone()) {
+            Ok(state) => return (state, None),
+            Err(e) => e,
+        };
+
+        let path = VaultRegistry::path(&data_dir);
+        let stamp = Utc::now().format("%Y%m%d%H%M%S");
+        let bad = path.with_extension(format!("json.{}.bad", stamp));
+        let _ = fs::rename(&path, bad);
+        let state = Self::load(data_dir.clone()).unwrap_or_else(|_| Self {
+            data_dir,
+            registry: VaultRegistry::default(),
+            open: HashMap::new(),
+            active: None,
+        });
+        (state, Some(error))
+    }
+
+    pub fn registry(&self) -> &VaultRegistry {
+        &self.registry
+    }
+
+    pub fn active_id(&self) -> Option<Uuid> {
+        self.active
+    }
+
+    pub fn list(&self) -> Vec<VaultInfo> {
+        self.registry
+            .vaults
+            .iter()
+            .map(|record| VaultInfo {
+                record: record.clone(),
+                unlocked: self.open.get(&record.id).is_some_and(Vault::is_unlocked),
+                active: self.active == Some(record.id),
+            })
+            .collect()
+    }
+
+    /// The vault with `id`, opened (still locked) if it was not already.
+    pub fn vault(&mut self, id: Uuid) -> Result<&mut Vault, VaultError> {
+        let record = self.registry.get(id)?;
+        let kind = record.storage_kind;
+        let path = record.path.clone();
+        Ok(self
+            .open
+            .entry(id)
+            .or_insert_with(|| Vault::with_storage(kind.open(path))))
+    }
+
+    /// The vault selected in the switcher; `NotFound` if there is none.
+    pub fn active(&mut self) -> Result<&mut Vault, VaultError> {
+        let id = self
+            .active
+            .ok_or_else(|| VaultError::NotFound("Vault".to_string()))?;
+        self.vault(id)
+    }
+
+    /// Creates a new vault, registers it and makes it active, unlocked.
+    /// Without `path` it goes into the app's `vaults` directory.
+    pub fn create(
+        &mut self,
+        name: &str,
+        master_password: &str,
+        kdf_params: KdfParams,
+        storage_kind: StorageKind,
+        path: Option<PathBuf>,
+    ) -> Result<Uuid, VaultError> {
+        let name = self.registry.check_name(name, None)?;
+        let id = Uuid::new_v4();
+        let path = path.unwrap_or_else(|| {
+            let dir = self.data_dir.join(VAULTS_DIR_NAME);
+            match storage_kind {
+                StorageKind::File => dir.join(format!("{}.encrypted", id)),
+                StorageKind::Directory => dir.join(id.to_string()),
+            }
+        });
+        let path = self.registry.check_path(&path)?;
+
+        let mut vault = Vault::with_storage(storage_kind.open(path.clone()));
+        vault.create(master_password, kdf_params)?;
+
+        let now = Utc::now();
+        self.registry.vaults.push(VaultRecord {
+            id,
+            name,
+            path,
+            storage_kind,
+            created_at: now,
+            last_opened: Some(now),
+        });
+        if let Err(e) = self.registry.save(&self.data_dir) {
+            self.registry.vaults.pop();
+            return Err(e);
+        }
+
+        self.open.insert(id, vault);
+        self.active = Some(id);
+        Ok(id)
+    }
+
+    /// Registers a vault that already exists at `path`, e.g. one copied from
+    /// another machine. It stays locked until unlocked.
+    pub fn add_existing(&mut self, name: &str, path: PathBuf) -> Result<Uuid, VaultError> {
+        let name = self.registry.check_name(name, None)?;
+        let path = self.registry.check_path(&path)?;
+
+        let storage_kind = StorageKind::detect(&path);
+        if !storage_kind.open(path.clone()).exists() {
+            return Err(VaultError::NotFound("Vault file".to_string()));
+        }
+
+        let id = Uuid::new_v4();
+        self.registry.vaults.push(VaultRecord {
+            id,
+            name,
+            path,
+            storage_kind,
+            created_at: Utc::now(),
+            last_opened: None,
+        });
+        if let Err(e) = self.registry.save(&self.data_dir) {
+            self.registry.vaults.pop();
+            return Err(e);
+        }
+        Ok(id)
+    }
+
+    /// Switches to vault `id`. Other unlocked vaults stay unlocked.
+    pub fn open(&mut self, id: Uuid) -> Result<&mut Vault, VaultError> {
+        self.registry.get_mut(id)?.last_opened = Some(Utc::now());
+        self.registry.save(&self.data_dir)?;
+        self.active = Some(id);
+        self.vault(id)
+    }
+
+    /// Locks vault `id` and forgets it, deselecting it if it was active.
+    pub fn close(&mut self, id: Uuid) -> Result<(), VaultError> {
+        self.registry.get(id)?;
+        if let Some(mut vault) = self.open.remove(&id) {
+            vault.lock();
+        }
+        if self.active == Some(id) {
+            self.active = None;
+        }
+        Ok(())
+    }
+
+    pub fn rename(&mut self, id: Uuid, name: &str) -> Result<(), VaultError> {
+        let name = self.registry.check_name(name, Some(id))?;
+        let record = self.registry.get_mut(id)?;
+        let old = std::mem::replace(&mut record.name, name);
+        if let Err(e) = self.registry.save(&self.data_dir) {
+            self.registry.get_mut(id)?.name = old;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Takes vault `id` out of the registry, with its quick-unlock
+    /// credential, which is kept by registry id. With `delete_files` its data
+    /// and backups are deleted as well, which is only allowed while it is
+    /// unlocked so the password has been proven.
+    pub fn remove(&mut self, id: Uuid, delete_files: bool) -> Result<(), VaultError> {
+        let vault = self.vault(id)?;
+        if delete_files {
+            vault.ensure_unlocked()?;
+            // Locking releases the storage lock before its files go.
+            vault.lock();
+            vault.storage().delete()?;
+        } else {
+            vault.storage().delete_quick_unlock()?;
+        }
+        // Best-effort, as the keyring may be unavailable.
+        if let Ok(device_key) = storage::device_key_entry(id) {
+            let _ = storage::delete_credential(&device_key);
+        }
+
+        self.close(id)?;
+        let index = self
+            .registry
+            .vaults
+            .iter()
+            .position(|v| v.id == id)
+            .ok_or_else(|| VaultError::NotFound("Vault".to_string()))?;
+        self.registry.vaults.remove(index);
+        self.registry.save(&self.data_dir)
+    }
+
+    /// Locks every unlocked vault that has been idle past its auto-lock
+    /// setting and returns their ids.
+    pub fn lock_expired(&mut self, now: Instant) -> Vec<Uuid> {
+        self.lock_where(|vault| vault.idle_expired(now))
+    }
+
+    /// Locks every unlocked vault and returns their ids.
+    pub fn lock_all(&mut self) -> Vec<Uuid> {
+        self.lock_where(|_| true)
+    }
+
+    fn lock_where(&mut self, mut pred: impl FnMut(&Vault) -> bool) -> Vec<Uuid> {
+        let mut locked = Vec::new();
+        for (id, vault) in self.open.iter_mut() {
+            if vault.is_unlocked() && pred(vault) {
+                vault.lock();
+                locked.push(*id);
+            }
+        }
+        locked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::password::types::EntryInput;
+
+    const KDF: KdfParams = KdfParams::POLICY_FLOOR;
+
+    fn input(title: &str) -> EntryInput {
+        EntryInput {
+            title: title.to_string(),
+            password: "s3cret".to_string().into(),
+            ..EntryInput::default()
+        }
+    }
+
+    #[test]
+    fn test_vaults_are_independent() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = VaultState::load(dir.path()).unwrap();
+        assert!(state.active().is_err());
+
+        let personal = state
+            .create("Personal", "one", KDF, StorageKind::File, None)
+            .unwrap();
+        state.active().unwrap().add_entry(input("Bank")).unwrap();
+
+        let work = state
+            .create("Work", "two", KDF, StorageKind::Directory, None)
+            .unwrap();
+        assert_eq!(state.active_id(), Some(work));
+        state.active().unwrap().add_entry(input("VPN")).unwrap();
+
+        // Both stay unlocked, each with its own entries.
+        let info = state.list();
+        assert!(info.iter().all(|v| v.unlocked));
+        state.open(personal).unwrap();
+        assert_eq!(state.active().unwrap().entries().unwrap()[0].title, "Bank");
+        assert_eq!(
+            state.vault(work).unwrap().entries().unwrap()[0].title,
+            "VPN"
+        );
+
+        let mut locked = state.lock_all();
+        locked.sort();
+        let mut both = vec![personal, work];
+        both.sort();
+        assert_eq!(locked, both);
+
+        // A fresh state sees both vaults and reopens the last one used.
+        drop(state);
+        let mut state = VaultState::load(dir.path()).unwrap();
+        assert_eq!(state.registry().vaults.len(), 2);
+        assert_eq!(state.active_id(), Some(personal));
+        assert!(matches!(
+            state.vault(work).unwrap().unlock("one"),
+            Err(VaultError::WrongPassword)
+        ));
+        state.vault(work).unwrap().unlock("two").unwrap();
+    }
+
+    #[test]
+    fn test_names_are_unique() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = VaultState::load(dir.path()).unwrap();
+        let personal = state
+            .create("Personal", "one", KDF, StorageKind::File, None)
+            .unwrap();
+        let work = state
+            .create("Work", "two", KDF, StorageKind::File, None)
+            .unwrap();
+
+        assert!(matches!(
+            state.create("personal ", "x", KDF, StorageKind::File, None),
+            Err(VaultError::AlreadyExists)
+        ));
+        assert!(matches!(
+            state.rename(work, "PERSONAL"),
+            Err(VaultError::AlreadyExists)
+        ));
+        assert!(matches!(
+            state.rename(work, "  "),
+            Err(VaultError::InvalidInput(_))
+        ));
+        state.rename(personal, "Home").unwrap();
+
+        let state = VaultState::load(dir.path()).unwrap();
+        assert_eq!(state.registry().get(personal).unwrap().name, "Home");
+    }
+
+    #[test]
+    fn test_paths_are_unique() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = VaultState::load(dir.path()).unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        let path = dir.path().join("sub").join("..").join("shared.encrypted");
+        let id = state
+            .create("Shared", "one", KDF, StorageKind::File, Some(path))
+            .unwrap();
+        let stored = state.registry().get(id).unwrap().path.clone();
+        assert_eq!(stored, fs::canonicalize(&stored).unwrap());
+
+        assert!(matches!(
+            state.add_existing("Again", dir.path().join(".").join("shared.encrypted")),
+            Err(VaultError::AlreadyExists)
+        ));
+        assert!(matches!(
+            state.create(
+                "Again",
+                "two",
+                KDF,
+                StorageKind::File,
+                Some(dir.path().join("shared.encrypted"))
+            ),
+            Err(VaultError::AlreadyExists)
+        ));
+    }
+
+    #[test]
+    fn test_remove_vault() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = VaultState::load(dir.path()).unwrap();
+        let id = state
+            .create("Work", "two", KDF, StorageKind::Directory, None)
+            .unwrap();
+        let path = state.registry().get(id).unwrap().path.clone();
+
+        state.close(id).unwrap();
+        assert_eq!(state.active_id(), None);
+        assert!(matches!(state.remove(id, true), Err(VaultError::Locked)));
+
+        state.vault(id).unwrap().unlock("two").unwrap();
+        state.remove(id, true).unwrap();
+        assert!(!path.exists());
+        assert!(state.registry().vaults.is_empty());
+
+        // Removing without deleting leaves the data in place.
+        let id = state
+            .create("Kept", "three", KDF, StorageKind::File, None)
+            .unwrap();
+        let path = state.registry().get(id).unwrap().path.clone();
+        keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+        let device_key = storage::device_key_entry(id).unwrap();
+        state
+            .active()
+            .unwrap()
+            .enable_quick_unlock(&device_key)
+            .unwrap();
+        state.remove(id, false).unwrap();
+        assert!(path.exists());
+        assert!(!storage::quick_unlock_path(&path).exists());
+
+        let readded = state.add_existing("Kept", path).unwrap();
+        state.open(readded).unwrap().unlock("three").unwrap();
+    }
+
+    #[test]
+    fn test_unreadable_registry_is_moved_aside() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(VaultRegistry::path(dir.path()), b"{ not json").unwrap();
+        assert!(VaultState::load(dir.path()).is_err());
+
+        let (mut state, error) = VaultState::load_or_reset(dir.path());
+        assert!(error.is_some());
+        assert!(state.registry().vaults.is_empty());
+        let moved: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.starts_with("vaults.json.") && name.ends_with(".bad"))
+            .collect();
+        assert_eq!(moved.len(), 1);
+        assert_eq!(fs::read(dir.path().join(&moved[0])).unwrap(), b"{ not json");
+
+        state
+            .create("Personal", "one", KDF, StorageKind::File, None)
+            .unwrap();
+        let (state, error) = VaultState::load_or_reset(dir.path());
+        assert!(error.is_none());
+        assert_eq!(state.registry().vaults.len(), 1);
+    }
+
+    #[test]
+    fn test_legacy_vault_is_registered() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut legacy = Vault::new(dir.path().join(LEGACY_VAULT_FILE_NAME));
+        legacy.create("hunter2", KDF).unwrap();
+        legacy.lock();
+
+        let mut state = VaultState::load(dir.path()).unwrap();
+        let record = &state.registry().vaults[0];
+        assert_eq!(record.name, LEGACY_VAULT_NAME);
+        assert_eq!(state.active_id(), Some(record.id));
+        state.active().unwrap().unlock("hunter2").unwrap();
+    }
+}
@@ -0,0 +1,289 @@
+//! Apple's password rules syntax, as used by the `passwordrules` HTML
+//! attribute and shared site quirks lists: properties separated by `;`,
+//! e.g. `minlength: 8; maxlength: 24; required: lower, upper; required:
+//! digit; allowed: [-().&@?'#,/"+]; max-consecutive: 2;`.
+//!
+//! `required` and `allowed` take a comma-separated list of `upper`,
+//! `lower`, `digit`, `special` (ASCII punctuation and space), `ascii-printable`,
+//! `unicode` and custom classes in brackets, where `-` has to come first
+//! and `]` last. Each `required` property asks for at least one character
+//! from its list. zims only generates ASCII, so `unicode` is read as
+//! `ascii-printable`, as is an empty rule set.
+//!
+//! Rules for some well-known sites ship in `password_rules.json`, a subset
+//! of the quirks in Apple's password-manager-resources (MIT) in the same
+//! format. The vault settings can add or override rules per site.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use super::error::VaultError;
+
+pub const UPPER: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+pub const LOWER: &str = "abcdefghijklmnopqrstuvwxyz";
+pub const DIGIT: &str = "0123456789";
+
+static SITE_PRESETS: &str = include_str!("password_rules.json");
+
+#[derive(Deserialize)]
+struct SitePreset {
+    #[serde(rename = "password-rules")]
+    password_rules: String,
+}
+
+/// The bundled rules for `domain`, which they cover with its subdomains;
+/// see `urls::site_domains`.
+pub fn site_preset(domain: &str) -> Option<&'static str> {
+    static PRESETS: OnceLock<HashMap<String, SitePreset>> = OnceLock::new();
+    PRESETS
+        .get_or_init(|| serde_json::from_str(SITE_PRESETS).expect("bundled password rules parse"))
+        .get(domain)
+        .map(|preset| preset.password_rules.as_str())
+}
+
+/// Password rules read from their text form; see the module docs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PasswordRules {
+    /// Sets the password needs at least one character of each from.
+    pub required: Vec<Vec<char>>,
+    /// Every character the password may use, including the required ones.
+    pub allowed: Vec<char>,
+    pub max_consecutive: Option<u32>,
+    pub min_length: Option<u32>,
+    pub max_length: Option<u32>,
+}
+
+impl PasswordRules {
+    pub fn parse(text: &str) -> Result<Self, VaultError> {
+        let mut rules = PasswordRules::default();
+        let mut allowed: Vec<char> = Vec::new();
+
+        let mut rest = text.trim();
+        while !rest.is_empty() {
+            let (name, value) = rest
+                .split_once(':')
+                .ok_or_else(|| invalid(format!("'{}' is not a rule", rest)))?;
+            let end = value_end(value);
+            rest = value[end..].trim_start_matches(';').trim_start();
+            let value = value[..end].trim();
+
+            let number = || {
+                value
+                    .parse::<u32>()
+                    .map_err(|_| invalid(format!("'{}' is not a number", value)))
+            };
+            match name.trim().to_ascii_lowercase().as_str() {
+                "required" => {
+                    let set = parse_classes(value)?;
+                    allowed.extend(&set);
+                    rules.required.push(set);
+                }
+                "allowed" => allowed.extend(parse_classes(value)?),
+                "max-consecutive" => {
+                    let max = number()?;
+                    rules.max_consecutive = Some(rules.max_consecutive.map_or(max, |m| m.min(max)));
+                }
+                "minlength" => {
+                    let min = number()?;
+                    rules.min_length = Some(rules.min_length.map_or(min, |m| m.max(min)));
+                }
+                "maxlength" => {
+                    let max = number()?;
+                    rules.max_length = Some(rules.max_length.map_or(max, |m| m.min(max)));
+                }
+                name => return Err(invalid(format!("Unknown password rule '{}'", name))),
+            }
+        }
+
+        if allowed.is_empty() {
+            allowed = ascii_printable();
+        }
+        rules.allowed = dedup(allowed);
+        rules.required = rules.required.into_iter().map(dedup).collect();
+
+        if rules.max_consecutive == Some(0) {
+            return Err(invalid("max-consecutive has to be at least 1".to_string()));
+        }
+        if let (Some(min), Some(max)) = (rules.min_length, rules.max_length) {
+            if min > max {
+                return Err(invalid(format!(
+                    "minlength {} is more than maxlength {}",
+                    min, max
+                )));
+            }
+        }
+        Ok(rules)
+    }
+}
+
+/// ASCII punctuation and space, which Apple's rules call `special`.
+pub fn special() -> Vec<char> {
+    (' '..='~')
+        .filter(|c| *c == ' ' || c.is_ascii_punctuation())
+        .collect()
+}
+
+fn ascii_printable() -> Vec<char> {
+    (' '..='~').collect()
+}
+
+/// Where a property's value ends: at the first `;` outside a custom class.
+fn value_end(value: &str) -> usize {
+    let mut i = 0;
+    while i < value.len() {
+        match value.as_bytes()[i] {
+            b';' => return i,
+            b'[' => match class_end(&value[i + 1..]) {
+                Some(end) => i += end + 2,
+                None => return value.len(),
+            },
+            _ => i += 1,
+        }
+    }
+    value.len()
+}
+
+/// The offset of the `]` closing a custom class whose text follows `[`. A
+/// `]` in the class comes last, so `[ab]]` is `a`, `b` and `]`.
+fn class_end(class: &str) -> Option<usize> {
+    let bytes = class.as_bytes();
+    (0..bytes.len()).find(|&i| bytes[i] == b']' && bytes.get(i + 1) != Some(&b']'))
+}
+
+fn parse_classes(value: &str) -> Result<Vec<char>, VaultError> {
+    let mut chars = Vec::new();
+    let mut rest = value.trim();
+    while !rest.is_empty() {
+        if let Some(class) = rest.strip_prefix('[') {
+            let end = class_end(class)
+                .ok_or_else(|| invalid(format!("Unclosed character class '{}'", rest)))?;
+            chars.extend(class[..end].chars().filter(|c| !c.is_control()));
+            rest = &class[end + 1..];
+        } else {
+            let (name, tail) = rest.split_once(',').unwrap_or((rest, ""));
+            match name.trim().to_ascii_lowercase().as_str() {
+                "upper" => chars.extend(UPPER.chars()),
+                "lower" => chars.extend(LOWER.chars()),
+                "digit" => chars.extend(DIGIT.chars()),
+                "special" => chars.extend(special()),
+                "ascii-printable" | "unicode" => chars.extend(ascii_printable()),
+                name => return Err(invalid(format!("Unknown character class '{}'", name))),
+            }
+            rest = tail;
+        }
+        rest = rest.trim_start();
+        rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+    }
+    Ok(chars)
+}
+
+fn dedup(chars: Vec<char>) -> Vec<char> {
+    let mut kept = Vec::with_capacity(chars.len());
+    for c in chars {
+        if !kept.contains(&c) {
+            kept.push(c);
+        }
+    }
+    kept
+}
+
+fn invalid(message: String) -> VaultError {
+    VaultError::InvalidInput(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        let rules = PasswordRules::parse(
+            "minlength: 8; maxlength: 24; required: lower, upper; required: digit; \
+             allowed: [-().&@?'#,/\"+]; max-consecutive: 2",
+        )
+        .unwrap();
+        assert_eq!(rules.min_length, Some(8));
+        assert_eq!(rules.max_length, Some(24));
+        assert_eq!(rules.max_consecutive, Some(2));
+        assert_eq!(rules.required.len(), 2);
+        assert_eq!(rules.required[0], chars(&format!("{}{}", LOWER, UPPER)));
+        assert_eq!(rules.required[1], chars(DIGIT));
+        assert_eq!(
+            rules.allowed,
+            chars(&format!("{}{}{}-().&@?'#,/\"+", LOWER, UPPER, DIGIT))
+        );
+    }
+
+    #[test]
+    fn test_custom_classes() {
+        // `;` and `,` inside a class do not end it, and `]` comes last.
+        let rules = PasswordRules::parse("REQUIRED: [;,]]; Allowed: [-ab], DIGIT").unwrap();
+        assert_eq!(rules.required, vec![chars(";,]")]);
+        assert_eq!(rules.allowed, chars(";,]-ab0123456789"));
+
+        // Repeated limits keep the strictest.
+        let rules =
+            PasswordRules::parse("maxlength: 30; maxlength: 20; minlength: 4; minlength: 6")
+                .unwrap();
+        assert_eq!((rules.min_length, rules.max_length), (Some(6), Some(20)));
+    }
+
+    #[test]
+    fn test_site_presets() {
+        let presets: HashMap<String, SitePreset> = serde_json::from_str(SITE_PRESETS).unwrap();
+        assert!(!presets.is_empty());
+        for (site, preset) in &presets {
+            let rules = PasswordRules::parse(&preset.password_rules)
+                .unwrap_or_else(|e| panic!("{}: {}", site, e));
+            assert!(!rules.allowed.is_empty(), "{}", site);
+        }
+
+        let paypal = PasswordRules::parse(site_preset("paypal.com").unwrap()).unwrap();
+        assert_eq!(paypal.max_length, Some(20));
+        assert_eq!(paypal.max_consecutive, Some(3));
+        assert_eq!(site_preset("www.paypal.com"), None);
+    }
+
+    #[test]
+    fn test_special_includes_space() {
+        let special = special();
+        assert_eq!(special.len(), 33);
+        assert!(special.contains(&' '));
+        assert!(special.iter().all(|c| !c.is_ascii_alphanumeric()));
+    }
+
+    #[test]
+    fn test_defaults_and_errors() {
+        let rules = PasswordRules::parse("").unwrap();
+        assert!(rules.required.is_empty());
+        assert_eq!(rules.allowed.len(), 95);
+        assert_eq!(
+            PasswordRules::parse("required: unicode")
+                .unwrap()
+                .allowed
+                .len(),
+            95
+        );
+
+        for text in [
+            "required: emoji",
+            "color: red",
+            "minlength: many",
+            "allowed: [abc",
+            "required",
+            "max-consecutive: 0",
+            "minlength: 10; maxlength: 8",
+        ] {
+            assert!(
+                matches!(PasswordRules::parse(text), Err(VaultError::InvalidInput(_))),
+                "{}",
+                text
+            );
+        }
+    }
+}
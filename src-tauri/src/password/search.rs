@@ -0,0 +1,301 @@
+//! Ranked fuzzy search over entries, for the command palette. A query is
+//! split on whitespace and every term has to match somewhere: as a fuzzy
+//! subsequence of the title, a URL's host, username or a tag, or as a plain
+//! substring of the notes. Matches in the title count most, and matches at
+//! the start of a word or in one run count more than scattered letters.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::import;
+use super::types::{EntryKind, PasswordEntry, PasswordEntrySummary};
+use super::urls;
+
+const TITLE_WEIGHT: u32 = 4;
+const HOST_WEIGHT: u32 = 3;
+const USERNAME_WEIGHT: u32 = 2;
+const TAG_WEIGHT: u32 = 2;
+const NOTES_WEIGHT: u32 = 1;
+
+// Per-character scores for a fuzzy match.
+const MATCH: u32 = 2;
+const CONSECUTIVE_BONUS: u32 = 6;
+const WORD_START_BONUS: u32 = 8;
+const TEXT_START_BONUS: u32 = 12;
+/// For a text equal to the term, on top of the per-character scores.
+const EXACT_BONUS: u32 = 30;
+
+/// Narrows a search; every filter given has to hold.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchFilters {
+    pub favorite: Option<bool>,
+    /// Only entries with every one of these tags.
+    pub tags: Vec<String>,
+    pub kind: Option<EntryKind>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub updated_after: Option<DateTime<Utc>>,
+    pub updated_before: Option<DateTime<Utc>>,
+}
+
+impl SearchFilters {
+    fn matches(&self, entry: &PasswordEntry) -> bool {
+        let within = |time: DateTime<Utc>, after: Option<_>, before: Option<_>| {
+            after.is_none_or(|after| time >= after) && before.is_none_or(|before| time <= before)
+        };
+
+        self.favorite
+            .is_none_or(|favorite| entry.favorite == favorite)
+            && self.tags.iter().all(|tag| entry.tags.contains(tag))
+            && self.kind.is_none_or(|kind| entry.kind == kind)
+            && within(entry.created_at, self.created_after, self.created_before)
+            && within(entry.updated_at, self.updated_after, self.updated_before)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub entry: PasswordEntrySummary,
+    /// Higher is a better match; 0 for every entry of an empty query.
+    pub score: u32,
+}
+
+/// The entries matching `query` and `filters`, best match first. An empty
+/// query lists every entry the filters let through, by title.
+pub fn search(
+    entries: &[PasswordEntry],
+    query: &str,
+    filters: &SearchFilters,
+) -> Vec<SearchResult> {
+    let terms: Vec<Vec<char>> = query
+        .split_whitespace()
+        .map(|term| term.to_lowercase().chars().collect())
+        .collect();
+
+    let mut results: Vec<SearchResult> = entries
+        .iter()
+        .filter(|entry| filters.matches(entry))
+        .filter_map(|entry| {
+            let score = terms
+                .iter()
+                .map(|term| score_entry(entry, term))
+                .sum::<Option<u32>>()?;
+            Some(SearchResult {
+                entry: entry.clone().into(),
+                score,
+            })
+        })
+        .collect();
+
+    results.sort_by(|a, b| {
+        b.score.cmp(&a.score).then_with(|| {
+            a.entry
+                .title
+                .to_lowercase()
+                .cmp(&b.entry.title.to_lowercase())
+        })
+    });
+    results
+}
+
+/// The best weighted score of `term` in any of the entry's fields.
+fn score_entry(entry: &PasswordEntry, term: &[char]) -> Option<u32> {
+    let hosts: Vec<String> = urls::entry_urls(entry).map(import::host).collect();
+    let fuzzy = [
+        (entry.title.as_str(), TITLE_WEIGHT),
+        (entry.username.as_str(), USERNAME_WEIGHT),
+    ]
+    .into_iter()
+    .chain(hosts.iter().map(|host| (host.as_str(), HOST_WEIGHT)))
+    .chain(entry.tags.iter().map(|tag| (tag.as_str(), TAG_WEIGHT)))
+    .filter_map(|(text, weight)| Some(fuzzy_score(term, text)? * weight));
+
+    // Notes are long enough for most terms to be a scattered subsequence,
+    // so only a substring counts there.
+    let notes = entry.notes.as_deref().and_then(|notes| {
+        let term: String = term.iter().collect();
+        notes
+            .to_lowercase()
+            .contains(&term)
+            .then(|| MATCH * term.chars().count() as u32 * NOTES_WEIGHT)
+    });
+
+    fuzzy.chain(notes).max()
+}
+
+/// How well `term`, already lowercased, matches `text` as a subsequence of
+/// its characters, or `None` if it does not. Every occurrence of the term's
+/// first character is tried as a starting point, matching the rest greedily.
+fn fuzzy_score(term: &[char], text: &str) -> Option<u32> {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let first = *term.first()?;
+
+    let best = (0..text.len())
+        .filter(|&start| text[start] == first)
+        .filter_map(|start| score_from(term, &text, start))
+        .max()?;
+
+    if text.len() == term.len() {
+        Some(best + EXACT_BONUS)
+    } else {
+        Some(best)
+    }
+}
+
+fn score_from(term: &[char], text: &[char], start: usize) -> Option<u32> {
+    let mut score = 0;
+    let mut pos = start;
+    let mut previous: Option<usize> = None;
+
+    for &c in term {
+        pos += text[pos..].iter().position(|&t| t == c)?;
+
+        score += MATCH;
+        if pos == 0 {
+            score += TEXT_START_BONUS;
+        } else if !text[pos - 1].is_alphanumeric() {
+            score += WORD_START_BONUS;
+        }
+        if previous == Some(pos.wrapping_sub(1)) {
+            score += CONSECUTIVE_BONUS;
+        }
+
+        previous = Some(pos);
+        pos += 1;
+    }
+
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use uuid::Uuid;
+
+    fn entry(title: &str, username: &str, url: Option<&str>, tags: &[&str]) -> PasswordEntry {
+        let time = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        PasswordEntry {
+            id: Uuid::new_v4(),
+            kind: EntryKind::Login,
+            title: title.to_string(),
+            username: username.to_string(),
+            password: "pw".to_string().into(),
+            url: url.map(str::to_string),
+            urls: Vec::new(),
+            url_match: Default::default(),
+            notes: None,
+            totp: None,
+            fields: Vec::new(),
+            history: Vec::new(),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            favorite: false,
+            created_at: time,
+            updated_at: time,
+            deleted_at: None,
+        }
+    }
+
+    fn titles(results: &[SearchResult]) -> Vec<&str> {
+        results.iter().map(|r| r.entry.title.as_str()).collect()
+    }
+
+    fn chars(term: &str) -> Vec<char> {
+        term.chars().collect()
+    }
+
+    #[test]
+    fn test_fuzzy_score() {
+        assert_eq!(fuzzy_score(&chars("ghb"), "bitbucket"), None);
+        assert!(fuzzy_score(&chars("ghb"), "GitHub").is_some());
+        assert_eq!(fuzzy_score(&chars("x"), ""), None);
+
+        // Runs beat scattered letters, word starts beat the middle of a
+        // word, and an exact match beats both.
+        let score = |term: &str, text| fuzzy_score(&chars(term), text).unwrap();
+        assert!(score("hub", "github") > score("hub", "thxuxb"));
+        assert!(score("mail", "my mail") > score("mail", "gmail"));
+        assert!(score("bank", "bank") > score("bank", "banking"));
+    }
+
+    #[test]
+    fn test_search_ranks_fields() {
+        let mut notes = entry("Router", "admin", None, &[]);
+        notes.notes = Some("Wi-Fi password for the github office".to_string());
+        let entries = vec![
+            entry("Bitbucket", "alice", Some("https://bitbucket.org"), &[]),
+            notes,
+            entry(
+                "Work code",
+                "alice",
+                Some("https://www.github.com/login"),
+                &[],
+            ),
+            entry("GitHub", "alice", None, &["dev"]),
+        ];
+
+        let results = search(&entries, "github", &SearchFilters::default());
+        assert_eq!(titles(&results), vec!["GitHub", "Work code", "Router"]);
+        assert!(results[0].score > results[1].score);
+
+        // Every term has to match, in any field.
+        let results = search(&entries, "gh dev", &SearchFilters::default());
+        assert_eq!(titles(&results), vec!["GitHub"]);
+        assert!(search(&entries, "github zzz", &SearchFilters::default()).is_empty());
+
+        let all = search(&entries, "  ", &SearchFilters::default());
+        assert_eq!(
+            titles(&all),
+            vec!["Bitbucket", "GitHub", "Router", "Work code"]
+        );
+        assert!(all.iter().all(|r| r.score == 0));
+    }
+
+    #[test]
+    fn test_search_filters() {
+        let mut card = entry("Visa", "", None, &["finance"]);
+        card.kind = EntryKind::CreditCard;
+        card.favorite = true;
+        let mut old = entry("Old bank", "alice", None, &["finance", "archive"]);
+        old.updated_at = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+        let entries = vec![card, old, entry("Mail", "alice", None, &[])];
+
+        let search_with = |filters: SearchFilters| {
+            search(&entries, "", &filters)
+                .into_iter()
+                .map(|r| r.entry.title)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            search_with(SearchFilters {
+                favorite: Some(true),
+                ..SearchFilters::default()
+            }),
+            vec!["Visa"]
+        );
+        assert_eq!(
+            search_with(SearchFilters {
+                tags: vec!["finance".to_string(), "archive".to_string()],
+                ..SearchFilters::default()
+            }),
+            vec!["Old bank"]
+        );
+        assert_eq!(
+            search_with(SearchFilters {
+                kind: Some(EntryKind::Login),
+                updated_after: Some(Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap()),
+                ..SearchFilters::default()
+            }),
+            vec!["Mail"]
+        );
+        assert_eq!(
+            search_with(SearchFilters {
+                created_before: Some(Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap()),
+                ..SearchFilters::default()
+            }),
+            Vec::<String>::new()
+        );
+    }
+}
@@ -1,57 +1,228 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::fs;
-use std::path::PathBuf;
-use tauri::AppHandle;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+#[cfg(feature = "gui")]
+use tauri::{AppHandle, Manager};
+use uuid::Uuid;
+use zeroize::Zeroizing;
 
-use super::types::PasswordEntry;
+use super::crypto::{KdfParams, VaultKey};
+use super::error::VaultError;
+use super::types::{PasswordEntry, VaultSettings};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct VaultData {
     pub version: u32,
-    pub salt: [u8; 32],
+    #[serde(default)]
+    pub settings: VaultSettings,
     pub entries: Vec<PasswordEntry>,
+    /// Deleted entries, each with its `deleted_at`.
+    #[serde(default)]
+    pub trash: Vec<PasswordEntry>,
 }
 
-pub fn get_vault_path(app: &AppHandle) -> Result<PathBuf, Box<dyn std::error::Error>> {
-    let app_data_dir = app
-        .path()
-        .app_data_dir()
-        .map_err(|e| format!("Failed to get app data dir: {}", e))?;
+/// Upper bound on retained backup generations, regardless of settings.
+pub const MAX_BACKUP_GENERATIONS: u32 = 20;
 
-    fs::create_dir_all(&app_data_dir)?;
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupInfo {
+    pub generation: u32,
+    pub size: u64,
+    pub modified: DateTime<Utc>,
+}
 
-    Ok(app_data_dir.join("vault.encrypted"))
+/// Magic bytes at the start of every vault file.
+pub const VAULT_MAGIC: &[u8; 4] = b"ZIMS";
+pub const VAULT_FORMAT_VERSION: u16 = 2;
+pub const CIPHER_CHACHA20_POLY1305: u8 = 1;
+pub const KDF_ARGON2ID: u8 = 1;
+
+/// v1: magic(4) | version(2) | cipher(1) | kdf(1) | m_cost(4) | t_cost(4) | p_cost(4) | salt(32)
+const VAULT_HEADER_V1_LEN: usize = 52;
+/// v2: v1 followed by generation(8)
+pub const VAULT_HEADER_LEN: usize = 60;
+
+/// Unencrypted prefix of the vault file. It carries everything needed to
+/// re-derive the key, and is authenticated as AEAD associated data.
+/// Older format versions are upgraded on read and always written back in
+/// the current layout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VaultHeader {
+    pub cipher: u8,
+    pub kdf: u8,
+    pub kdf_params: KdfParams,
+    pub salt: [u8; 32],
+    /// Incremented on every save; used to detect writes by another process.
+    pub generation: u64,
 }
 
-pub fn vault_exists(app: &AppHandle) -> bool {
-    match get_vault_path(app) {
-        Ok(path) => path.exists(),
-        Err(_) => false,
+impl VaultHeader {
+    pub fn new(salt: [u8; 32], kdf_params: KdfParams) -> Self {
+        Self {
+            cipher: CIPHER_CHACHA20_POLY1305,
+            kdf: KDF_ARGON2ID,
+            kdf_params,
+            salt,
+            generation: 0,
+        }
     }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(VAULT_HEADER_LEN);
+        out.extend_from_slice(VAULT_MAGIC);
+        out.extend_from_slice(&VAULT_FORMAT_VERSION.to_le_bytes());
+        out.push(self.cipher);
+        out.push(self.kdf);
+        out.extend_from_slice(&self.kdf_params.memory_kib.to_le_bytes());
+        out.extend_from_slice(&self.kdf_params.iterations.to_le_bytes());
+        out.extend_from_slice(&self.kdf_params.parallelism.to_le_bytes());
+        out.extend_from_slice(&self.salt);
+        out.extend_from_slice(&self.generation.to_le_bytes());
+        out
+    }
+
+    /// Parses a header, returning it along with its length on disk.
+    pub fn from_bytes(data: &[u8]) -> Result<(Self, usize), VaultError> {
+        if data.len() < 6 || &data[..4] != VAULT_MAGIC {
+            return Err(VaultError::Corrupted("Not a zims vault file".to_string()));
+        }
+
+        let format_version = u16::from_le_bytes([data[4], data[5]]);
+        let header_len = match format_version {
+            1 => VAULT_HEADER_V1_LEN,
+            2 => VAULT_HEADER_LEN,
+            _ => return Err(VaultError::VersionUnsupported(format_version)),
+        };
+
+        if data.len() < header_len {
+            return Err(VaultError::Corrupted("Vault header truncated".to_string()));
+        }
+
+        let cipher = data[6];
+        if cipher != CIPHER_CHACHA20_POLY1305 {
+            return Err(VaultError::Unsupported(format!("vault cipher {}", cipher)));
+        }
+
+        let kdf = data[7];
+        if kdf != KDF_ARGON2ID {
+            return Err(VaultError::Unsupported(format!("vault KDF {}", kdf)));
+        }
+
+        let read_u32 = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+        let kdf_params = KdfParams {
+            memory_kib: read_u32(8),
+            iterations: read_u32(12),
+            parallelism: read_u32(16),
+        };
+
+        let mut salt = [0u8; 32];
+        salt.copy_from_slice(&data[20..52]);
+
+        let generation = if format_version >= 2 {
+            u64::from_le_bytes(data[52..60].try_into().unwrap())
+        } else {
+            0
+        };
+
+        let header = Self {
+            cipher,
+            kdf,
+            kdf_params,
+            salt,
+            generation,
+        };
+
+        Ok((header, header_len))
+    }
+}
+
+/// Joins a header and its AEAD payload (nonce || ciphertext) into file bytes.
+pub fn encode_vault_file(header: &VaultHeader, payload: &[u8]) -> Vec<u8> {
+    let mut out = header.to_bytes();
+    out.extend_from_slice(payload);
+    out
 }
 
+/// Splits vault file bytes into the parsed header, the raw header bytes
+/// (for use as associated data) and the AEAD payload.
+pub fn decode_vault_file(data: &[u8]) -> Result<(VaultHeader, &[u8], &[u8]), VaultError> {
+    let (header, header_len) = VaultHeader::from_bytes(data)?;
+    let (header_bytes, payload) = data.split_at(header_len);
+    Ok((header, header_bytes, payload))
+}
+
+/// The single vault of earlier versions, before vaults were registered in
+/// `vaults.json`.
+pub const LEGACY_VAULT_FILE_NAME: &str = "vault.encrypted";
+
+/// Bundle identifier from `tauri.conf.json`; the app data directory is named
+/// after it.
+const APP_IDENTIFIER: &str = "com.zmscode.zims";
+
+#[cfg(feature = "gui")]
+pub fn get_app_data_dir(app: &AppHandle) -> Result<PathBuf, VaultError> {
+    let app_data_dir = app.path().app_data_dir().map_err(|e| {
+        VaultError::Io(std::io::Error::other(format!(
+            "Failed to get app data dir: {}",
+            e
+        )))
+    })?;
+
+    fs::create_dir_all(&app_data_dir)?;
+
+    Ok(app_data_dir)
+}
+
+/// The directory `get_app_data_dir` resolves to, for callers without an
+/// AppHandle such as the CLI. Tauri's app data directory is the platform
+/// data directory joined with the bundle identifier.
+pub fn default_data_dir() -> Result<PathBuf, VaultError> {
+    let data_dir = dirs::data_dir().ok_or_else(|| {
+        VaultError::Io(std::io::Error::other("Failed to get data dir".to_string()))
+    })?;
+
+    Ok(data_dir.join(APP_IDENTIFIER))
+}
+
+/// Writes the vault. When `expected_generation` is set, the write is refused
+/// if the file on disk no longer carries that generation, i.e. someone else
+/// saved since we loaded it.
 pub fn save_vault(
-    app: &AppHandle,
+    vault_path: &Path,
     encrypted_data: &[u8],
-) -> Result<(), Box<dyn std::error::Error>> {
-    let vault_path = get_vault_path(app)?;
-    fs::write(vault_path, encrypted_data)?;
-    Ok(())
+    expected_generation: Option<u64>,
+    backup_generations: u32,
+) -> Result<(), VaultError> {
+    if let Some(expected) = expected_generation {
+        check_generation(vault_path, expected)?;
+    }
+    write_vault_file(vault_path, encrypted_data, backup_generations)
 }
 
-pub fn load_vault(app: &AppHandle) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    let vault_path = get_vault_path(app)?;
-
+pub fn load_vault(vault_path: &Path) -> Result<Vec<u8>, VaultError> {
     if !vault_path.exists() {
-        return Err("Vault file does not exist".into());
+        return Err(VaultError::NotFound("Vault file".to_string()));
     }
 
     let data = fs::read(vault_path)?;
     Ok(data)
 }
 
-pub fn delete_vault(app: &AppHandle) -> Result<(), Box<dyn std::error::Error>> {
-    let vault_path = get_vault_path(app)?;
+/// Removes the vault file together with its backups and any quick-unlock
+/// credential stored next to it.
+pub fn delete_vault(vault_path: &Path) -> Result<(), VaultError> {
+    for generation in 1..=MAX_BACKUP_GENERATIONS {
+        let path = backup_path(vault_path, generation);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+    delete_quick_unlock(vault_path)?;
+    delete_lock_file(vault_path)?;
 
     if vault_path.exists() {
         fs::remove_file(vault_path)?;
@@ -60,30 +231,516 @@ pub fn delete_vault(app: &AppHandle) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+pub fn load_backup(vault_path: &Path, generation: u32) -> Result<Vec<u8>, VaultError> {
+    let path = backup_path(vault_path, generation);
+
+    if !path.exists() {
+        return Err(VaultError::NotFound(format!("Backup {}", generation)));
+    }
+
+    Ok(fs::read(path)?)
+}
+
+/// `vault.encrypted` -> `vault.encrypted.<generation>`; generation 1 is the
+/// most recent previous version.
+pub fn backup_path(vault_path: &Path, generation: u32) -> PathBuf {
+    let mut name = vault_path.as_os_str().to_owned();
+    name.push(format!(".{}", generation));
+    PathBuf::from(name)
+}
+
+/// Crash-safe replacement of the vault file: the new contents are written
+/// and fsynced to a sibling temp file, the current file is rotated into the
+/// backup ring, and the temp file is renamed over the original. At every
+/// point either the old or the new vault is intact on disk.
+pub fn write_vault_file(
+    vault_path: &Path,
+    data: &[u8],
+    backup_generations: u32,
+) -> Result<(), VaultError> {
+    let mut tmp_name = vault_path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+
+    {
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+    }
+
+    if vault_path.exists() {
+        rotate_backups(vault_path, backup_generations.min(MAX_BACKUP_GENERATIONS))?;
+    }
+
+    fs::rename(&tmp_path, vault_path)?;
+    sync_parent_dir(vault_path)?;
+
+    Ok(())
+}
+
+fn rotate_backups(vault_path: &Path, generations: u32) -> Result<(), VaultError> {
+    // Drop the oldest generation and anything left over from a larger ring.
+    for generation in generations.max(1)..=MAX_BACKUP_GENERATIONS {
+        let path = backup_path(vault_path, generation);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    if generations == 0 {
+        return Ok(());
+    }
+
+    for generation in (1..generations).rev() {
+        let from = backup_path(vault_path, generation);
+        if from.exists() {
+            fs::rename(from, backup_path(vault_path, generation + 1))?;
+        }
+    }
+
+    // A hard link keeps the current file in place until the final rename,
+    // so there is never a moment without a vault on disk.
+    let first = backup_path(vault_path, 1);
+    if fs::hard_link(vault_path, &first).is_err() {
+        fs::copy(vault_path, &first)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+pub fn sync_parent_dir(path: &Path) -> Result<(), VaultError> {
+    if let Some(parent) = path.parent() {
+        fs::File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn sync_parent_dir(_path: &Path) -> Result<(), VaultError> {
+    Ok(())
+}
+
+/// Fails with `Conflict` if the vault at `vault_path` has moved past
+/// `expected`. A missing file is not a conflict.
+pub fn check_generation(vault_path: &Path, expected: u64) -> Result<(), VaultError> {
+    let mut header_bytes = Vec::with_capacity(VAULT_HEADER_LEN);
+    match fs::File::open(vault_path) {
+        Ok(file) => {
+            file.take(VAULT_HEADER_LEN as u64)
+                .read_to_end(&mut header_bytes)?;
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    }
+
+    let (on_disk, _) = VaultHeader::from_bytes(&header_bytes)?;
+    if on_disk.generation != expected {
+        return Err(VaultError::Conflict);
+    }
+
+    Ok(())
+}
+
+/// Advisory cross-process lock held for as long as a vault is unlocked: an
+/// OS lock on `<vault>.lock`, which the OS releases if the holder crashes.
+/// The file records the holder's PID for `InUse`. It stays in place when
+/// the lock is released, as removing it would let a process that had just
+/// opened it lock a file no one else can see.
+#[derive(Debug)]
+pub struct VaultLock {
+    path: PathBuf,
+    file: fs::File,
+}
+
+/// Lock files held by this process, which OS locks do not reliably keep
+/// from being locked again through another handle.
+fn held_locks() -> &'static Mutex<HashSet<PathBuf>> {
+    static HELD: OnceLock<Mutex<HashSet<PathBuf>>> = OnceLock::new();
+    HELD.get_or_init(Default::default)
+}
+
+impl VaultLock {
+    pub fn acquire(vault_path: &Path) -> Result<Self, VaultError> {
+        let path = lock_path(vault_path);
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        let path = fs::canonicalize(&path)?;
+
+        let mut held = held_locks().lock().unwrap();
+        if held.contains(&path) {
+            return Err(VaultError::InUse(std::process::id()));
+        }
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(fs::TryLockError::WouldBlock) => {
+                // Best-effort: Windows does not let us read a locked file.
+                let mut holder = String::new();
+                let _ = file.read_to_string(&mut holder);
+                return Err(VaultError::InUse(holder.trim().parse().unwrap_or(0)));
+            }
+            Err(fs::TryLockError::Error(e)) => return Err(e.into()),
+        }
+
+        file.set_len(0)?;
+        write!(file, "{}", std::process::id())?;
+        file.sync_all()?;
+        held.insert(path.clone());
+        Ok(Self { path, file })
+    }
+}
+
+impl Drop for VaultLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+        held_locks().lock().unwrap().remove(&self.path);
+    }
+}
+
+fn lock_path(vault_path: &Path) -> PathBuf {
+    let mut name = vault_path.as_os_str().to_owned();
+    name.push(".lock");
+    PathBuf::from(name)
+}
+
+/// Removes the lock file of a vault that is being deleted.
+pub fn delete_lock_file(vault_path: &Path) -> Result<(), VaultError> {
+    match fs::remove_file(lock_path(vault_path)) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn list_backup_files(vault_path: &Path) -> Result<Vec<BackupInfo>, VaultError> {
+    let mut backups = Vec::new();
+
+    for generation in 1..=MAX_BACKUP_GENERATIONS {
+        let path = backup_path(vault_path, generation);
+        if let Ok(metadata) = fs::metadata(&path) {
+            backups.push(BackupInfo {
+                generation,
+                size: metadata.len(),
+                modified: metadata.modified()?.into(),
+            });
+        }
+    }
+
+    Ok(backups)
+}
+
+/// `vault.encrypted` -> `vault.encrypted.quick`, the vault key wrapped under
+/// the device key while quick unlock is enabled.
+pub fn quick_unlock_path(vault_path: &Path) -> PathBuf {
+    let mut name = vault_path.as_os_str().to_owned();
+    name.push(".quick");
+    PathBuf::from(name)
+}
+
+pub fn write_quick_unlock(vault_path: &Path, wrapped_key: &[u8]) -> Result<(), VaultError> {
+    let path = quick_unlock_path(vault_path);
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(wrapped_key)?;
+    file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp_path, &path)?;
+    Ok(())
+}
+
+pub fn read_quick_unlock(vault_path: &Path) -> Result<Vec<u8>, VaultError> {
+    match fs::read(quick_unlock_path(vault_path)) {
+        Ok(data) => Ok(data),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Err(VaultError::NotFound("Quick unlock credential".to_string()))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn delete_quick_unlock(vault_path: &Path) -> Result<(), VaultError> {
+    match fs::remove_file(quick_unlock_path(vault_path)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
 const SERVICE_NAME: &str = "com.zmscode.zims";
-const USERNAME: &str = "master";
+/// Earlier versions stored the raw vault key under this account. It is
+/// never written any more, only deleted.
+const LEGACY_MASTER_KEY_ACCOUNT: &str = "master";
+/// Before there were several vaults, their one device key was stored under
+/// this account. Like the master key, it is only deleted now.
+const LEGACY_DEVICE_KEY_ACCOUNT: &str = "device-key";
+
+/// The keyring entry holding the quick-unlock device key of the vault
+/// registered as `vault_id`. Helpers below take the entry so tests can run
+/// them against the mock credential store.
+pub fn device_key_entry(vault_id: Uuid) -> Result<keyring::Entry, VaultError> {
+    Ok(keyring::Entry::new(
+        SERVICE_NAME,
+        &device_key_account(vault_id),
+    )?)
+}
 
-pub fn store_master_key(key_hash: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let entry = keyring::Entry::new(SERVICE_NAME, USERNAME)?;
-    entry.set_password(key_hash)?;
+fn device_key_account(vault_id: Uuid) -> String {
+    format!("{}/{}", LEGACY_DEVICE_KEY_ACCOUNT, vault_id)
+}
+
+pub fn store_device_key(entry: &keyring::Entry, key: &[u8; 32]) -> Result<(), VaultError> {
+    entry.set_secret(key)?;
     Ok(())
 }
 
-pub fn get_master_key() -> Result<String, Box<dyn std::error::Error>> {
-    let entry = keyring::Entry::new(SERVICE_NAME, USERNAME)?;
-    let password = entry.get_password()?;
-    Ok(password)
+pub fn get_device_key(entry: &keyring::Entry) -> Result<VaultKey, VaultError> {
+    let secret = match entry.get_secret() {
+        Ok(secret) => Zeroizing::new(secret),
+        Err(keyring::Error::NoEntry) => {
+            return Err(VaultError::NotFound("Quick unlock credential".to_string()))
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    if secret.len() != key.len() {
+        return Err(VaultError::Keyring(
+            "Device key has wrong length".to_string(),
+        ));
+    }
+    key.copy_from_slice(&secret);
+    Ok(key)
+}
+
+/// Deletes the credential behind `entry`; a missing credential is not an error.
+pub fn delete_credential(entry: &keyring::Entry) -> Result<(), VaultError> {
+    match entry.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
 }
 
-pub fn delete_master_key() -> Result<(), Box<dyn std::error::Error>> {
-    let entry = keyring::Entry::new(SERVICE_NAME, USERNAME)?;
-    entry.delete_credential()?;
+/// Removes the raw vault key and the shared device key stored by earlier
+/// versions, if any.
+pub fn delete_legacy_credentials() -> Result<(), VaultError> {
+    for account in [LEGACY_MASTER_KEY_ACCOUNT, LEGACY_DEVICE_KEY_ACCOUNT] {
+        delete_credential(&keyring::Entry::new(SERVICE_NAME, account)?)?;
+    }
     Ok(())
 }
 
-pub fn master_key_exists() -> bool {
-    match keyring::Entry::new(SERVICE_NAME, USERNAME) {
-        Ok(entry) => entry.get_password().is_ok(),
-        Err(_) => false,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_header() -> VaultHeader {
+        VaultHeader::new([7u8; 32], KdfParams::default())
+    }
+
+    #[test]
+    fn test_header_roundtrip() {
+        let header = sample_header();
+        let bytes = header.to_bytes();
+        assert_eq!(bytes.len(), VAULT_HEADER_LEN);
+        assert_eq!(
+            VaultHeader::from_bytes(&bytes).unwrap(),
+            (header, VAULT_HEADER_LEN)
+        );
+    }
+
+    #[test]
+    fn test_decode_splits_payload() {
+        let header = sample_header();
+        let file = encode_vault_file(&header, b"ciphertext");
+        let (parsed, header_bytes, payload) = decode_vault_file(&file).unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!(header_bytes, header.to_bytes().as_slice());
+        assert_eq!(payload, b"ciphertext");
+    }
+
+    #[test]
+    fn test_rejects_bad_magic_and_version() {
+        let mut bytes = sample_header().to_bytes();
+        bytes[0] = b'X';
+        assert!(VaultHeader::from_bytes(&bytes).is_err());
+
+        let mut bytes = sample_header().to_bytes();
+        bytes[4] = 99;
+        assert!(matches!(
+            VaultHeader::from_bytes(&bytes),
+            Err(VaultError::VersionUnsupported(99))
+        ));
+
+        assert!(VaultHeader::from_bytes(&[0u8; 10]).is_err());
+    }
+
+    #[test]
+    fn test_reads_v1_header() {
+        let header = sample_header();
+        let mut bytes = header.to_bytes();
+        bytes.truncate(VAULT_HEADER_V1_LEN);
+        bytes[4..6].copy_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(b"payload");
+
+        let (parsed, header_bytes, payload) = decode_vault_file(&bytes).unwrap();
+        assert_eq!(parsed.generation, 0);
+        assert_eq!(parsed.salt, header.salt);
+        assert_eq!(header_bytes.len(), VAULT_HEADER_V1_LEN);
+        assert_eq!(payload, b"payload");
+    }
+
+    #[test]
+    fn test_generation_conflict() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault_path = dir.path().join("vault.encrypted");
+
+        assert!(check_generation(&vault_path, 0).is_ok());
+
+        let mut header = sample_header();
+        header.generation = 3;
+        write_vault_file(&vault_path, &encode_vault_file(&header, b"x"), 0).unwrap();
+
+        assert!(check_generation(&vault_path, 3).is_ok());
+        assert!(matches!(
+            check_generation(&vault_path, 2),
+            Err(VaultError::Conflict)
+        ));
+    }
+
+    #[test]
+    fn test_lock_excludes_other_holders() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault_path = dir.path().join("vault.encrypted");
+        let lock_path = dir.path().join("vault.encrypted.lock");
+
+        // A second handle in this process, under any spelling of the path.
+        let lock = VaultLock::acquire(&vault_path).unwrap();
+        let respelled = dir.path().join(".").join("vault.encrypted");
+        assert!(matches!(
+            VaultLock::acquire(&respelled),
+            Err(VaultError::InUse(pid)) if pid == std::process::id()
+        ));
+        drop(lock);
+        drop(VaultLock::acquire(&vault_path).unwrap());
+
+        // Another holder of the OS lock, as another process would be.
+        fs::write(&lock_path, "4242").unwrap();
+        let other = fs::File::open(&lock_path).unwrap();
+        other.lock().unwrap();
+        let held = VaultLock::acquire(&vault_path);
+        #[cfg(unix)]
+        assert!(matches!(held, Err(VaultError::InUse(4242))));
+        #[cfg(not(unix))]
+        assert!(matches!(held, Err(VaultError::InUse(_))));
+        drop(other);
+
+        // A PID left behind without the OS lock is stale.
+        let lock = VaultLock::acquire(&vault_path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&lock_path).unwrap(),
+            std::process::id().to_string()
+        );
+        drop(lock);
+    }
+
+    #[test]
+    fn test_atomic_write_rotates_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault_path = dir.path().join("vault.encrypted");
+
+        for version in 0..5u8 {
+            write_vault_file(&vault_path, &[version], 3).unwrap();
+        }
+
+        assert_eq!(fs::read(&vault_path).unwrap(), vec![4]);
+        assert_eq!(fs::read(backup_path(&vault_path, 1)).unwrap(), vec![3]);
+        assert_eq!(fs::read(backup_path(&vault_path, 2)).unwrap(), vec![2]);
+        assert_eq!(fs::read(backup_path(&vault_path, 3)).unwrap(), vec![1]);
+        assert!(!backup_path(&vault_path, 4).exists());
+        assert!(!dir.path().join("vault.encrypted.tmp").exists());
+
+        let generations: Vec<u32> = list_backup_files(&vault_path)
+            .unwrap()
+            .iter()
+            .map(|b| b.generation)
+            .collect();
+        assert_eq!(generations, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_shrinking_ring_prunes_old_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault_path = dir.path().join("vault.encrypted");
+
+        for version in 0..4u8 {
+            write_vault_file(&vault_path, &[version], 3).unwrap();
+        }
+        write_vault_file(&vault_path, &[9], 1).unwrap();
+
+        assert_eq!(fs::read(backup_path(&vault_path, 1)).unwrap(), vec![3]);
+        assert!(!backup_path(&vault_path, 2).exists());
+        assert!(!backup_path(&vault_path, 3).exists());
+
+        write_vault_file(&vault_path, &[10], 0).unwrap();
+        assert!(list_backup_files(&vault_path).unwrap().is_empty());
+        assert_eq!(fs::read(&vault_path).unwrap(), vec![10]);
+    }
+
+    /// Mock credentials keep no shared store, so each test must reuse the
+    /// one entry it creates.
+    fn mock_entry() -> keyring::Entry {
+        keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+        keyring::Entry::new(SERVICE_NAME, &device_key_account(Uuid::new_v4())).unwrap()
+    }
+
+    #[test]
+    fn test_device_key_in_keyring() {
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        assert_ne!(device_key_account(a), device_key_account(b));
+        assert_eq!(device_key_account(a), format!("device-key/{}", a));
+
+        let entry = mock_entry();
+        assert!(matches!(
+            get_device_key(&entry),
+            Err(VaultError::NotFound(_))
+        ));
+
+        let key = [42u8; 32];
+        store_device_key(&entry, &key).unwrap();
+        assert_eq!(*get_device_key(&entry).unwrap(), key);
+
+        delete_credential(&entry).unwrap();
+        assert!(matches!(
+            get_device_key(&entry),
+            Err(VaultError::NotFound(_))
+        ));
+        // Revoking twice is fine.
+        delete_credential(&entry).unwrap();
+    }
+
+    #[test]
+    fn test_quick_unlock_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let vault_path = dir.path().join("vault.encrypted");
+
+        assert!(matches!(
+            read_quick_unlock(&vault_path),
+            Err(VaultError::NotFound(_))
+        ));
+
+        write_quick_unlock(&vault_path, b"wrapped").unwrap();
+        assert_eq!(read_quick_unlock(&vault_path).unwrap(), b"wrapped");
+        assert!(list_backup_files(&vault_path).unwrap().is_empty());
+
+        delete_quick_unlock(&vault_path).unwrap();
+        delete_quick_unlock(&vault_path).unwrap();
+        assert!(!quick_unlock_path(&vault_path).exists());
     }
 }
@@ -1,26 +1,138 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use uuid::Uuid;
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
+/// At-rest representation of an entry. Secret fields are always serialized,
+/// so this type must only ever be written inside the encrypted payload and
+/// never returned to the webview directly.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PasswordEntry {
     pub id: Uuid,
+    #[serde(default)]
+    pub kind: EntryKind,
     pub title: String,
     pub username: String,
-    #[serde(skip_serializing)]
-    pub password: String,
+    pub password: SecureString,
     pub url: Option<String>,
+    /// More URLs the entry is for, besides `url`.
+    #[serde(default)]
+    pub urls: Vec<String>,
+    /// How `url` and `urls` are matched to a page; see `password::urls`.
+    #[serde(default)]
+    pub url_match: UrlMatch,
     pub notes: Option<String>,
+    /// Two-factor seed, as an `otpauth://` URI or base32 secret; see
+    /// `password::totp`.
+    #[serde(default)]
+    pub totp: Option<SecureString>,
+    /// The kind's own fields (see `password::fields`) and any the user added.
+    #[serde(default)]
+    pub fields: Vec<CustomField>,
+    /// Previous passwords, newest first, at most
+    /// `VaultSettings::password_history` of them.
+    #[serde(default)]
+    pub history: Vec<PasswordHistoryItem>,
     pub tags: Vec<String>,
     pub favorite: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// When the entry was moved to the trash; `None` outside it.
+    #[serde(default)]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+impl PasswordEntry {
+    pub fn field(&self, name: &str) -> Option<&CustomField> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+}
+
+/// A password an entry used to have. Returned to the webview only by
+/// `get_password_history`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PasswordHistoryItem {
+    pub password: SecureString,
+    /// When it was replaced.
+    pub changed_at: DateTime<Utc>,
+}
+
+/// How an entry's URLs are compared with a page's to decide whether the
+/// entry is offered there.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UrlMatch {
+    /// Same registrable domain, so `www.example.com` also matches
+    /// `accounts.example.com`.
+    #[default]
+    BaseDomain,
+    /// Same host, and port if the entry's URL names one.
+    Host,
+    /// The page's URL starts with the entry's.
+    StartsWith,
+    /// The entry's URLs are regular expressions for the page's URL.
+    Regex,
+    /// The entry is never offered for a page.
+    Never,
+}
+
+/// What an entry holds, which decides the fields it is expected to have.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryKind {
+    #[default]
+    Login,
+    SecureNote,
+    CreditCard,
+    Identity,
+    SshKey,
+    ApiToken,
+    Wifi,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldKind {
+    #[default]
+    Text,
+    /// Kept out of entry details like the password, and revealed on request.
+    Hidden,
+    Url,
+    /// `YYYY-MM-DD`, or `YYYY-MM` for expiry dates.
+    Date,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CustomField {
+    pub name: String,
+    #[serde(default)]
+    pub kind: FieldKind,
+    pub value: SecureString,
+}
+
+/// A custom field as shown in entry details; hidden values are left out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomFieldDetails {
+    pub name: String,
+    pub kind: FieldKind,
+    pub value: Option<String>,
+}
+
+impl From<CustomField> for CustomFieldDetails {
+    fn from(field: CustomField) -> Self {
+        Self {
+            value: (field.kind != FieldKind::Hidden).then(|| field.value.into_inner()),
+            name: field.name,
+            kind: field.kind,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PasswordEntrySummary {
     pub id: Uuid,
+    pub kind: EntryKind,
     pub title: String,
     pub username: String,
     pub url: Option<String>,
@@ -28,12 +140,14 @@ pub struct PasswordEntrySummary {
     pub favorite: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
 }
 
 impl From<PasswordEntry> for PasswordEntrySummary {
     fn from(entry: PasswordEntry) -> Self {
         Self {
             id: entry.id,
+            kind: entry.kind,
             title: entry.title,
             username: entry.username,
             url: entry.url,
@@ -41,10 +155,81 @@ impl From<PasswordEntry> for PasswordEntrySummary {
             favorite: entry.favorite,
             created_at: entry.created_at,
             updated_at: entry.updated_at,
+            deleted_at: entry.deleted_at,
         }
     }
 }
 
+/// IPC-facing view of a single entry. Carries everything except the secret,
+/// which is only sent across through an explicit reveal command.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasswordEntryDetails {
+    pub id: Uuid,
+    pub kind: EntryKind,
+    pub title: String,
+    pub username: String,
+    pub url: Option<String>,
+    pub urls: Vec<String>,
+    pub url_match: UrlMatch,
+    pub notes: Option<String>,
+    /// Whether codes can be had from `get_totp_code`.
+    pub has_totp: bool,
+    pub fields: Vec<CustomFieldDetails>,
+    pub tags: Vec<String>,
+    pub favorite: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<PasswordEntry> for PasswordEntryDetails {
+    fn from(entry: PasswordEntry) -> Self {
+        Self {
+            id: entry.id,
+            kind: entry.kind,
+            title: entry.title,
+            username: entry.username,
+            url: entry.url,
+            urls: entry.urls,
+            url_match: entry.url_match,
+            notes: entry.notes,
+            has_totp: entry.totp.is_some(),
+            fields: entry.fields.into_iter().map(Into::into).collect(),
+            tags: entry.tags,
+            favorite: entry.favorite,
+            created_at: entry.created_at,
+            updated_at: entry.updated_at,
+        }
+    }
+}
+
+/// Editable fields of an entry, as supplied when creating or updating one.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EntryInput {
+    #[serde(default)]
+    pub kind: EntryKind,
+    pub title: String,
+    pub username: String,
+    pub password: SecureString,
+    pub url: Option<String>,
+    #[serde(default)]
+    pub urls: Vec<String>,
+    #[serde(default)]
+    pub url_match: UrlMatch,
+    pub notes: Option<String>,
+    /// When updating, `None` leaves the stored seed as it is and an empty
+    /// seed removes it.
+    #[serde(default)]
+    pub totp: Option<SecureString>,
+    #[serde(default)]
+    pub fields: Vec<CustomField>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub favorite: bool,
+}
+
+/// What `generator::generate_password` makes. Every included class gets
+/// at least one character, or its minimum if that is more.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PasswordOptions {
     pub length: u32,
@@ -53,6 +238,29 @@ pub struct PasswordOptions {
     pub include_numbers: bool,
     pub include_symbols: bool,
     pub exclude_ambiguous: bool,
+    #[serde(default)]
+    pub min_uppercase: u32,
+    #[serde(default)]
+    pub min_lowercase: u32,
+    #[serde(default)]
+    pub min_numbers: u32,
+    #[serde(default)]
+    pub min_symbols: u32,
+    /// Characters to use besides those of the included classes.
+    #[serde(default)]
+    pub include_chars: String,
+    /// Characters never to use, such as symbols a site rejects.
+    #[serde(default)]
+    pub exclude_chars: String,
+    /// The longest run of one character; 1 allows no character twice in a
+    /// row.
+    #[serde(default)]
+    pub max_consecutive: Option<u32>,
+    /// Apple password rules (see `password::rules`), which take the place
+    /// of the classes and minimums above. The length is kept within the
+    /// rules' bounds, and the exclusions still apply.
+    #[serde(default)]
+    pub rules: Option<String>,
 }
 
 impl Default for PasswordOptions {
@@ -64,18 +272,98 @@ impl Default for PasswordOptions {
             include_numbers: true,
             include_symbols: true,
             exclude_ambiguous: true,
+            min_uppercase: 0,
+            min_lowercase: 0,
+            min_numbers: 0,
+            min_symbols: 0,
+            include_chars: String::new(),
+            exclude_chars: String::new(),
+            max_consecutive: None,
+            rules: None,
         }
     }
 }
 
+/// Per-vault preferences, stored inside the encrypted payload.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VaultSettings {
+    pub backup_generations: u32,
+    /// Idle time before the vault locks itself; 0 disables auto-lock.
+    pub auto_lock_secs: u64,
+    /// Previous passwords kept per entry; 0 keeps none.
+    pub password_history: u32,
+    /// Days a deleted entry stays in the trash before it is purged; 0 keeps
+    /// it until the trash is emptied.
+    pub trash_retention_days: u32,
+    /// Apple password rules by site, a host or a domain above it, used by
+    /// `generate_password_for_site` before the bundled presets.
+    pub password_rules: BTreeMap<String, String>,
+}
+
+impl Default for VaultSettings {
+    fn default() -> Self {
+        Self {
+            backup_generations: 5,
+            auto_lock_secs: 300,
+            password_history: 10,
+            trash_retention_days: 30,
+            password_rules: BTreeMap::new(),
+        }
+    }
+}
+
+/// Options for a diceware passphrase; see `generator::generate_passphrase`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PassphraseOptions {
+    pub words: u32,
+    pub separator: String,
+    pub case: WordCase,
+    /// Append a random digit to one of the words, for sites that demand
+    /// one.
+    pub include_digit: bool,
+}
+
+impl Default for PassphraseOptions {
+    fn default() -> Self {
+        Self {
+            words: 6,
+            separator: "-".to_string(),
+            case: WordCase::default(),
+            include_digit: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WordCase {
+    #[default]
+    Lowercase,
+    /// First letter of each word in uppercase.
+    Capitalized,
+    Uppercase,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Passphrase {
+    pub passphrase: String,
+    /// Bits, counting the words as drawn uniformly from the wordlist.
+    pub entropy: f64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StrengthScore {
-    pub score: u8, 
+    pub score: u8,
     pub entropy: f64,
     pub feedback: Vec<String>,
 }
 
-#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+/// A string that is wiped from memory when dropped and never printed by
+/// `Debug`. Serializes as a plain string.
+#[derive(Clone, Default, PartialEq, Eq, Zeroize, ZeroizeOnDrop, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct SecureString(String);
 
 impl SecureString {
@@ -87,8 +375,8 @@ impl SecureString {
         &self.0
     }
 
-    pub fn into_inner(self) -> String {
-        self.0
+    pub fn into_inner(mut self) -> String {
+        std::mem::take(&mut self.0)
     }
 }
 
@@ -0,0 +1,426 @@
+//! Matching entries to the page a browser is on, for autofill. An entry is
+//! offered for a URL when any of its URLs matches it under the entry's
+//! `UrlMatch` mode. Base domains come from the Public Suffix List, bundled
+//! as `public_suffix_list.dat` from <https://publicsuffix.org/list/>, so
+//! that `a.example.co.uk` and `b.example.co.uk` count as one site but
+//! `alice.github.io` and `bob.github.io` do not.
+
+use std::cmp::Reverse;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use publicsuffix::{List, Psl};
+use regex::Regex;
+use url::{Host, Url};
+
+use super::error::VaultError;
+use super::types::{PasswordEntry, PasswordEntrySummary, UrlMatch};
+
+static PUBLIC_SUFFIX_LIST: &str = include_str!("public_suffix_list.dat");
+
+fn public_suffixes() -> &'static List {
+    static LIST: OnceLock<List> = OnceLock::new();
+    LIST.get_or_init(|| {
+        PUBLIC_SUFFIX_LIST
+            .parse()
+            .expect("bundled public suffix list parses")
+    })
+}
+
+impl UrlMatch {
+    pub const ALL: [UrlMatch; 5] = [
+        UrlMatch::BaseDomain,
+        UrlMatch::Host,
+        UrlMatch::StartsWith,
+        UrlMatch::Regex,
+        UrlMatch::Never,
+    ];
+
+    /// The serialized name, e.g. `base_domain`.
+    pub fn name(self) -> &'static str {
+        match self {
+            UrlMatch::BaseDomain => "base_domain",
+            UrlMatch::Host => "host",
+            UrlMatch::StartsWith => "starts_with",
+            UrlMatch::Regex => "regex",
+            UrlMatch::Never => "never",
+        }
+    }
+}
+
+impl fmt::Display for UrlMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for UrlMatch {
+    type Err = VaultError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|mode| mode.name() == s)
+            .ok_or_else(|| VaultError::InvalidInput(format!("Unknown URL match mode '{}'", s)))
+    }
+}
+
+/// Parses a URL as a user types it or a browser reports it. One without a
+/// scheme is taken to be `https`, and the fragment is dropped; the `url`
+/// crate already lowercases and punycode-encodes the host and drops a
+/// scheme's default port.
+pub fn normalize(url: &str) -> Option<Url> {
+    let url = url.trim();
+    if url.is_empty() {
+        return None;
+    }
+    let mut url = if url.contains("://") {
+        Url::parse(url)
+    } else {
+        Url::parse(&format!("https://{}", url))
+    }
+    .ok()?;
+    url.set_fragment(None);
+    Some(url)
+}
+
+/// The registrable part of `host`, e.g. `example.co.uk` for
+/// `www.example.co.uk`. IP addresses, and names that are a public suffix
+/// themselves or have none, such as `localhost`, are their own base domain.
+pub fn base_domain(host: &Host<&str>) -> String {
+    match host {
+        Host::Domain(domain) => public_suffixes()
+            .domain(domain.trim_end_matches('.').as_bytes())
+            .and_then(|base| std::str::from_utf8(base.as_bytes()).ok())
+            .unwrap_or(domain)
+            .to_string(),
+        host => host.to_string(),
+    }
+}
+
+/// `host` and the domains above it up to its base domain, most specific
+/// first: `a.b.example.com`, `b.example.com`, `example.com`.
+pub fn site_domains(host: &Host<&str>) -> Vec<String> {
+    let base = base_domain(host);
+    let host = host.to_string();
+    let mut domains = vec![host.trim_end_matches('.').to_string()];
+    while domains.last() != Some(&base) {
+        let Some((_, parent)) = domains.last().and_then(|d| d.split_once('.')) else {
+            break;
+        };
+        domains.push(parent.to_string());
+    }
+    domains
+}
+
+/// Whether the page at `page` matches `pattern`, one of an entry's URLs,
+/// under `mode`. In `Regex` mode the pattern is matched, unanchored, against
+/// the whole normalized page URL; a pattern that does not compile matches
+/// nothing.
+pub fn matches(mode: UrlMatch, pattern: &str, page: &Url) -> bool {
+    rank(mode, pattern, page).is_some()
+}
+
+/// How closely `page` matches `pattern`, if at all: 0 for the same base
+/// domain, 1 for the same host and 2 for a match on the path too.
+fn rank(mode: UrlMatch, pattern: &str, page: &Url) -> Option<u8> {
+    match mode {
+        UrlMatch::Never => None,
+        UrlMatch::Regex => Regex::new(pattern)
+            .ok()
+            .filter(|regex| regex.is_match(page.as_str()))
+            .map(|_| 2),
+        // A normalized URL always has a path, so `https://example.com`
+        // becomes `https://example.com/` and cannot match
+        // `https://example.com.evil.test/`.
+        UrlMatch::StartsWith => normalize(pattern)
+            .filter(|pattern| page.as_str().starts_with(pattern.as_str()))
+            .map(|_| 2),
+        UrlMatch::Host | UrlMatch::BaseDomain => {
+            let pattern = normalize(pattern)?;
+            let (host, page_host) = (pattern.host()?, page.host()?);
+            // A port is only compared when the entry's URL names one.
+            if pattern
+                .port()
+                .is_some_and(|port| Some(port) != page.port_or_known_default())
+            {
+                return None;
+            }
+            if host == page_host {
+                Some(1)
+            } else if mode == UrlMatch::BaseDomain && base_domain(&host) == base_domain(&page_host)
+            {
+                Some(0)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// The entry's URLs: `url` first, then the others.
+pub fn entry_urls(entry: &PasswordEntry) -> impl Iterator<Item = &str> {
+    entry
+        .url
+        .as_deref()
+        .into_iter()
+        .chain(entry.urls.iter().map(String::as_str))
+}
+
+/// The entries offered for `url`: those matching on the path first, then on
+/// the host, then on the base domain, and by title within each.
+pub fn find_entries(entries: &[PasswordEntry], url: &str) -> Vec<PasswordEntrySummary> {
+    let Some(page) = normalize(url) else {
+        return Vec::new();
+    };
+
+    let mut found: Vec<(u8, &PasswordEntry)> = entries
+        .iter()
+        .filter_map(|entry| {
+            let best = entry_urls(entry)
+                .filter_map(|pattern| rank(entry.url_match, pattern, &page))
+                .max()?;
+            Some((best, entry))
+        })
+        .collect();
+
+    found.sort_by_key(|(rank, entry)| (Reverse(*rank), entry.title.to_lowercase()));
+    found
+        .into_iter()
+        .map(|(_, entry)| entry.clone().into())
+        .collect()
+}
+
+/// An entry's further URLs without blank ones and any repeating another.
+pub fn clean(url: Option<&str>, urls: Vec<String>) -> Vec<String> {
+    let mut kept: Vec<String> = Vec::with_capacity(urls.len());
+    for other in urls {
+        let other = other.trim();
+        if !other.is_empty() && Some(other) != url && !kept.iter().any(|u| u == other) {
+            kept.push(other.to_string());
+        }
+    }
+    kept
+}
+
+/// In `Regex` mode, rejects an entry whose URLs are not all patterns that
+/// compile.
+pub fn check(mode: UrlMatch, url: Option<&str>, urls: &[String]) -> Result<(), VaultError> {
+    if mode != UrlMatch::Regex {
+        return Ok(());
+    }
+    for pattern in url.into_iter().chain(urls.iter().map(String::as_str)) {
+        Regex::new(pattern).map_err(|e| {
+            VaultError::InvalidInput(format!("'{}' is not a valid pattern: {}", pattern, e))
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    fn entry(title: &str, mode: UrlMatch, urls: &[&str]) -> PasswordEntry {
+        PasswordEntry {
+            id: Uuid::new_v4(),
+            kind: Default::default(),
+            title: title.to_string(),
+            username: String::new(),
+            password: "pw".to_string().into(),
+            url: urls.first().map(|u| u.to_string()),
+            urls: urls.iter().skip(1).map(|u| u.to_string()).collect(),
+            url_match: mode,
+            notes: None,
+            totp: None,
+            fields: Vec::new(),
+            history: Vec::new(),
+            tags: Vec::new(),
+            favorite: false,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            deleted_at: None,
+        }
+    }
+
+    fn page(url: &str) -> Url {
+        normalize(url).unwrap()
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            normalize("  WWW.Example.COM/Login#top ").unwrap().as_str(),
+            "https://www.example.com/Login"
+        );
+        assert_eq!(
+            normalize("http://example.com:80").unwrap().as_str(),
+            "http://example.com/"
+        );
+        assert_eq!(
+            normalize("localhost:3000").unwrap().as_str(),
+            "https://localhost:3000/"
+        );
+        assert_eq!(
+            normalize("https://bücher.de").unwrap().host_str(),
+            Some("xn--bcher-kva.de")
+        );
+        assert!(normalize("   ").is_none());
+        assert!(normalize("https://exa mple.com").is_none());
+    }
+
+    #[test]
+    fn test_base_domain() {
+        let base = |url: &str| base_domain(&page(url).host().unwrap());
+        assert_eq!(base("https://accounts.google.com"), "google.com");
+        assert_eq!(base("https://www.bbc.co.uk"), "bbc.co.uk");
+        assert_eq!(base("https://alice.github.io"), "alice.github.io");
+        assert_eq!(base("https://localhost:8080"), "localhost");
+        assert_eq!(base("http://192.168.1.1"), "192.168.1.1");
+        assert_eq!(base("http://[::1]"), "[::1]");
+    }
+
+    #[test]
+    fn test_site_domains() {
+        let domains = |url: &str| site_domains(&page(url).host().unwrap());
+        assert_eq!(
+            domains("https://a.login.example.co.uk."),
+            vec![
+                "a.login.example.co.uk",
+                "login.example.co.uk",
+                "example.co.uk"
+            ]
+        );
+        assert_eq!(domains("https://example.com"), vec!["example.com"]);
+        assert_eq!(domains("http://192.168.1.1"), vec!["192.168.1.1"]);
+        assert_eq!(domains("http://localhost"), vec!["localhost"]);
+    }
+
+    #[test]
+    fn test_match_modes() {
+        let login = page("https://accounts.example.co.uk/login?next=/");
+
+        assert!(matches(UrlMatch::BaseDomain, "example.co.uk", &login));
+        assert!(matches(
+            UrlMatch::BaseDomain,
+            "http://www.example.co.uk",
+            &login
+        ));
+        assert!(!matches(UrlMatch::BaseDomain, "other.co.uk", &login));
+        assert!(!matches(
+            UrlMatch::BaseDomain,
+            "https://bob.github.io",
+            &page("https://alice.github.io")
+        ));
+
+        assert!(matches(UrlMatch::Host, "accounts.example.co.uk", &login));
+        assert!(!matches(UrlMatch::Host, "www.example.co.uk", &login));
+        // Ports count only when the entry names one.
+        assert!(matches(
+            UrlMatch::Host,
+            "localhost",
+            &page("localhost:3000")
+        ));
+        assert!(!matches(
+            UrlMatch::Host,
+            "localhost:8080",
+            &page("localhost:3000")
+        ));
+        assert!(matches(
+            UrlMatch::Host,
+            "https://example.com:443",
+            &page("https://example.com")
+        ));
+
+        assert!(matches(
+            UrlMatch::StartsWith,
+            "accounts.example.co.uk/login",
+            &login
+        ));
+        assert!(!matches(
+            UrlMatch::StartsWith,
+            "https://accounts.example.co.uk/admin",
+            &login
+        ));
+        assert!(!matches(
+            UrlMatch::StartsWith,
+            "https://example.com",
+            &page("https://example.com.evil.test/")
+        ));
+
+        assert!(matches(
+            UrlMatch::Regex,
+            r"^https://[a-z]+\.example\.co\.uk/login",
+            &login
+        ));
+        assert!(!matches(UrlMatch::Regex, r"^http://", &login));
+        assert!(!matches(UrlMatch::Regex, "(", &login));
+
+        assert!(!matches(
+            UrlMatch::Never,
+            "https://accounts.example.co.uk",
+            &login
+        ));
+    }
+
+    #[test]
+    fn test_find_entries() {
+        let entries = vec![
+            entry("Example", UrlMatch::BaseDomain, &["https://example.com"]),
+            entry(
+                "Example admin",
+                UrlMatch::StartsWith,
+                &["https://example.com/admin", "https://admin.example.com"],
+            ),
+            entry(
+                "Mail",
+                UrlMatch::Host,
+                &["https://gmail.com", "mail.example.com"],
+            ),
+            entry("Hidden", UrlMatch::Never, &["https://mail.example.com"]),
+            entry("Other", UrlMatch::BaseDomain, &["https://example.org"]),
+        ];
+        let titles = |url: &str| {
+            find_entries(&entries, url)
+                .into_iter()
+                .map(|e| e.title)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            titles("https://mail.example.com/inbox"),
+            ["Mail", "Example"]
+        );
+        assert_eq!(
+            titles("https://admin.example.com/users"),
+            ["Example admin", "Example"]
+        );
+        assert_eq!(titles("example.org"), ["Other"]);
+        assert!(titles("https://example.net").is_empty());
+        assert!(titles("not a url").is_empty());
+    }
+
+    #[test]
+    fn test_clean_and_check() {
+        let urls = clean(
+            Some("https://example.com"),
+            vec![
+                " https://example.org ".to_string(),
+                String::new(),
+                "https://example.com".to_string(),
+                "https://example.org".to_string(),
+            ],
+        );
+        assert_eq!(urls, ["https://example.org"]);
+
+        assert!(check(UrlMatch::BaseDomain, Some("("), &[]).is_ok());
+        assert!(check(UrlMatch::Regex, Some("^https://"), &urls).is_ok());
+        assert!(matches!(
+            check(UrlMatch::Regex, None, &["(".to_string()]),
+            Err(VaultError::InvalidInput(_))
+        ));
+    }
+}
@@ -1,385 +1,1840 @@
-use chrono::Utc;
-use rand::Rng;
-use std::sync::Mutex;
-use tauri::{AppHandle, Manager, State};
+use chrono::{Duration as TimeDelta, Utc};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 use zeroize::Zeroizing;
 
-use super::crypto::{decrypt, derive_key, encrypt, generate_salt};
-use super::storage::{self, VaultData};
-use super::types::{PasswordEntry, PasswordEntrySummary, PasswordOptions, SecureString, StrengthScore};
-
-pub struct VaultState {
-    pub is_unlocked: bool,
-    pub encryption_key: Option<[u8; 32]>,
-    pub salt: Option<[u8; 32]>,
-    pub entries: Vec<PasswordEntry>,
+use super::backend::{StorageKind, StorageLock, VaultStorage};
+use super::crypto::{
+    self, decrypt_with_aad, derive_key_with_params, encrypt_with_aad, generate_salt, KdfParams,
+    VaultKey,
+};
+use super::error::VaultError;
+use super::export::{self, ExportFormat};
+use super::fields;
+use super::import::{self, ImportOptions, ImportReport, ImportedEntry};
+use super::kdbx::{self, KdbxDatabase};
+use super::rules::{self, PasswordRules};
+use super::search::{self, SearchFilters, SearchResult};
+use super::storage::{self, BackupInfo, VaultData, VaultHeader, MAX_BACKUP_GENERATIONS};
+use super::totp::{Totp, TotpCode};
+use super::types::{
+    CustomField, EntryInput, PasswordEntry, PasswordEntrySummary, PasswordHistoryItem,
+    SecureString, VaultSettings,
+};
+use super::urls;
+
+/// The most previous passwords `VaultSettings::password_history` may keep.
+pub const MAX_PASSWORD_HISTORY: u32 = 100;
+
+/// A vault in some storage and, while unlocked, its decrypted contents and key. This is
+/// the whole vault logic with no dependency on Tauri; the commands in
+/// `password::commands` and the CLI are thin wrappers around it.
+///
+/// Every method that reads or changes entries fails with `Locked` unless
+/// the vault is unlocked, and counts as activity for the auto-lock timer.
+///
+/// A vault may also be a KeePass KDBX 4 database (see `password::kdbx`),
+/// detected when unlocking; `key` and `header` are then unused.
+pub struct Vault {
+    storage: Box<dyn VaultStorage>,
+    key: Option<VaultKey>,
+    header: Option<VaultHeader>,
+    kdbx: Option<KdbxDatabase>,
+    settings: VaultSettings,
+    entries: Vec<PasswordEntry>,
+    /// Deleted entries, until restored or purged.
+    trash: Vec<PasswordEntry>,
+    /// Held while unlocked so no other process writes the same vault.
+    file_lock: Option<StorageLock>,
+    /// Last vault operation; the auto-lock timer measures idleness from here.
+    last_activity: Instant,
 }
 
-impl VaultState {
-    pub fn new() -> Self {
+impl Vault {
+    /// A locked handle on the vault at `path`, which need not exist yet. The
+    /// layout is detected from what is at `path`; new vaults are single files.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self::with_storage(StorageKind::detect(&path).open(path))
+    }
+
+    pub fn with_storage(storage: Box<dyn VaultStorage>) -> Self {
         Self {
-            is_unlocked: false,
-            encryption_key: None,
-            salt: None,
+            storage,
+            key: None,
+            header: None,
+            kdbx: None,
+            settings: VaultSettings::default(),
+            entries: Vec::new(),
+            trash: Vec::new(),
+            file_lock: None,
+            last_activity: Instant::now(),
+        }
+    }
+
+    pub fn storage(&self) -> &dyn VaultStorage {
+        self.storage.as_ref()
+    }
+
+    pub fn exists(&self) -> bool {
+        self.storage.exists()
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.key.is_some() || self.kdbx.is_some()
+    }
+
+    /// Whether the vault is unlocked and is a KeePass database.
+    pub fn is_kdbx(&self) -> bool {
+        self.kdbx.is_some()
+    }
+
+    /// Creates a new, empty vault file and leaves it unlocked. `kdf_params`
+    /// are used as given; callers validate or calibrate them first. A vault
+    /// whose location ends in `.kdbx` is created as a KeePass database.
+    pub fn create(
+        &mut self,
+        master_password: &str,
+        kdf_params: KdfParams,
+    ) -> Result<(), VaultError> {
+        // An existing vault is refused even while someone else holds it; the
+        // check is repeated under the lock for a create racing this one.
+        if self.exists() {
+            return Err(VaultError::AlreadyExists);
+        }
+        self.ensure_file_lock()?;
+
+        if self.exists() {
+            self.release_unless_unlocked();
+            return Err(VaultError::AlreadyExists);
+        }
+
+        let vault_data = VaultData {
+            version: 1,
+            settings: VaultSettings::default(),
             entries: Vec::new(),
+            trash: Vec::new(),
+        };
+
+        if self.storage.location().to_lowercase().ends_with(".kdbx") {
+            let mut db = KdbxDatabase::create(master_password, kdf_params)?;
+            let file = db.seal(&vault_data)?;
+            self.storage
+                .save(&file, None, vault_data.settings.backup_generations)?;
+            db.mark_saved(&file);
+            self.set_unlocked_kdbx(db, vault_data);
+            return Ok(());
+        }
+
+        let header = VaultHeader::new(generate_salt(), kdf_params);
+        let key = derive_key_with_params(master_password, &header.salt, &header.kdf_params)?;
+        let encrypted = seal_vault(&header, &key, &vault_data)?;
+
+        self.storage
+            .save(&encrypted, None, vault_data.settings.backup_generations)?;
+
+        // A wrapped key left over from a previous vault at this path is useless.
+        let _ = self.storage.delete_quick_unlock();
+
+        self.set_unlocked(header, key, vault_data);
+
+        Ok(())
+    }
+
+    pub fn unlock(&mut self, master_password: &str) -> Result<(), VaultError> {
+        self.ensure_file_lock()?;
+
+        let opened = self
+            .storage
+            .load()
+            .and_then(|encrypted| open_any(&encrypted, master_password));
+        let (mut header, mut key, vault_data) = match opened {
+            Ok(Opened::Zims(header, key, vault_data)) => (header, key, vault_data),
+            Ok(Opened::Kdbx(db, vault_data)) => {
+                self.set_unlocked_kdbx(*db, vault_data);
+                return Ok(());
+            }
+            Err(e) => {
+                self.release_unless_unlocked();
+                return Err(e);
+            }
+        };
+
+        // Vaults whose KDF parameters fell below policy are re-keyed on the
+        // spot. This is best-effort: on failure the vault simply stays as it is.
+        // Re-keying invalidates any quick-unlock credential, so that is revoked.
+        if !header.kdf_params.meets_policy() {
+            let upgraded = reencrypt(
+                &header,
+                master_password,
+                header.kdf_params.strengthened(),
+                &vault_data,
+            )
+            .and_then(|(new_header, new_key, encrypted)| {
+                self.storage.save(
+                    &encrypted,
+                    Some(header.generation),
+                    vault_data.settings.backup_generations,
+                )?;
+                let _ = self.storage.delete_quick_unlock();
+                Ok((new_header, new_key))
+            });
+
+            if let Ok((new_header, new_key)) = upgraded {
+                header = new_header;
+                key = new_key;
+            }
+        }
+
+        self.set_unlocked(header, key, vault_data);
+
+        Ok(())
+    }
+
+    /// Opens the vault with the key remembered by `enable_quick_unlock`. If
+    /// the credential no longer opens the vault it is revoked, and
+    /// `NotFound` is returned so the caller falls back to the master password.
+    pub fn unlock_with_device_key(
+        &mut self,
+        device_key: &keyring::Entry,
+    ) -> Result<(), VaultError> {
+        self.ensure_file_lock()?;
+
+        let encrypted = match self.storage.load() {
+            Ok(encrypted) => encrypted,
+            Err(e) => {
+                self.release_unless_unlocked();
+                return Err(e);
+            }
+        };
+        let opened = open_vault_with_device_key(&encrypted, self.storage.as_ref(), device_key);
+        let (header, key, vault_data) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                self.release_unless_unlocked();
+                // A wrapped key that no longer opens the vault, or whose
+                // device key is gone, is of no use; drop both halves.
+                if let VaultError::WrongPassword
+                | VaultError::Corrupted(_)
+                | VaultError::NotFound(_) = e
+                {
+                    let _ = forget_key(self.storage.as_ref(), device_key);
+                    return Err(VaultError::NotFound("Quick unlock credential".to_string()));
+                }
+                return Err(e);
+            }
+        };
+
+        self.set_unlocked(header, key, vault_data);
+
+        Ok(())
+    }
+
+    /// Remembers the vault key on this device: it is wrapped under a fresh
+    /// random device key, which goes into the OS keyring, and the wrapped key
+    /// is stored next to the vault. Neither half alone opens the vault.
+    pub fn enable_quick_unlock(&mut self, device_key: &keyring::Entry) -> Result<(), VaultError> {
+        self.ensure_unlocked()?;
+
+        if self.kdbx.is_some() {
+            return Err(VaultError::Unsupported(
+                "Quick unlock is not available for KeePass databases".to_string(),
+            ));
+        }
+
+        let key = self.key.as_ref().ok_or(VaultError::Locked)?;
+        let header = self.header.as_ref().ok_or(VaultError::Locked)?;
+
+        remember_key(self.storage.as_ref(), device_key, key, header)
+    }
+
+    /// Revokes quick unlock. Works whether or not the vault is unlocked.
+    pub fn disable_quick_unlock(&self, device_key: &keyring::Entry) -> Result<(), VaultError> {
+        forget_key(self.storage.as_ref(), device_key)
+    }
+
+    pub fn quick_unlock_enabled(&self) -> bool {
+        self.storage.has_quick_unlock()
+    }
+
+    fn set_unlocked(&mut self, header: VaultHeader, key: VaultKey, vault_data: VaultData) {
+        self.key = Some(key);
+        self.header = Some(header);
+        self.kdbx = None;
+        self.settings = vault_data.settings;
+        self.entries = vault_data.entries;
+        self.trash = vault_data.trash;
+        self.last_activity = Instant::now();
+        self.purge_expired_on_unlock();
+    }
+
+    fn set_unlocked_kdbx(&mut self, db: KdbxDatabase, vault_data: VaultData) {
+        self.key = None;
+        self.header = None;
+        self.kdbx = Some(db);
+        self.settings = vault_data.settings;
+        self.entries = vault_data.entries;
+        self.trash = vault_data.trash;
+        self.last_activity = Instant::now();
+        self.purge_expired_on_unlock();
+    }
+
+    /// Purging is best-effort, like the KDF upgrade in `unlock`: if the save
+    /// fails, the entries are dropped again on the next save or unlock.
+    fn purge_expired_on_unlock(&mut self) {
+        if self.purge_expired() {
+            let _ = self.save();
+        }
+    }
+
+    /// Drops trashed entries older than `VaultSettings::trash_retention_days`,
+    /// returning whether there were any.
+    fn purge_expired(&mut self) -> bool {
+        let days = self.settings.trash_retention_days;
+        if days == 0 {
+            return false;
         }
+
+        let cutoff = Utc::now() - TimeDelta::days(days.into());
+        let before = self.trash.len();
+        self.trash.retain(|entry| {
+            entry
+                .deleted_at
+                .is_none_or(|deleted_at| deleted_at > cutoff)
+        });
+        self.trash.len() != before
+    }
+
+    /// Fails with `Locked` unless unlocked, and counts as activity for the
+    /// auto-lock timer.
+    pub fn ensure_unlocked(&mut self) -> Result<(), VaultError> {
+        if !self.is_unlocked() {
+            return Err(VaultError::Locked);
+        }
+        self.last_activity = Instant::now();
+        Ok(())
+    }
+
+    /// Whether the vault is unlocked and has been idle longer than its
+    /// configured auto-lock period (0 disables auto-lock).
+    pub fn idle_expired(&self, now: Instant) -> bool {
+        self.is_unlocked()
+            && self.settings.auto_lock_secs > 0
+            && now.saturating_duration_since(self.last_activity)
+                >= Duration::from_secs(self.settings.auto_lock_secs)
     }
 
     pub fn lock(&mut self) {
-        self.is_unlocked = false;
-        self.encryption_key = None;
+        self.key = None;
+        self.header = None;
+        self.kdbx = None;
+        self.settings = VaultSettings::default();
         self.entries.clear();
+        self.trash.clear();
+        self.file_lock = None;
     }
-}
 
-pub type VaultStateMutex = Mutex<VaultState>;
+    pub fn entries(&mut self) -> Result<&[PasswordEntry], VaultError> {
+        self.ensure_unlocked()?;
+        Ok(&self.entries)
+    }
+
+    pub fn entry(&mut self, id: Uuid) -> Result<&PasswordEntry, VaultError> {
+        self.ensure_unlocked()?;
+
+        self.entries
+            .iter()
+            .find(|e| e.id == id)
+            .ok_or_else(|| VaultError::NotFound("Password entry".to_string()))
+    }
 
-#[tauri::command]
-pub async fn initialize_vault(
-    app: AppHandle,
-    master_password: String,
-    state: State<'_, VaultStateMutex>,
-) -> Result<(), String> {
-    if storage::vault_exists(&app) {
-        return Err("Vault already exists".to_string());
+    /// Entries matching `query` and `filters`, best match first; see
+    /// `password::search`. Trashed entries are left out.
+    pub fn search_entries(
+        &mut self,
+        query: &str,
+        filters: &SearchFilters,
+    ) -> Result<Vec<SearchResult>, VaultError> {
+        self.ensure_unlocked()?;
+        Ok(search::search(&self.entries, query, filters))
     }
 
-    let salt = generate_salt();
+    /// Entries to offer for the page at `url`, best match first; see
+    /// `password::urls`. Trashed entries are left out.
+    pub fn find_entries_for_url(
+        &mut self,
+        url: &str,
+    ) -> Result<Vec<PasswordEntrySummary>, VaultError> {
+        self.ensure_unlocked()?;
+        if urls::normalize(url).is_none() {
+            return Err(VaultError::InvalidInput(format!("'{}' is not a URL", url)));
+        }
+        Ok(urls::find_entries(&self.entries, url))
+    }
 
-    let key = derive_key(&master_password, &salt).map_err(|e| e.to_string())?;
+    /// The password rules for the site at `url`: those `settings.password_rules`
+    /// has for its host or a domain above it, up to its base domain, or else
+    /// the bundled preset for one of those, most specific first.
+    pub fn password_rules_for(&mut self, url: &str) -> Result<Option<String>, VaultError> {
+        self.ensure_unlocked()?;
+        let page = urls::normalize(url)
+            .ok_or_else(|| VaultError::InvalidInput(format!("'{}' is not a URL", url)))?;
+        let Some(host) = page.host() else {
+            return Ok(None);
+        };
+
+        let domains = urls::site_domains(&host);
+        let own = &self.settings.password_rules;
+        Ok(domains
+            .iter()
+            .find_map(|domain| own.get(domain).cloned())
+            .or_else(|| {
+                domains
+                    .iter()
+                    .find_map(|domain| rules::site_preset(domain))
+                    .map(str::to_string)
+            }))
+    }
 
-    let vault_data = VaultData {
-        version: 1,
-        salt,
-        entries: Vec::new(),
-    };
+    pub fn add_entry(&mut self, input: EntryInput) -> Result<Uuid, VaultError> {
+        self.ensure_unlocked()?;
+
+        fields::validate(&input)?;
+        let totp = check_totp(input.totp)?;
+        let urls = urls::clean(input.url.as_deref(), input.urls);
+        urls::check(input.url_match, input.url.as_deref(), &urls)?;
+
+        let entry = PasswordEntry {
+            id: Uuid::new_v4(),
+            kind: input.kind,
+            title: input.title,
+            username: input.username,
+            password: input.password,
+            url: input.url,
+            urls,
+            url_match: input.url_match,
+            notes: input.notes,
+            totp,
+            fields: trim_names(input.fields),
+            history: Vec::new(),
+            tags: input.tags,
+            favorite: input.favorite,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            deleted_at: None,
+        };
+
+        let id = entry.id;
+        self.entries.push(entry);
+
+        self.save()?;
+
+        Ok(id)
+    }
+
+    pub fn update_entry(&mut self, id: Uuid, input: EntryInput) -> Result<(), VaultError> {
+        self.ensure_unlocked()?;
+
+        fields::validate(&input)?;
+        // `None` keeps the stored seed; an empty one removes it.
+        let totp = input.totp.map(|seed| check_totp(Some(seed))).transpose()?;
+        let urls = urls::clean(input.url.as_deref(), input.urls);
+        urls::check(input.url_match, input.url.as_deref(), &urls)?;
+
+        let limit = self.settings.password_history as usize;
+        let entry = self
+            .entries
+            .iter_mut()
+            .find(|e| e.id == id)
+            .ok_or_else(|| VaultError::NotFound("Password entry".to_string()))?;
+
+        if entry.password != input.password {
+            let old = std::mem::replace(&mut entry.password, input.password);
+            push_history(entry, old, limit);
+        }
+        entry.kind = input.kind;
+        entry.title = input.title;
+        entry.username = input.username;
+        entry.url = input.url;
+        entry.urls = urls;
+        entry.url_match = input.url_match;
+        entry.notes = input.notes;
+        if let Some(totp) = totp {
+            entry.totp = totp;
+        }
+        entry.fields = trim_names(input.fields);
+        entry.tags = input.tags;
+        entry.favorite = input.favorite;
+        entry.updated_at = Utc::now();
+
+        self.save()
+    }
+
+    /// The entry's previous passwords, newest first.
+    pub fn password_history(&mut self, id: Uuid) -> Result<&[PasswordHistoryItem], VaultError> {
+        Ok(&self.entry(id)?.history)
+    }
+
+    /// Makes the password at `index` in the entry's history current again.
+    /// The password it replaces goes into the history in turn, so that a
+    /// restore can itself be undone.
+    pub fn restore_password(&mut self, id: Uuid, index: usize) -> Result<(), VaultError> {
+        self.ensure_unlocked()?;
+
+        let limit = self.settings.password_history as usize;
+        let entry = self
+            .entries
+            .iter_mut()
+            .find(|e| e.id == id)
+            .ok_or_else(|| VaultError::NotFound("Password entry".to_string()))?;
+        if index >= entry.history.len() {
+            return Err(VaultError::NotFound("Previous password".to_string()));
+        }
+
+        let restored = entry.history.remove(index);
+        let old = std::mem::replace(&mut entry.password, restored.password);
+        push_history(entry, old, limit);
+        entry.updated_at = Utc::now();
+
+        self.save()
+    }
+
+    /// The current code for the entry's two-factor seed.
+    pub fn totp_code(&mut self, id: Uuid) -> Result<TotpCode, VaultError> {
+        let seed = self
+            .entry(id)?
+            .totp
+            .as_ref()
+            .ok_or_else(|| VaultError::NotFound("TOTP secret".to_string()))?;
+
+        Ok(Totp::parse(seed.as_str())?.now())
+    }
+
+    /// Moves the entry to the trash, from where `restore_entry` brings it
+    /// back until it is purged.
+    pub fn delete_entry(&mut self, id: Uuid) -> Result<(), VaultError> {
+        self.ensure_unlocked()?;
+
+        let index = self
+            .entries
+            .iter()
+            .position(|e| e.id == id)
+            .ok_or_else(|| VaultError::NotFound("Password entry".to_string()))?;
+
+        let mut entry = self.entries.remove(index);
+        entry.deleted_at = Some(Utc::now());
+        self.trash.push(entry);
+        self.purge_expired();
+
+        self.save()
+    }
+
+    /// Deleted entries, oldest deletion first.
+    pub fn trash(&mut self) -> Result<&[PasswordEntry], VaultError> {
+        self.ensure_unlocked()?;
+        Ok(&self.trash)
+    }
 
-    let json = serde_json::to_vec(&vault_data).map_err(|e| e.to_string())?;
-    let encrypted = encrypt(&json, &key).map_err(|e| e.to_string())?;
+    /// Moves a trashed entry back among the others.
+    pub fn restore_entry(&mut self, id: Uuid) -> Result<(), VaultError> {
+        self.ensure_unlocked()?;
 
-    storage::save_vault(&app, &encrypted).map_err(|e| e.to_string())?;
+        let index = self.trash_index(id)?;
+        let mut entry = self.trash.remove(index);
+        entry.deleted_at = None;
+        self.entries.push(entry);
 
-    let key_hash = hex::encode(key);
-    storage::store_master_key(&key_hash).map_err(|e| e.to_string())?;
+        self.save()
+    }
+
+    /// Deletes a trashed entry for good.
+    pub fn purge_entry(&mut self, id: Uuid) -> Result<(), VaultError> {
+        self.ensure_unlocked()?;
+
+        let index = self.trash_index(id)?;
+        self.trash.remove(index);
+
+        self.save()
+    }
+
+    /// Deletes every trashed entry for good, returning how many there were.
+    pub fn empty_trash(&mut self) -> Result<usize, VaultError> {
+        self.ensure_unlocked()?;
+
+        let purged = self.trash.len();
+        if purged > 0 {
+            self.trash.clear();
+            self.save()?;
+        }
+        Ok(purged)
+    }
+
+    fn trash_index(&self, id: Uuid) -> Result<usize, VaultError> {
+        self.trash
+            .iter()
+            .position(|e| e.id == id)
+            .ok_or_else(|| VaultError::NotFound("Trashed entry".to_string()))
+    }
+
+    /// Adds entries read by `password::import`, reporting which ones look
+    /// like duplicates of existing entries. A dry run only builds the report.
+    pub fn import_entries(
+        &mut self,
+        entries: Vec<PasswordEntry>,
+        options: &ImportOptions,
+    ) -> Result<ImportReport, VaultError> {
+        self.ensure_unlocked()?;
+
+        let duplicates = import::find_duplicates(&self.entries, &entries);
+        let mut report = ImportReport {
+            dry_run: options.dry_run,
+            ..ImportReport::default()
+        };
+        let mut accepted = Vec::new();
+        for (entry, duplicate_of) in entries.into_iter().zip(duplicates) {
+            let skipped = options.skip_duplicates && duplicate_of.is_some();
+            report.entries.push(ImportedEntry {
+                summary: entry.clone().into(),
+                duplicate_of,
+                skipped,
+            });
+            if skipped {
+                report.skipped += 1;
+            } else {
+                accepted.push(entry);
+            }
+        }
+        report.imported = accepted.len();
+
+        if !options.dry_run && !accepted.is_empty() {
+            for entry in &mut accepted {
+                entry
+                    .history
+                    .truncate(self.settings.password_history as usize);
+            }
+            self.entries.extend(accepted);
+            self.save()?;
+        }
+
+        Ok(report)
+    }
+
+    /// A standalone vault file holding the entries with any of `tags` (all
+    /// of them when `tags` is empty), encrypted under `passphrase` rather
+    /// than the master password, with this vault's KDF parameters.
+    pub fn export_encrypted(
+        &mut self,
+        passphrase: &str,
+        tags: &[String],
+    ) -> Result<Vec<u8>, VaultError> {
+        self.ensure_unlocked()?;
+
+        if passphrase.is_empty() {
+            return Err(VaultError::InvalidInput(
+                "Export passphrase cannot be empty".to_string(),
+            ));
+        }
+
+        let header = VaultHeader::new(generate_salt(), self.kdf_params()?);
+        let key = derive_key_with_params(passphrase, &header.salt, &header.kdf_params)?;
+
+        let vault_data = VaultData {
+            version: 1,
+            settings: VaultSettings::default(),
+            entries: export::filter_by_tags(&self.entries, tags)
+                .cloned()
+                .collect(),
+            trash: Vec::new(),
+        };
+
+        seal_vault(&header, &key, &vault_data)
+    }
+
+    /// The entries with any of `tags` in a plaintext `format`. Since this
+    /// writes every secret out unprotected, `master_password` has to be
+    /// given again and is verified against the file on disk.
+    pub fn export_plaintext(
+        &mut self,
+        master_password: &str,
+        format: ExportFormat,
+        tags: &[String],
+    ) -> Result<Zeroizing<Vec<u8>>, VaultError> {
+        self.ensure_unlocked()?;
 
-    let mut vault_state = state.lock().unwrap();
-    vault_state.is_unlocked = true;
-    vault_state.encryption_key = Some(key);
-    vault_state.salt = Some(salt);
-    vault_state.entries = Vec::new();
+        open_any(&self.storage.load()?, master_password)?;
 
-    Ok(())
+        export::plaintext(format, export::filter_by_tags(&self.entries, tags))
+    }
+
+    pub fn settings(&mut self) -> Result<&VaultSettings, VaultError> {
+        self.ensure_unlocked()?;
+        Ok(&self.settings)
+    }
+
+    pub fn update_settings(&mut self, mut settings: VaultSettings) -> Result<(), VaultError> {
+        self.ensure_unlocked()?;
+
+        if settings.backup_generations > MAX_BACKUP_GENERATIONS {
+            return Err(VaultError::InvalidInput(format!(
+                "At most {} backup generations are supported",
+                MAX_BACKUP_GENERATIONS
+            )));
+        }
+        if settings.password_history > MAX_PASSWORD_HISTORY {
+            return Err(VaultError::InvalidInput(format!(
+                "At most {} previous passwords can be kept",
+                MAX_PASSWORD_HISTORY
+            )));
+        }
+
+        settings.password_rules = settings
+            .password_rules
+            .into_iter()
+            .map(|(site, rules)| {
+                PasswordRules::parse(&rules).map_err(|e| match e {
+                    VaultError::InvalidInput(message) => VaultError::InvalidInput(format!(
+                        "Password rules for {}: {}",
+                        site, message
+                    )),
+                    e => e,
+                })?;
+                Ok((site.trim().trim_end_matches('.').to_lowercase(), rules))
+            })
+            .collect::<Result<_, VaultError>>()?;
+
+        for entry in self.entries.iter_mut().chain(&mut self.trash) {
+            entry.history.truncate(settings.password_history as usize);
+        }
+        self.settings = settings;
+        self.purge_expired();
+
+        self.save()
+    }
+
+    pub fn list_backups(&self) -> Result<Vec<BackupInfo>, VaultError> {
+        self.storage.list_backups()
+    }
+
+    /// Replaces the current vault with backup `generation` once it has been
+    /// verified to decrypt under `master_password`. The replaced file is
+    /// itself rotated into the backup ring, so a restore can be undone.
+    pub fn restore_backup(
+        &mut self,
+        generation: u32,
+        master_password: &str,
+    ) -> Result<(), VaultError> {
+        self.ensure_file_lock()?;
+
+        let opened = self
+            .storage
+            .load_backup(generation)
+            .and_then(|encrypted| Ok((open_any(&encrypted, master_password)?, encrypted)));
+        let (opened, encrypted) = match opened {
+            Ok(opened) => opened,
+            Err(e) => {
+                self.release_unless_unlocked();
+                return Err(e);
+            }
+        };
+
+        // Restoring deliberately rolls the generation back, so skip the check.
+        match opened {
+            Opened::Zims(header, key, vault_data) => {
+                self.storage
+                    .save(&encrypted, None, vault_data.settings.backup_generations)?;
+                self.set_unlocked(header, key, vault_data);
+            }
+            Opened::Kdbx(db, vault_data) => {
+                self.storage
+                    .save(&encrypted, None, vault_data.settings.backup_generations)?;
+                self.set_unlocked_kdbx(*db, vault_data);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Re-encrypts the whole vault under `new_password` with a fresh salt.
+    /// The old password is verified against the file on disk. Quick unlock
+    /// is revoked and has to be enabled again.
+    pub fn change_master_password(
+        &mut self,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<(), VaultError> {
+        self.ensure_unlocked()?;
+
+        if new_password.is_empty() {
+            return Err(VaultError::InvalidInput(
+                "Master password cannot be empty".to_string(),
+            ));
+        }
+
+        let kdf_params = self.kdf_params()?;
+
+        self.rekey(old_password, new_password, kdf_params)
+    }
+
+    pub fn update_kdf_params(
+        &mut self,
+        master_password: &str,
+        kdf_params: KdfParams,
+    ) -> Result<(), VaultError> {
+        self.ensure_unlocked()?;
+
+        kdf_params.validate()?;
+
+        self.rekey(master_password, master_password, kdf_params)
+    }
+
+    fn ensure_file_lock(&mut self) -> Result<(), VaultError> {
+        if self.file_lock.is_none() {
+            self.file_lock = Some(self.storage.lock()?);
+        }
+        Ok(())
+    }
+
+    /// Drops a file lock taken for a failed unlock attempt; an already
+    /// unlocked vault keeps its lock.
+    fn release_unless_unlocked(&mut self) {
+        if !self.is_unlocked() {
+            self.file_lock = None;
+        }
+    }
+
+    fn save(&mut self) -> Result<(), VaultError> {
+        if let Some(db) = &self.kdbx {
+            // KDBX files carry no generation, so compare against what was
+            // last read or written instead.
+            db.check_unchanged(&self.storage.load()?)?;
+            let file = db.seal(&self.snapshot())?;
+            self.storage
+                .save(&file, None, self.settings.backup_generations)?;
+            if let Some(db) = &mut self.kdbx {
+                db.mark_saved(&file);
+            }
+            return Ok(());
+        }
+
+        let key = self.key.as_ref().ok_or(VaultError::Locked)?;
+
+        let current = self.header.as_ref().ok_or(VaultError::Locked)?;
+        let mut header = current.clone();
+        header.generation += 1;
+
+        let encrypted = seal_vault(&header, key, &self.snapshot())?;
+
+        self.storage.save(
+            &encrypted,
+            Some(current.generation),
+            self.settings.backup_generations,
+        )?;
+
+        self.header = Some(header);
+
+        Ok(())
+    }
+
+    /// Verifies `old_password` against the file on disk, then re-encrypts
+    /// the in-memory vault under `new_password` and `kdf_params` with a fresh
+    /// salt. The previous file stays in the backup ring. The key changes, so
+    /// any quick-unlock credential is revoked.
+    fn rekey(
+        &mut self,
+        old_password: &str,
+        new_password: &str,
+        kdf_params: KdfParams,
+    ) -> Result<(), VaultError> {
+        if let Some(current) = &self.kdbx {
+            let on_disk = self.storage.load()?;
+            current.check_unchanged(&on_disk)?;
+            KdbxDatabase::open(&on_disk, old_password)?;
+
+            let mut db = current.clone();
+            db.rekey(new_password, kdf_params)?;
+            let file = db.seal(&self.snapshot())?;
+            self.storage
+                .save(&file, None, self.settings.backup_generations)?;
+            db.mark_saved(&file);
+            self.kdbx = Some(db);
+            return Ok(());
+        }
+
+        let current = self.header.clone().ok_or(VaultError::Locked)?;
+
+        let disk_header = self.verify_master_password(old_password)?;
+        if disk_header.generation != current.generation {
+            return Err(VaultError::Conflict);
+        }
+
+        let (header, key, encrypted) =
+            reencrypt(&current, new_password, kdf_params, &self.snapshot())?;
+
+        self.storage.save(
+            &encrypted,
+            Some(current.generation),
+            self.settings.backup_generations,
+        )?;
+
+        let _ = self.storage.delete_quick_unlock();
+
+        self.key = Some(key);
+        self.header = Some(header);
+
+        Ok(())
+    }
+
+    /// Checks `master_password` against the vault as stored, returning the
+    /// stored header.
+    fn verify_master_password(&self, master_password: &str) -> Result<VaultHeader, VaultError> {
+        let on_disk = self.storage.load()?;
+        let (header, _, _) = open_vault(&on_disk, master_password)?;
+        Ok(header)
+    }
+
+    fn kdf_params(&self) -> Result<KdfParams, VaultError> {
+        match (&self.header, &self.kdbx) {
+            (Some(header), _) => Ok(header.kdf_params),
+            (None, Some(db)) => Ok(db.kdf_params()),
+            (None, None) => Err(VaultError::Locked),
+        }
+    }
+
+    fn snapshot(&self) -> VaultData {
+        VaultData {
+            version: 1,
+            settings: self.settings.clone(),
+            entries: self.entries.clone(),
+            trash: self.trash.clone(),
+        }
+    }
 }
 
-#[tauri::command]
-pub async fn unlock_vault(
-    app: AppHandle,
-    master_password: String,
-    state: State<'_, VaultStateMutex>,
-) -> Result<bool, String> {
-    let encrypted = storage::load_vault(&app).map_err(|e| e.to_string())?;
+/// Puts `old` at the front of the entry's history, dropping the oldest
+/// passwords beyond `limit`.
+fn push_history(entry: &mut PasswordEntry, old: SecureString, limit: usize) {
+    if !old.as_str().is_empty() {
+        entry.history.insert(
+            0,
+            PasswordHistoryItem {
+                password: old,
+                changed_at: Utc::now(),
+            },
+        );
+    }
+    entry.history.truncate(limit);
+}
 
-    Err("Unlock not fully implemented - need to refactor salt storage".to_string())
+fn trim_names(mut fields: Vec<CustomField>) -> Vec<CustomField> {
+    for field in &mut fields {
+        field.name = field.name.trim().to_string();
+    }
+    fields
 }
 
-#[tauri::command]
-pub async fn lock_vault(state: State<'_, VaultStateMutex>) -> Result<(), String> {
-    let mut vault_state = state.lock().unwrap();
-    vault_state.lock();
-    Ok(())
+/// Drops an empty TOTP seed and rejects one `Totp::parse` cannot read.
+fn check_totp(totp: Option<SecureString>) -> Result<Option<SecureString>, VaultError> {
+    match totp {
+        Some(seed) if !seed.as_str().trim().is_empty() => {
+            Totp::parse(seed.as_str())?;
+            Ok(Some(seed))
+        }
+        _ => Ok(None),
+    }
 }
 
-#[tauri::command]
-pub async fn vault_exists(app: AppHandle) -> Result<bool, String> {
-    Ok(storage::vault_exists(&app))
+/// Wraps `key` under a fresh device key bound to this vault's salt. A new
+/// device key each time means re-enabling also invalidates older copies.
+fn remember_key(
+    storage: &dyn VaultStorage,
+    device_key: &keyring::Entry,
+    key: &[u8; 32],
+    header: &VaultHeader,
+) -> Result<(), VaultError> {
+    let wrapping_key = crypto::generate_key();
+    let wrapped = crypto::wrap_key(key, &wrapping_key, &header.salt)?;
+
+    storage::store_device_key(device_key, &wrapping_key)?;
+    storage.save_quick_unlock(&wrapped)
 }
 
-#[tauri::command]
-pub async fn is_vault_unlocked(state: State<'_, VaultStateMutex>) -> Result<bool, String> {
-    let vault_state = state.lock().unwrap();
-    Ok(vault_state.is_unlocked)
+fn forget_key(storage: &dyn VaultStorage, device_key: &keyring::Entry) -> Result<(), VaultError> {
+    storage.delete_quick_unlock()?;
+    storage::delete_credential(device_key)
 }
 
-#[tauri::command]
-pub async fn get_all_passwords(
-    state: State<'_, VaultStateMutex>,
-) -> Result<Vec<PasswordEntrySummary>, String> {
-    let vault_state = state.lock().unwrap();
+/// Seals `vault_data` under a key derived from `password` with a fresh salt,
+/// as the successor of `current`. Returns the new header, key and file bytes.
+fn reencrypt(
+    current: &VaultHeader,
+    password: &str,
+    kdf_params: KdfParams,
+    vault_data: &VaultData,
+) -> Result<(VaultHeader, VaultKey, Vec<u8>), VaultError> {
+    let mut header = VaultHeader::new(generate_salt(), kdf_params);
+    header.generation = current.generation + 1;
+
+    let key = derive_key_with_params(password, &header.salt, &header.kdf_params)?;
+    let encrypted = seal_vault(&header, &key, vault_data)?;
+
+    Ok((header, key, encrypted))
+}
 
-    if !vault_state.is_unlocked {
-        return Err("Vault is locked".to_string());
+/// Serializes and encrypts the vault data, returning the complete file bytes.
+fn seal_vault(
+    header: &VaultHeader,
+    key: &[u8; 32],
+    vault_data: &VaultData,
+) -> Result<Vec<u8>, VaultError> {
+    // Size the buffer up front: a growing Vec would leave unzeroized copies
+    // of the plaintext behind in every buffer it reallocates away from.
+    let mut counter = ByteCounter(0);
+    serde_json::to_writer(&mut counter, vault_data)?;
+    let mut json = Zeroizing::new(Vec::with_capacity(counter.0));
+    serde_json::to_writer(&mut *json, vault_data)?;
+
+    let payload = encrypt_with_aad(&json, &header.to_bytes(), key)?;
+
+    Ok(storage::encode_vault_file(header, &payload))
+}
+
+struct ByteCounter(usize);
+
+impl std::io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
     }
 
-    let summaries: Vec<PasswordEntrySummary> = vault_state
-        .entries
-        .iter()
-        .map(|e| e.clone().into())
-        .collect();
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
 
-    Ok(summaries)
+/// Parses the header, re-derives the key and decrypts the payload.
+fn open_vault(
+    data: &[u8],
+    master_password: &str,
+) -> Result<(VaultHeader, VaultKey, VaultData), VaultError> {
+    open_vault_with(data, |header| {
+        if !header.kdf_params.within_limits() {
+            return Err(VaultError::Unsupported(
+                "KDF parameters out of range".to_string(),
+            ));
+        }
+        derive_key_with_params(master_password, &header.salt, &header.kdf_params)
+    })
 }
 
-#[tauri::command]
-pub async fn get_password(
-    id: String,
-    state: State<'_, VaultStateMutex>,
-) -> Result<PasswordEntry, String> {
-    let vault_state = state.lock().unwrap();
+/// A vault file decrypted in either of the formats `Vault` reads.
+enum Opened {
+    Zims(VaultHeader, VaultKey, VaultData),
+    Kdbx(Box<KdbxDatabase>, VaultData),
+}
 
-    if !vault_state.is_unlocked {
-        return Err("Vault is locked".to_string());
+fn open_any(data: &[u8], master_password: &str) -> Result<Opened, VaultError> {
+    if kdbx::is_kdbx(data) {
+        let (db, vault_data) = KdbxDatabase::open(data, master_password)?;
+        return Ok(Opened::Kdbx(Box::new(db), vault_data));
     }
+    let (header, key, vault_data) = open_vault(data, master_password)?;
+    Ok(Opened::Zims(header, key, vault_data))
+}
 
-    let uuid = Uuid::parse_str(&id).map_err(|e| e.to_string())?;
-
-    vault_state
-        .entries
-        .iter()
-        .find(|e| e.id == uuid)
-        .cloned()
-        .ok_or_else(|| "Password entry not found".to_string())
+/// Opens the vault with the key unwrapped from its quick-unlock credential.
+fn open_vault_with_device_key(
+    data: &[u8],
+    storage: &dyn VaultStorage,
+    device_key: &keyring::Entry,
+) -> Result<(VaultHeader, VaultKey, VaultData), VaultError> {
+    open_vault_with(data, |header| {
+        let wrapped = storage.load_quick_unlock()?;
+        let wrapping_key = storage::get_device_key(device_key)?;
+        crypto::unwrap_key(&wrapped, &wrapping_key, &header.salt)
+    })
 }
 
-#[tauri::command]
-pub async fn create_password(
-    app: AppHandle,
-    title: String,
-    username: String,
-    password: String,
-    url: Option<String>,
-    notes: Option<String>,
-    tags: Vec<String>,
-    state: State<'_, VaultStateMutex>,
-) -> Result<String, String> {
-    let mut vault_state = state.lock().unwrap();
-
-    if !vault_state.is_unlocked {
-        return Err("Vault is locked".to_string());
-    }
-
-    let entry = PasswordEntry {
-        id: Uuid::new_v4(),
-        title,
-        username,
-        password,
-        url,
-        notes,
-        tags,
-        favorite: false,
-        created_at: Utc::now(),
-        updated_at: Utc::now(),
-    };
+fn open_vault_with(
+    data: &[u8],
+    key_for: impl FnOnce(&VaultHeader) -> Result<VaultKey, VaultError>,
+) -> Result<(VaultHeader, VaultKey, VaultData), VaultError> {
+    let (header, header_bytes, payload) = storage::decode_vault_file(data)?;
 
-    let id = entry.id.to_string();
-    vault_state.entries.push(entry);
+    let key = key_for(&header)?;
 
-    save_vault_internal(&app, &vault_state).map_err(|e| e.to_string())?;
+    let json = decrypt_with_aad(payload, header_bytes, &key)?;
 
-    Ok(id)
-}
+    let vault_data: VaultData = serde_json::from_slice(&json)?;
 
-#[tauri::command]
-pub async fn update_password(
-    app: AppHandle,
-    id: String,
-    title: String,
-    username: String,
-    password: String,
-    url: Option<String>,
-    notes: Option<String>,
-    tags: Vec<String>,
-    favorite: bool,
-    state: State<'_, VaultStateMutex>,
-) -> Result<(), String> {
-    let mut vault_state = state.lock().unwrap();
-
-    if !vault_state.is_unlocked {
-        return Err("Vault is locked".to_string());
-    }
-
-    let uuid = Uuid::parse_str(&id).map_err(|e| e.to_string())?;
-
-    let entry = vault_state
-        .entries
-        .iter_mut()
-        .find(|e| e.id == uuid)
-        .ok_or_else(|| "Password entry not found".to_string())?;
-
-    entry.title = title;
-    entry.username = username;
-    entry.password = password;
-    entry.url = url;
-    entry.notes = notes;
-    entry.tags = tags;
-    entry.favorite = favorite;
-    entry.updated_at = Utc::now();
-
-    save_vault_internal(&app, &vault_state).map_err(|e| e.to_string())?;
-
-    Ok(())
+    Ok((header, key, vault_data))
 }
 
-#[tauri::command]
-pub async fn delete_password(
-    app: AppHandle,
-    id: String,
-    state: State<'_, VaultStateMutex>,
-) -> Result<(), String> {
-    let mut vault_state = state.lock().unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::password::backend::MemoryStorage;
+    use crate::password::types::{
+        EntryKind, FieldKind, PasswordEntryDetails, PasswordEntrySummary, UrlMatch,
+    };
+
+    const FAST_KDF: KdfParams = KdfParams {
+        memory_kib: 1024,
+        iterations: 1,
+        parallelism: 1,
+    };
 
-    if !vault_state.is_unlocked {
-        return Err("Vault is locked".to_string());
+    fn fast_header() -> VaultHeader {
+        VaultHeader::new(generate_salt(), FAST_KDF)
     }
 
-    let uuid = Uuid::parse_str(&id).map_err(|e| e.to_string())?;
+    /// A vault created with policy-compliant but cheap parameters, so that
+    /// unlocking does not trigger the automatic KDF upgrade.
+    fn created_vault(dir: &tempfile::TempDir) -> Vault {
+        let mut vault = Vault::new(dir.path().join("vault.encrypted"));
+        vault.create("hunter2", KdfParams::POLICY_FLOOR).unwrap();
+        vault
+    }
 
-    let index = vault_state
-        .entries
-        .iter()
-        .position(|e| e.id == uuid)
-        .ok_or_else(|| "Password entry not found".to_string())?;
+    fn input(title: &str, password: &str) -> EntryInput {
+        EntryInput {
+            title: title.to_string(),
+            username: "alice".to_string(),
+            password: password.to_string().into(),
+            ..EntryInput::default()
+        }
+    }
 
-    vault_state.entries.remove(index);
+    #[test]
+    fn test_seal_and_open_roundtrip() {
+        let header = fast_header();
+        let key = derive_key_with_params("hunter2", &header.salt, &header.kdf_params).unwrap();
 
-    save_vault_internal(&app, &vault_state).map_err(|e| e.to_string())?;
+        let sealed = seal_vault(&header, &key, &empty_data()).unwrap();
+        let (opened_header, opened_key, data) = open_vault(&sealed, "hunter2").unwrap();
 
-    Ok(())
-}
+        assert_eq!(opened_header, header);
+        assert_eq!(opened_key, key);
+        assert!(data.entries.is_empty());
+    }
 
-#[tauri::command]
-pub async fn generate_password(options: PasswordOptions) -> Result<String, String> {
-    let mut charset = String::new();
+    #[test]
+    fn test_open_with_wrong_password() {
+        let header = fast_header();
+        let key = derive_key_with_params("hunter2", &header.salt, &header.kdf_params).unwrap();
+        let sealed = seal_vault(&header, &key, &empty_data()).unwrap();
 
-    if options.include_lowercase {
-        charset.push_str(if options.exclude_ambiguous {
-            "abcdefghjkmnpqrstuvwxyz"
-        } else {
-            "abcdefghijklmnopqrstuvwxyz"
-        });
+        let err = open_vault(&sealed, "hunter3").unwrap_err();
+        assert!(matches!(err, VaultError::WrongPassword));
     }
 
-    if options.include_uppercase {
-        charset.push_str(if options.exclude_ambiguous {
-            "ABCDEFGHJKLMNPQRSTUVWXYZ"
-        } else {
-            "ABCDEFGHIJKLMNOPQRSTUVWXYZ"
-        });
+    fn empty_data() -> VaultData {
+        VaultData {
+            version: 1,
+            settings: VaultSettings::default(),
+            entries: Vec::new(),
+            trash: Vec::new(),
+        }
     }
 
-    if options.include_numbers {
-        charset.push_str(if options.exclude_ambiguous {
-            "23456789"
-        } else {
-            "0123456789"
-        });
+    fn sample_entry(password: &str) -> PasswordEntry {
+        PasswordEntry {
+            id: Uuid::new_v4(),
+            kind: EntryKind::Login,
+            title: "Example".to_string(),
+            username: "alice".to_string(),
+            password: password.to_string().into(),
+            url: Some("https://example.com".to_string()),
+            urls: Vec::new(),
+            url_match: Default::default(),
+            notes: Some("recovery codes in safe".to_string()),
+            totp: None,
+            fields: Vec::new(),
+            history: Vec::new(),
+            tags: vec!["work".to_string()],
+            favorite: true,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            deleted_at: None,
+        }
     }
 
-    if options.include_symbols {
-        charset.push_str("!@#$%^&*()-_=+[]{}|;:,.<>?");
+    #[test]
+    fn test_secrets_survive_roundtrip() {
+        let header = fast_header();
+        let key = derive_key_with_params("hunter2", &header.salt, &header.kdf_params).unwrap();
+        let entries = vec![sample_entry("s3cret!"), sample_entry("other-secret")];
+        let vault_data = VaultData {
+            version: 1,
+            settings: VaultSettings::default(),
+            entries: entries.clone(),
+            trash: Vec::new(),
+        };
+
+        let sealed = seal_vault(&header, &key, &vault_data).unwrap();
+        let (_, _, data) = open_vault(&sealed, "hunter2").unwrap();
+
+        assert_eq!(data.entries.len(), 2);
+        assert_eq!(data.entries[0].id, entries[0].id);
+        assert_eq!(data.entries[0].password.as_str(), "s3cret!");
+        assert_eq!(data.entries[1].password.as_str(), "other-secret");
+        assert_eq!(data.entries[0].notes, entries[0].notes);
     }
 
-    if charset.is_empty() {
-        return Err("No character types selected".to_string());
+    #[test]
+    fn test_reencrypt_with_new_password() {
+        let header = fast_header();
+        let mut vault_data = empty_data();
+        vault_data.entries.push(sample_entry("s3cret!"));
+
+        let (new_header, new_key, sealed) =
+            reencrypt(&header, "new-master", header.kdf_params, &vault_data).unwrap();
+
+        assert_ne!(new_header.salt, header.salt);
+        assert_eq!(new_header.generation, header.generation + 1);
+        assert!(matches!(
+            open_vault(&sealed, "hunter2"),
+            Err(VaultError::WrongPassword)
+        ));
+
+        let (_, key, data) = open_vault(&sealed, "new-master").unwrap();
+        assert_eq!(key, new_key);
+        assert_eq!(data.entries[0].password.as_str(), "s3cret!");
     }
 
-    let charset: Vec<char> = charset.chars().collect();
-    let mut rng = rand::thread_rng();
+    #[test]
+    fn test_idle_expiry() {
+        let mut state = Vault::new("vault.encrypted");
+        let now = Instant::now();
+        assert!(!state.idle_expired(now + Duration::from_secs(3600)));
 
-    let password: String = (0..options.length)
-        .map(|_| charset[rng.gen_range(0..charset.len())])
-        .collect();
+        let header = fast_header();
+        state.set_unlocked(header, Zeroizing::new([0u8; 32]), empty_data());
+        state.settings.auto_lock_secs = 60;
 
-    Ok(password)
-}
+        let start = state.last_activity;
+        assert!(!state.idle_expired(start + Duration::from_secs(59)));
+        assert!(state.idle_expired(start + Duration::from_secs(60)));
 
-#[tauri::command]
-pub async fn check_password_strength(password: String) -> Result<StrengthScore, String> {
-    let length = password.len();
-    let has_lowercase = password.chars().any(|c| c.is_lowercase());
-    let has_uppercase = password.chars().any(|c| c.is_uppercase());
-    let has_numbers = password.chars().any(|c| c.is_numeric());
-    let has_symbols = password.chars().any(|c| !c.is_alphanumeric());
+        state.ensure_unlocked().unwrap();
+        assert!(state.last_activity >= start);
 
-    let mut charset_size = 0;
-    if has_lowercase {
-        charset_size += 26;
+        state.settings.auto_lock_secs = 0;
+        assert!(!state.idle_expired(start + Duration::from_secs(3600)));
+
+        state.lock();
+        assert!(matches!(state.ensure_unlocked(), Err(VaultError::Locked)));
     }
-    if has_uppercase {
-        charset_size += 26;
+
+    #[test]
+    fn test_details_view_omits_secret() {
+        let details: PasswordEntryDetails = sample_entry("s3cret!").into();
+        let json = serde_json::to_string(&details).unwrap();
+
+        assert!(!json.contains("s3cret!"));
+        assert!(!json.contains("\"password\""));
     }
-    if has_numbers {
-        charset_size += 10;
+
+    #[test]
+    fn test_open_detects_tampered_header() {
+        let header = fast_header();
+        let key = derive_key_with_params("hunter2", &header.salt, &header.kdf_params).unwrap();
+        let mut sealed = seal_vault(&header, &key, &empty_data()).unwrap();
+
+        // Flip a salt byte: the key changes and the AAD no longer matches.
+        sealed[20] ^= 0xff;
+        assert!(open_vault(&sealed, "hunter2").is_err());
     }
-    if has_symbols {
-        charset_size += 32;
+
+    #[test]
+    fn test_quick_unlock_lifecycle() {
+        keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+        let device_key = keyring::Entry::new("com.zmscode.zims.test", "device-key").unwrap();
+        let storage = MemoryStorage::new();
+
+        let header = fast_header();
+        let key = derive_key_with_params("hunter2", &header.salt, &header.kdf_params).unwrap();
+        let mut vault_data = empty_data();
+        vault_data.entries.push(sample_entry("s3cret!"));
+        let sealed = seal_vault(&header, &key, &vault_data).unwrap();
+
+        assert!(matches!(
+            open_vault_with_device_key(&sealed, &storage, &device_key),
+            Err(VaultError::NotFound(_))
+        ));
+
+        remember_key(&storage, &device_key, &key, &header).unwrap();
+        let (_, opened_key, data) =
+            open_vault_with_device_key(&sealed, &storage, &device_key).unwrap();
+        assert_eq!(opened_key, key);
+        assert_eq!(data.entries[0].password.as_str(), "s3cret!");
+
+        // The stored wrapped key is not the vault key.
+        let wrapped = storage.load_quick_unlock().unwrap();
+        assert!(!wrapped.windows(32).any(|w| w == key.as_slice()));
+
+        forget_key(&storage, &device_key).unwrap();
+        assert!(!storage.has_quick_unlock());
+        assert!(matches!(
+            open_vault_with_device_key(&sealed, &storage, &device_key),
+            Err(VaultError::NotFound(_))
+        ));
     }
 
-    let entropy = (length as f64) * (charset_size as f64).log2();
+    #[test]
+    fn test_quick_unlock_stale_after_rekey() {
+        keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+        let device_key = keyring::Entry::new("com.zmscode.zims.test", "device-key").unwrap();
+        let storage = MemoryStorage::new();
+
+        let header = fast_header();
+        let key = derive_key_with_params("hunter2", &header.salt, &header.kdf_params).unwrap();
+        remember_key(&storage, &device_key, &key, &header).unwrap();
+
+        let (_, _, sealed) =
+            reencrypt(&header, "new-master", header.kdf_params, &empty_data()).unwrap();
+        assert!(matches!(
+            open_vault_with_device_key(&sealed, &storage, &device_key),
+            Err(VaultError::WrongPassword)
+        ));
+    }
 
-    let score = if entropy < 28.0 {
-        0 
-    } else if entropy < 36.0 {
-        1
-    } else if entropy < 60.0 {
-        2 
-    } else if entropy < 128.0 {
-        3 
-    } else {
-        4 
-    };
+    #[test]
+    fn test_vault_lifecycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut vault = created_vault(&dir);
+        assert!(vault.exists());
+        assert!(vault.is_unlocked());
+
+        let id = vault.add_entry(input("Example", "s3cret!")).unwrap();
+        let other = vault.add_entry(input("Other", "other-secret")).unwrap();
+        vault
+            .update_entry(id, input("Renamed", "n3w-s3cret"))
+            .unwrap();
+        vault.delete_entry(other).unwrap();
+
+        vault.lock();
+        assert!(matches!(vault.entries(), Err(VaultError::Locked)));
+        assert!(matches!(
+            vault.unlock("hunter3"),
+            Err(VaultError::WrongPassword)
+        ));
+
+        let mut reopened = Vault::new(dir.path().join("vault.encrypted"));
+        reopened.unlock("hunter2").unwrap();
+        assert_eq!(reopened.entries().unwrap().len(), 1);
+        let entry = reopened.entry(id).unwrap();
+        assert_eq!(entry.title, "Renamed");
+        assert_eq!(entry.password.as_str(), "n3w-s3cret");
+        assert!(matches!(
+            reopened.entry(other),
+            Err(VaultError::NotFound(_))
+        ));
+    }
 
-    let mut feedback = Vec::new();
-    if length < 8 {
-        feedback.push("Use at least 8 characters".to_string());
+    #[test]
+    fn test_totp_code() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut vault = created_vault(&dir);
+
+        let plain = vault.add_entry(input("Plain", "pw")).unwrap();
+        assert!(matches!(
+            vault.totp_code(plain),
+            Err(VaultError::NotFound(_))
+        ));
+
+        let mut with_totp = input("GitHub", "pw");
+        with_totp.totp = Some(
+            "otpauth://totp/GitHub?secret=JBSWY3DPEHPK3PXP&digits=8"
+                .to_string()
+                .into(),
+        );
+        let id = vault.add_entry(with_totp).unwrap();
+        let code = vault.totp_code(id).unwrap();
+        assert_eq!(code.code.len(), 8);
+        assert!((1..=30).contains(&code.seconds_remaining));
+
+        let mut invalid = input("GitHub", "pw");
+        invalid.totp = Some("not base32!".to_string().into());
+        assert!(matches!(
+            vault.update_entry(id, invalid),
+            Err(VaultError::InvalidInput(_))
+        ));
+        assert!(vault.entry(id).unwrap().totp.is_some());
+
+        // Editing without a seed keeps it; an empty one removes it.
+        vault
+            .update_entry(id, input("GitHub (work)", "pw"))
+            .unwrap();
+        assert_eq!(vault.entry(id).unwrap().title, "GitHub (work)");
+        assert_eq!(vault.totp_code(id).unwrap().code.len(), 8);
+
+        let mut cleared = input("GitHub (work)", "pw");
+        cleared.totp = Some(String::new().into());
+        vault.update_entry(id, cleared).unwrap();
+        assert!(matches!(vault.totp_code(id), Err(VaultError::NotFound(_))));
     }
-    if !has_uppercase {
-        feedback.push("Add uppercase letters".to_string());
+
+    #[test]
+    fn test_entry_kinds() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut vault = created_vault(&dir);
+
+        let field = |name: &str, kind, value: &str| CustomField {
+            name: name.to_string(),
+            kind,
+            value: value.to_string().into(),
+        };
+        let card = |number: &str| EntryInput {
+            kind: EntryKind::CreditCard,
+            title: "Visa".to_string(),
+            fields: vec![
+                field(fields::CARD_NUMBER, FieldKind::Hidden, number),
+                field(" Bank ", FieldKind::Url, "https://bank.example.com"),
+            ],
+            ..EntryInput::default()
+        };
+
+        assert!(matches!(
+            vault.add_entry(card("4111 1111 1111 1112")),
+            Err(VaultError::InvalidInput(_))
+        ));
+        let id = vault.add_entry(card("4111 1111 1111 1111")).unwrap();
+
+        let summary: PasswordEntrySummary = vault.entry(id).unwrap().clone().into();
+        assert_eq!(summary.kind, EntryKind::CreditCard);
+        let details: PasswordEntryDetails = vault.entry(id).unwrap().clone().into();
+        assert_eq!(details.fields[0].value, None);
+        assert_eq!(details.fields[1].name, "Bank");
+        assert_eq!(
+            details.fields[1].value.as_deref(),
+            Some("https://bank.example.com")
+        );
+
+        let mut note = card("4111 1111 1111 1111");
+        note.kind = EntryKind::SecureNote;
+        vault.update_entry(id, note).unwrap();
+        let entry = vault.entry(id).unwrap();
+        assert_eq!(entry.kind, EntryKind::SecureNote);
+        assert_eq!(
+            entry.field(fields::CARD_NUMBER).unwrap().value.as_str(),
+            "4111 1111 1111 1111"
+        );
     }
-    if !has_lowercase {
-        feedback.push("Add lowercase letters".to_string());
+
+    #[test]
+    fn test_password_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut vault = created_vault(&dir);
+        vault
+            .update_settings(VaultSettings {
+                password_history: 3,
+                ..VaultSettings::default()
+            })
+            .unwrap();
+
+        let id = vault.add_entry(input("GitHub", "pw1")).unwrap();
+        for password in ["pw2", "pw3", "pw3", "pw4", "pw5"] {
+            vault.update_entry(id, input("GitHub", password)).unwrap();
+        }
+        let passwords = |vault: &mut Vault| -> Vec<String> {
+            vault
+                .password_history(id)
+                .unwrap()
+                .iter()
+                .map(|item| item.password.as_str().to_string())
+                .collect()
+        };
+        // Unchanged passwords are not recorded, and the oldest fall off.
+        assert_eq!(passwords(&mut vault), vec!["pw4", "pw3", "pw2"]);
+
+        vault.restore_password(id, 2).unwrap();
+        assert_eq!(vault.entry(id).unwrap().password.as_str(), "pw2");
+        assert_eq!(passwords(&mut vault), vec!["pw5", "pw4", "pw3"]);
+        assert!(matches!(
+            vault.restore_password(id, 3),
+            Err(VaultError::NotFound(_))
+        ));
+
+        vault.lock();
+        vault.unlock("hunter2").unwrap();
+        assert_eq!(passwords(&mut vault), vec!["pw5", "pw4", "pw3"]);
+
+        vault
+            .update_settings(VaultSettings {
+                password_history: 1,
+                ..VaultSettings::default()
+            })
+            .unwrap();
+        assert_eq!(passwords(&mut vault), vec!["pw5"]);
+        assert!(matches!(
+            vault.update_settings(VaultSettings {
+                password_history: MAX_PASSWORD_HISTORY + 1,
+                ..VaultSettings::default()
+            }),
+            Err(VaultError::InvalidInput(_))
+        ));
     }
-    if !has_numbers {
-        feedback.push("Add numbers".to_string());
+
+    #[test]
+    fn test_trash() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut vault = created_vault(&dir);
+        let a = vault.add_entry(input("A", "pw")).unwrap();
+        let b = vault.add_entry(input("B", "pw")).unwrap();
+        let c = vault.add_entry(input("C", "pw")).unwrap();
+
+        for id in [a, b, c] {
+            vault.delete_entry(id).unwrap();
+        }
+        assert!(vault.entries().unwrap().is_empty());
+        assert!(matches!(vault.entry(a), Err(VaultError::NotFound(_))));
+        let found = vault.search_entries("a", &SearchFilters::default());
+        assert!(found.unwrap().is_empty());
+        assert_eq!(vault.trash().unwrap().len(), 3);
+        assert!(vault.trash().unwrap()[0].deleted_at.is_some());
+
+        vault.restore_entry(a).unwrap();
+        assert_eq!(vault.entry(a).unwrap().deleted_at, None);
+        vault.purge_entry(b).unwrap();
+        assert!(matches!(
+            vault.restore_entry(b),
+            Err(VaultError::NotFound(_))
+        ));
+
+        // Entries trashed longer ago than the retention period are purged
+        // when the vault is unlocked.
+        vault.trash[0].deleted_at = Some(Utc::now() - TimeDelta::days(31));
+        vault.save().unwrap();
+        vault.lock();
+        vault.unlock("hunter2").unwrap();
+        assert!(vault.trash().unwrap().is_empty());
+        assert_eq!(vault.entries().unwrap().len(), 1);
+
+        vault.delete_entry(a).unwrap();
+        vault.lock();
+        vault.unlock("hunter2").unwrap();
+        assert_eq!(vault.trash().unwrap()[0].id, a);
+        assert_eq!(vault.empty_trash().unwrap(), 1);
+        assert!(vault.trash().unwrap().is_empty());
     }
-    if !has_symbols {
-        feedback.push("Add symbols".to_string());
+
+    #[test]
+    fn test_find_entries_for_url() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut vault = created_vault(&dir);
+        let with_urls = |title: &str, url_match, urls: &[&str]| EntryInput {
+            url: Some(urls[0].to_string()),
+            urls: urls[1..].iter().map(|u| u.to_string()).collect(),
+            url_match,
+            ..input(title, "pw")
+        };
+        let mail = vault
+            .add_entry(with_urls(
+                "Mail",
+                UrlMatch::BaseDomain,
+                &["https://mail.example.com", "", "https://mail.example.com"],
+            ))
+            .unwrap();
+        assert!(vault.entry(mail).unwrap().urls.is_empty());
+        let shop = vault
+            .add_entry(with_urls(
+                "Shop",
+                UrlMatch::Host,
+                &["https://shop.example.org", "https://shop.example.com"],
+            ))
+            .unwrap();
+
+        let found = |vault: &mut Vault, url| -> Vec<Uuid> {
+            let found = vault.find_entries_for_url(url).unwrap();
+            found.into_iter().map(|s| s.id).collect()
+        };
+        assert_eq!(found(&mut vault, "shop.example.com/cart"), vec![shop, mail]);
+        assert_eq!(found(&mut vault, "https://example.com"), vec![mail]);
+        assert!(matches!(
+            vault.find_entries_for_url(" "),
+            Err(VaultError::InvalidInput(_))
+        ));
+
+        // Trashed entries are not offered.
+        vault.delete_entry(mail).unwrap();
+        assert!(found(&mut vault, "https://example.com").is_empty());
+
+        assert!(matches!(
+            vault.add_entry(with_urls("Bad", UrlMatch::Regex, &["https://(example"])),
+            Err(VaultError::InvalidInput(_))
+        ));
     }
-    if length < 12 && feedback.is_empty() {
-        feedback.push("Consider using 12+ characters for better security".to_string());
+
+    #[test]
+    fn test_password_rules_for() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut vault = created_vault(&dir);
+        let mut settings = vault.settings().unwrap().clone();
+        settings.password_rules.insert(
+            " Example.com ".to_string(),
+            "minlength: 8; required: digit".to_string(),
+        );
+        settings
+            .password_rules
+            .insert("login.example.com".to_string(), "maxlength: 16".to_string());
+        vault.update_settings(settings.clone()).unwrap();
+
+        let rules = |vault: &mut Vault, url| vault.password_rules_for(url).unwrap();
+        assert_eq!(
+            rules(&mut vault, "https://login.example.com/signin"),
+            Some("maxlength: 16".to_string())
+        );
+        assert_eq!(
+            rules(&mut vault, "www.example.com"),
+            Some("minlength: 8; required: digit".to_string())
+        );
+        assert_eq!(rules(&mut vault, "https://example.org"), None);
+
+        // Bundled presets apply unless the settings have rules of their own.
+        let paypal = rules::site_preset("paypal.com").map(str::to_string);
+        assert!(paypal.is_some());
+        assert_eq!(rules(&mut vault, "https://www.paypal.com/signin"), paypal);
+        settings
+            .password_rules
+            .insert("paypal.com".to_string(), "minlength: 30".to_string());
+        vault.update_settings(settings.clone()).unwrap();
+        assert_eq!(
+            rules(&mut vault, "https://www.paypal.com/signin"),
+            Some("minlength: 30".to_string())
+        );
+        assert!(vault.password_rules_for("").is_err());
+
+        settings
+            .password_rules
+            .insert("bad.example".to_string(), "required: emoji".to_string());
+        assert!(matches!(
+            vault.update_settings(settings),
+            Err(VaultError::InvalidInput(_))
+        ));
     }
 
-    Ok(StrengthScore {
-        score,
-        entropy,
-        feedback,
-    })
-}
+    #[test]
+    fn test_create_refuses_existing_vault() {
+        let dir = tempfile::tempdir().unwrap();
+        let _vault = created_vault(&dir);
+
+        let mut again = Vault::new(dir.path().join("vault.encrypted"));
+        assert!(matches!(
+            again.create("other", FAST_KDF),
+            Err(VaultError::AlreadyExists)
+        ));
+        assert!(!again.is_unlocked());
+    }
 
-fn save_vault_internal(
-    app: &AppHandle,
-    vault_state: &VaultState,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let key = vault_state
-        .encryption_key
-        .as_ref()
-        .ok_or("No encryption key available")?;
-
-    let salt = vault_state
-        .salt
-        .as_ref()
-        .ok_or("No salt available")?;
-
-    let vault_data = VaultData {
-        version: 1,
-        salt: *salt,
-        entries: vault_state.entries.clone(),
-    };
+    #[test]
+    fn test_import_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut vault = created_vault(&dir);
+        let existing = vault.add_entry(input("Example", "s3cret!")).unwrap();
+
+        let csv = "name,url,username,password\n\
+                   Example,,alice,other\n\
+                   Bank,https://bank.example,alice,pw\n";
+        let parse = || import::parse(import::ImportFormat::ChromeCsv, csv.as_bytes()).unwrap();
+
+        let preview = vault
+            .import_entries(
+                parse(),
+                &ImportOptions {
+                    dry_run: true,
+                    skip_duplicates: false,
+                },
+            )
+            .unwrap();
+        assert_eq!(preview.imported, 2);
+        assert_eq!(preview.entries[0].duplicate_of, Some(existing));
+        assert_eq!(preview.entries[1].duplicate_of, None);
+        assert_eq!(vault.entries().unwrap().len(), 1);
+
+        let report = vault
+            .import_entries(
+                parse(),
+                &ImportOptions {
+                    dry_run: false,
+                    skip_duplicates: true,
+                },
+            )
+            .unwrap();
+        assert_eq!((report.imported, report.skipped), (1, 1));
+        vault.lock();
+
+        vault.unlock("hunter2").unwrap();
+        let titles: Vec<&str> = vault
+            .entries()
+            .unwrap()
+            .iter()
+            .map(|e| e.title.as_str())
+            .collect();
+        assert_eq!(titles, vec!["Example", "Bank"]);
+    }
+
+    #[test]
+    fn test_export() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut vault = created_vault(&dir);
+        vault
+            .add_entry(EntryInput {
+                tags: vec!["work".to_string()],
+                ..input("VPN", "s3cret!")
+            })
+            .unwrap();
+        vault.add_entry(input("Bank", "pin")).unwrap();
+        let work = ["work".to_string()];
+
+        assert!(matches!(
+            vault.export_plaintext("wrong", ExportFormat::BitwardenJson, &[]),
+            Err(VaultError::WrongPassword)
+        ));
+        let json = vault
+            .export_plaintext("hunter2", ExportFormat::BitwardenJson, &work)
+            .unwrap();
+        let exported = import::parse(import::ImportFormat::BitwardenJson, &json).unwrap();
+        assert_eq!(exported.len(), 1);
+        assert_eq!(exported[0].password.as_str(), "s3cret!");
+
+        let encrypted = vault.export_encrypted("export-pass", &work).unwrap();
+        let path = dir.path().join("export.zims");
+        std::fs::write(&path, &encrypted).unwrap();
+
+        let mut opened = Vault::new(&path);
+        assert!(matches!(
+            opened.unlock("hunter2"),
+            Err(VaultError::WrongPassword)
+        ));
+        opened.unlock("export-pass").unwrap();
+        let titles: Vec<&str> = opened
+            .entries()
+            .unwrap()
+            .iter()
+            .map(|e| e.title.as_str())
+            .collect();
+        assert_eq!(titles, vec!["VPN"]);
+    }
+
+    #[test]
+    fn test_change_master_password() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut vault = created_vault(&dir);
+        vault.add_entry(input("Example", "s3cret!")).unwrap();
+
+        assert!(matches!(
+            vault.change_master_password("wrong", "new-master"),
+            Err(VaultError::WrongPassword)
+        ));
+        vault
+            .change_master_password("hunter2", "new-master")
+            .unwrap();
+        vault.lock();
+
+        assert!(matches!(
+            vault.unlock("hunter2"),
+            Err(VaultError::WrongPassword)
+        ));
+        vault.unlock("new-master").unwrap();
+        assert_eq!(vault.entries().unwrap()[0].password.as_str(), "s3cret!");
+    }
+
+    #[test]
+    fn test_restore_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut vault = created_vault(&dir);
+        let id = vault.add_entry(input("Example", "s3cret!")).unwrap();
+        vault.delete_entry(id).unwrap();
 
-    let json = serde_json::to_vec(&vault_data)?;
-    let encrypted = encrypt(&json, key)?;
+        vault.restore_backup(1, "hunter2").unwrap();
+        assert_eq!(vault.entry(id).unwrap().password.as_str(), "s3cret!");
+    }
 
-    storage::save_vault(app, &encrypted)?;
+    #[test]
+    fn test_quick_unlock_through_vault() {
+        keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+        let device_key = keyring::Entry::new("com.zmscode.zims.test", "device-key").unwrap();
+        let storage = MemoryStorage::new();
+
+        let mut vault = Vault::with_storage(Box::new(storage.clone()));
+        vault.create("hunter2", KdfParams::POLICY_FLOOR).unwrap();
+        let id = vault.add_entry(input("Example", "s3cret!")).unwrap();
+        vault.enable_quick_unlock(&device_key).unwrap();
+        assert!(vault.quick_unlock_enabled());
+
+        // A second vault over the same storage is another "process".
+        let mut other = Vault::with_storage(Box::new(storage.clone()));
+        assert!(matches!(
+            other.unlock_with_device_key(&device_key),
+            Err(VaultError::InUse(_))
+        ));
+
+        vault.lock();
+        vault.unlock_with_device_key(&device_key).unwrap();
+        assert_eq!(vault.entry(id).unwrap().password.as_str(), "s3cret!");
+
+        vault
+            .change_master_password("hunter2", "new-master")
+            .unwrap();
+        assert!(!vault.quick_unlock_enabled());
+        vault.lock();
+        assert!(matches!(
+            vault.unlock_with_device_key(&device_key),
+            Err(VaultError::NotFound(_))
+        ));
+    }
 
-    Ok(())
+    #[test]
+    fn test_quick_unlock_without_device_key() {
+        keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+        let device_key = keyring::Entry::new("com.zmscode.zims.test", "device-key/a").unwrap();
+        let mut vault = Vault::with_storage(Box::new(MemoryStorage::new()));
+        vault.create("hunter2", KdfParams::POLICY_FLOOR).unwrap();
+        vault.enable_quick_unlock(&device_key).unwrap();
+        vault.lock();
+
+        // Another account holds no credential, like the per-vault entry
+        // after upgrading from the shared one: the wrapped key is dropped.
+        let missing = keyring::Entry::new("com.zmscode.zims.test", "device-key/b").unwrap();
+        assert!(matches!(
+            vault.unlock_with_device_key(&missing),
+            Err(VaultError::NotFound(_))
+        ));
+        assert!(!vault.quick_unlock_enabled());
+    }
+
+    #[test]
+    fn test_kdbx_vault() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("team.kdbx");
+
+        let mut vault = Vault::new(path.clone());
+        vault.create("hunter2", KdfParams::POLICY_FLOOR).unwrap();
+        assert!(vault.is_kdbx());
+        assert!(kdbx::is_kdbx(&std::fs::read(&path).unwrap()));
+        let id = vault.add_entry(input("Example", "s3cret!")).unwrap();
+
+        vault.lock();
+        assert!(matches!(
+            vault.unlock("wrong"),
+            Err(VaultError::WrongPassword)
+        ));
+        vault.unlock("hunter2").unwrap();
+        assert!(vault.is_kdbx());
+        assert_eq!(vault.entry(id).unwrap().password.as_str(), "s3cret!");
+
+        vault
+            .change_master_password("hunter2", "new-master")
+            .unwrap();
+        vault.lock();
+        vault.unlock("new-master").unwrap();
+        assert_eq!(vault.entries().unwrap().len(), 1);
+
+        // The file changed behind our back, e.g. saved by KeePass.
+        let mut on_disk = std::fs::read(&path).unwrap();
+        on_disk.push(0);
+        std::fs::write(&path, on_disk).unwrap();
+        assert!(matches!(vault.delete_entry(id), Err(VaultError::Conflict)));
+    }
 }